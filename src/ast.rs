@@ -1,59 +1,207 @@
+use std::collections::BTreeSet;
 use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::{error::RuntimeError, interpreter::Object, stmt::Stmt, token::Token};
 
+/// The stretch of source a node was parsed from, as `(line, column)` pairs.
+/// Lines count from 1 and columns from 0, and `end` is just past the last
+/// character, as editors expect for ranges.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+impl Span {
+    /// Returns the span of a single token.
+    pub fn of(token: &Token) -> Self {
+        // a token's line is the one it ends on, which matters for strings
+        let lines = token.lexeme.matches('\n').count();
+        Self {
+            start: (token.line - lines, token.column),
+            end: (token.line, token.end_column),
+        }
+    }
+
+    /// Returns the span from the start of this one to the end of `other`.
+    pub fn to(self, other: Span) -> Self {
+        Self {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
+/// Identifies a node that refers to a variable, so the interpreter can find
+/// the scope the resolver found for it. Every parse takes fresh ids, so nodes
+/// from different sources run by one interpreter, such as successive REPL
+/// lines, never share one even when their tokens are identical.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ExprId(usize);
+
+impl ExprId {
+    pub fn fresh() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        ExprId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Expr {
+    Array {
+        bracket: Token,
+        elements: Vec<Expr>,
+        span: Span,
+    },
     Assign {
+        id: ExprId,
         name: Token,
         value: Box<Expr>,
+        span: Span,
     },
     Binary {
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
+        span: Span,
+    },
+    // `{ statements; value }`, run in a scope of its own
+    Block {
+        brace: Token,
+        statements: Vec<Stmt>,
+        value: Box<Expr>,
+        span: Span,
     },
     Call {
         callee: Box<Expr>,
         paren: Token,
-        arguments: Vec<Box<Expr>>,
+        arguments: Vec<Expr>,
+        span: Span,
+    },
+    Class {
+        keyword: Token,
+        fields: Vec<(Token, Expr)>,
+        methods: Vec<Stmt>,
+        getters: Vec<Stmt>,
+        setters: Vec<Stmt>,
+        span: Span,
+    },
+    Comma {
+        expressions: Vec<Expr>,
+        span: Span,
     },
     Get {
         object: Box<Expr>,
         name: Token,
+        span: Span,
     },
     Grouping {
         expression: Box<Expr>,
+        span: Span,
+    },
+    // `object?.name`, which is nil instead of an error when the object is nil
+    OptionalGet {
+        object: Box<Expr>,
+        name: Token,
+        span: Span,
+    },
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        span: Span,
     },
     Literal {
         value: Object,
+        // a number as written, e.g. `1_000`, so printing can keep its form
+        lexeme: Option<String>,
+        span: Span,
     },
     Logical {
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
+        span: Span,
+    },
+    Map {
+        brace: Token,
+        entries: Vec<(Expr, Expr)>,
+        span: Span,
     },
     Set {
         object: Box<Expr>,
         name: Token,
         value: Box<Expr>,
+        span: Span,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+        span: Span,
+    },
+    Super {
+        id: ExprId,
+        keyword: Token,
+        method: Token,
+        span: Span,
     },
     This {
+        id: ExprId,
         keyword: Token,
+        span: Span,
     },
     Unary {
         operator: Token,
         right: Box<Expr>,
+        span: Span,
     },
     Variable {
+        id: ExprId,
         name: Token,
+        span: Span,
     },
 }
 
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Array { span, .. }
+            | Expr::Assign { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Block { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Class { span, .. }
+            | Expr::Comma { span, .. }
+            | Expr::Get { span, .. }
+            | Expr::Grouping { span, .. }
+            | Expr::OptionalGet { span, .. }
+            | Expr::Index { span, .. }
+            | Expr::Literal { span, .. }
+            | Expr::Logical { span, .. }
+            | Expr::Map { span, .. }
+            | Expr::Set { span, .. }
+            | Expr::SetIndex { span, .. }
+            | Expr::Super { span, .. }
+            | Expr::This { span, .. }
+            | Expr::Unary { span, .. }
+            | Expr::Variable { span, .. } => *span,
+        }
+    }
+}
+
 impl Hash for Expr {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
-            Expr::Assign { name, value } => {
+            Expr::Array {
+                bracket, elements, ..
+            } => {
+                bracket.hash(state);
+                elements.hash(state);
+            }
+            Expr::Assign { name, value, .. } => {
                 name.hash(state);
                 value.hash(state);
             }
@@ -61,54 +209,123 @@ impl Hash for Expr {
                 left,
                 operator,
                 right,
+                ..
             } => {
                 left.hash(state);
                 operator.hash(state);
                 right.hash(state);
             }
+            Expr::Block {
+                brace,
+                statements,
+                value,
+                ..
+            } => {
+                brace.hash(state);
+                statements.hash(state);
+                value.hash(state);
+            }
             Expr::Call {
                 callee,
                 paren,
                 arguments,
+                ..
             } => {
                 callee.hash(state);
                 paren.hash(state);
                 arguments.hash(state);
             }
-            Expr::Get { object, name } => {
+            Expr::Class {
+                keyword,
+                fields,
+                methods,
+                getters,
+                setters,
+                ..
+            } => {
+                keyword.hash(state);
+                fields.hash(state);
+                methods.hash(state);
+                getters.hash(state);
+                setters.hash(state);
+            }
+            Expr::Comma { expressions, .. } => {
+                expressions.hash(state);
+            }
+            Expr::Get { object, name, .. } => {
                 object.hash(state);
                 name.hash(state);
             }
-            Expr::Grouping { expression } => {
+            Expr::Grouping { expression, .. } => {
                 expression.hash(state);
             }
-            Expr::Literal { value } => {
+            Expr::OptionalGet { object, name, .. } => {
+                object.hash(state);
+                name.hash(state);
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+                ..
+            } => {
+                object.hash(state);
+                bracket.hash(state);
+                index.hash(state);
+            }
+            Expr::Literal { value, .. } => {
                 value.hash(state);
             }
             Expr::Logical {
                 left,
                 operator,
                 right,
+                ..
             } => {
                 left.hash(state);
                 operator.hash(state);
                 right.hash(state);
             }
+            Expr::Map { brace, entries, .. } => {
+                brace.hash(state);
+                entries.hash(state);
+            }
             Expr::Set {
                 object,
                 name,
                 value,
+                ..
             } => {
                 object.hash(state);
                 name.hash(state);
                 value.hash(state);
             }
-            Expr::This { keyword } => keyword.hash(state),
-            Expr::Unary { operator, right } => {
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+                ..
+            } => {
+                object.hash(state);
+                bracket.hash(state);
+                index.hash(state);
+                value.hash(state);
+            }
+            Expr::Super {
+                keyword, method, ..
+            } => {
+                keyword.hash(state);
+                method.hash(state);
+            }
+            Expr::This { keyword, .. } => keyword.hash(state),
+            Expr::Unary {
+                operator, right, ..
+            } => {
                 operator.hash(state);
                 right.hash(state);
             }
-            Expr::Variable { name } => name.hash(state),
+            Expr::Variable { name, .. } => name.hash(state),
         }
     }
 }
@@ -155,23 +372,39 @@ impl Visitor<String, String> for AstPrinter {
     fn visit_expr(&mut self, e: &Expr) -> Result<String, RuntimeError> {
         let mut ast = String::new();
         match e {
-            Expr::Assign { name, value } => {
+            Expr::Array { elements, .. } => {
+                let elements = elements
+                    .iter()
+                    .map(|e| self.visit_expr(e))
+                    .collect::<Result<Vec<String>, RuntimeError>>()?;
+                self.parenthesize(&mut ast, "array", elements);
+            }
+            Expr::Assign { name, value, .. } => {
                 let expr = self.visit_expr(value)?;
-                self.parenthesize(&mut ast, &"assign", vec![name.lexeme.clone(), expr]);
+                self.parenthesize(&mut ast, "assign", vec![name.lexeme.clone(), expr]);
             }
             Expr::Binary {
                 left,
                 operator,
                 right,
+                ..
             } => {
                 let left_expr = self.visit_expr(left)?;
                 let right_expr = self.visit_expr(right)?;
                 self.parenthesize(&mut ast, &operator.lexeme, vec![left_expr, right_expr]);
             }
+            Expr::Block {
+                statements, value, ..
+            } => {
+                let mut parts = vec![];
+                for statement in statements {
+                    parts.push(self.visit_stmt(statement)?);
+                }
+                parts.push(self.visit_expr(value)?);
+                self.parenthesize(&mut ast, "block", parts);
+            }
             Expr::Call {
-                callee,
-                paren: _,
-                arguments,
+                callee, arguments, ..
             } => {
                 let callee = &self.visit_expr(callee)?;
                 let arguments: Vec<String> = arguments
@@ -181,28 +414,67 @@ impl Visitor<String, String> for AstPrinter {
 
                 self.parenthesize(&mut ast, callee, arguments)
             }
-            Expr::Get { object, name } => {
+            Expr::Class {
+                fields,
+                methods,
+                getters,
+                setters,
+                ..
+            } => {
+                let mut members = vec![];
+                for (name, value) in fields {
+                    members.push(format!("{} = {}", name.lexeme, self.visit_expr(value)?));
+                }
+                for method in methods {
+                    members.push(self.visit_stmt(method)?);
+                }
+                for getter in getters {
+                    members.push("get ".to_owned() + &self.visit_stmt(getter)?);
+                }
+                for setter in setters {
+                    members.push("set ".to_owned() + &self.visit_stmt(setter)?);
+                }
+                self.parenthesize(&mut ast, "class", members);
+            }
+            Expr::Comma { expressions, .. } => {
+                let mut values = vec![];
+                for expression in expressions {
+                    values.push(self.visit_expr(expression)?);
+                }
+                self.parenthesize(&mut ast, "comma", values);
+            }
+            Expr::Get { object, name, .. } => {
                 let value = self.visit_expr(object)?;
-                self.parenthesize(&mut ast, &"get", vec![name.lexeme.clone(), value]);
+                self.parenthesize(&mut ast, "get", vec![name.lexeme.clone(), value]);
             }
-            Expr::Grouping { expression } => {
+            Expr::Grouping { expression, .. } => {
                 let expr = self.visit_expr(expression)?;
-                self.parenthesize(&mut ast, &"group", vec![expr]);
+                self.parenthesize(&mut ast, "group", vec![expr]);
+            }
+            Expr::OptionalGet { object, name, .. } => {
+                let value = self.visit_expr(object)?;
+                self.parenthesize(&mut ast, "get?", vec![name.lexeme.clone(), value]);
+            }
+            Expr::Index { object, index, .. } => {
+                let object = self.visit_expr(object)?;
+                let index = self.visit_expr(index)?;
+                self.parenthesize(&mut ast, "index", vec![object, index]);
             }
-            Expr::Literal { value } => match value {
+            Expr::Literal { value, lexeme, .. } => match value {
                 Object::String(val) => {
                     ast.push_str(val);
                 }
-                Object::Number(val) => {
-                    ast.push_str(&val.to_string());
-                }
+                Object::Number(val) => match lexeme {
+                    Some(lexeme) => ast.push_str(lexeme),
+                    None => ast.push_str(&val.to_string()),
+                },
                 Object::Bool(b) => {
                     ast.push_str(&b.to_string());
                 }
-                Object::Class(_c) => ast.push_str(&"Class"),
-                Object::Instance(_i) => ast.push_str(&"Instance"),
+                Object::Class(_c) => ast.push_str("Class"),
+                Object::Instance(_i) => ast.push_str("Instance"),
                 Object::Nil => {
-                    ast.push_str(&"nil");
+                    ast.push_str("nil");
                 }
                 Object::Function(func) => {
                     let declaration = &func.declaration;
@@ -214,31 +486,61 @@ impl Visitor<String, String> for AstPrinter {
                     ast.push_str(&("<fun>".to_owned() + &name));
                 }
                 Object::NativeFunction(..) => {
-                    ast.push_str(&"<native fun>");
+                    ast.push_str("<native fun>");
+                }
+                Object::Array(..)
+                | Object::Map(..)
+                | Object::Generator(..)
+                | Object::Partial(..)
+                | Object::Enum(..)
+                | Object::Variant(..) => {
+                    ast.push_str(&value.to_string());
                 }
             },
             Expr::Logical {
                 left,
                 operator,
                 right,
+                ..
             } => {
                 let left_expr = self.visit_expr(left)?;
                 let right_expr = self.visit_expr(right)?;
                 self.parenthesize(&mut ast, &operator.lexeme, vec![left_expr, right_expr]);
             }
-            Expr::Set {
-                object: _,
-                name: _,
-                value: _,
-            } => {
+            Expr::Map { entries, .. } => {
+                let mut pairs = vec![];
+                for (key, value) in entries {
+                    let key = self.visit_expr(key)?;
+                    let value = self.visit_expr(value)?;
+                    pairs.push(format!("{key}: {value}"));
+                }
+                self.parenthesize(&mut ast, "map", pairs);
+            }
+            Expr::Set { .. } => {
                 todo!()
             }
-            Expr::This { keyword: _ } => todo!(),
-            Expr::Unary { operator, right } => {
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                let object = self.visit_expr(object)?;
+                let index = self.visit_expr(index)?;
+                let value = self.visit_expr(value)?;
+                self.parenthesize(&mut ast, "set-index", vec![object, index, value]);
+            }
+            Expr::Super { method, .. } => {
+                self.parenthesize(&mut ast, "super", vec![method.lexeme.clone()]);
+            }
+            Expr::This { .. } => todo!(),
+            Expr::Unary {
+                operator, right, ..
+            } => {
                 let expr = self.visit_expr(right)?;
                 self.parenthesize(&mut ast, &operator.lexeme, vec![expr]);
             }
-            Expr::Variable { name } => ast.push_str(&name.lexeme),
+            Expr::Variable { name, .. } => ast.push_str(&name.lexeme),
         };
         Ok(ast)
     }
@@ -246,23 +548,22 @@ impl Visitor<String, String> for AstPrinter {
     fn visit_stmt(&mut self, s: &Stmt) -> Result<String, RuntimeError> {
         let mut ast = String::new();
         match s {
-            Stmt::Block { statements } => {
+            Stmt::Block { statements, .. } => {
                 ast.push_str("{\n");
                 for s in statements {
                     let stmt = self.visit_stmt(s)?;
                     ast.push_str(&("  ".to_owned() + &stmt + ";\n"));
                 }
-                ast.push_str("}");
+                ast.push('}');
             }
-            Stmt::Class {
-                name: _,
-                methods: _,
-            } => todo!(),
-            Stmt::Expr(e) => {
+            Stmt::Class { .. } => todo!(),
+            Stmt::Expr(e, _) => {
                 let expr = self.visit_expr(e)?;
                 ast.push_str(&expr)
             }
-            Stmt::Function { name, params, body } => {
+            Stmt::Function {
+                name, params, body, ..
+            } => {
                 let mut function = String::new();
                 function.push_str(&("fun ".to_owned() + &name.lexeme + "("));
 
@@ -274,7 +575,7 @@ impl Visitor<String, String> for AstPrinter {
 
                 function.push_str(&params);
 
-                function.push_str(")");
+                function.push(')');
 
                 function.push_str(" {\n");
 
@@ -289,42 +590,102 @@ impl Visitor<String, String> for AstPrinter {
 
                 function.push_str(&body);
 
-                function.push_str("}");
+                function.push('}');
 
                 ast.push_str(&function);
             }
+            Stmt::Break { keyword, label, .. } | Stmt::Continue { keyword, label, .. } => {
+                ast.push_str(&keyword.lexeme);
+                if let Some(l) = label {
+                    ast.push_str(&(" ".to_owned() + &l.lexeme));
+                }
+            }
+            Stmt::For {
+                label,
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                let i = match initializer {
+                    Some(i) => self.visit_stmt(i)?,
+                    None => String::new(),
+                };
+                let c = match condition {
+                    Some(c) => self.visit_expr(c)?,
+                    None => String::new(),
+                };
+                let inc = match increment {
+                    Some(inc) => self.visit_expr(inc)?,
+                    None => String::new(),
+                };
+                let b = self.visit_stmt(body)?;
+
+                if let Some(l) = label {
+                    ast.push_str(&(l.lexeme.clone() + ": "));
+                }
+                ast.push_str(&format!("for ({i}; {c}; {inc}) {{ {b} }}"));
+            }
             Stmt::If {
                 condition,
                 then_branch,
                 else_branch,
+                ..
             } => {
                 let c = self.visit_expr(condition)?;
-                let then = self.visit_stmt(&then_branch)?;
+                let then = self.visit_stmt(then_branch)?;
 
                 ast.push_str(format!("if ({c}) {{ {then} }}").as_str());
 
                 if let Some(b) = else_branch {
-                    let else_b = self.visit_stmt(&b)?;
+                    let else_b = self.visit_stmt(b)?;
                     ast.push_str(&(" else { ".to_owned() + &else_b + " }"));
                 }
             }
-            Stmt::Print(e) => {
+            Stmt::Enum { name, variants, .. } => {
+                let variants: Vec<&str> = variants.iter().map(|v| v.lexeme.as_str()).collect();
+                ast.push_str(&format!(
+                    "enum {} {{ {} }}",
+                    name.lexeme,
+                    variants.join(", ")
+                ));
+            }
+            Stmt::Import { path, name, .. } => {
+                ast.push_str(&format!("import {} as {}", path.lexeme, name.lexeme));
+            }
+            Stmt::Private { declaration, .. } => {
+                ast.push_str(&("private ".to_owned() + &self.visit_stmt(declaration)?));
+            }
+            Stmt::Print(e, _) => {
                 let expr = &self.visit_expr(e)?;
                 ast.push_str(&format!("print {expr};"));
             }
-            Stmt::Return { keyword, value } => {
+            Stmt::Defer { keyword, value, .. }
+            | Stmt::Return { keyword, value, .. }
+            | Stmt::Yield { keyword, value, .. } => {
                 ast.push_str(&(keyword.lexeme.clone() + " " + &self.visit_expr(value)?));
             }
-            Stmt::Var { name, initializer } => {
+            Stmt::Var {
+                name, initializer, ..
+            } => {
                 ast.push_str(&name.lexeme.clone());
                 if let Some(i) = initializer {
                     ast.push_str(&(" = ".to_owned() + &self.visit_expr(i)?));
                 }
             }
-            Stmt::While { condition, body } => {
+            Stmt::While {
+                label,
+                condition,
+                body,
+                ..
+            } => {
                 let c = self.visit_expr(condition)?;
-                let b = self.visit_stmt(&body)?;
+                let b = self.visit_stmt(body)?;
 
+                if let Some(l) = label {
+                    ast.push_str(&(l.lexeme.clone() + ": "));
+                }
                 ast.push_str(&("while (".to_owned() + &c + ") { " + &b + " }"));
             }
         }
@@ -332,6 +693,361 @@ impl Visitor<String, String> for AstPrinter {
     }
 }
 
+/// Collects the names a program refers to: variables read or assigned and
+/// properties accessed. Names that are only declared are left out.
+#[derive(Default)]
+pub struct IdentifierCollector {
+    pub identifiers: BTreeSet<String>,
+}
+
+impl IdentifierCollector {
+    pub fn collect(stmts: &[Stmt]) -> BTreeSet<String> {
+        let mut collector = IdentifierCollector::default();
+        for stmt in stmts {
+            // Collecting never fails.
+            let _ = collector.visit_stmt(stmt);
+        }
+        collector.identifiers
+    }
+
+    fn visit_exprs<'a>(&mut self, exprs: impl IntoIterator<Item = &'a Expr>) {
+        for expr in exprs {
+            let _ = self.visit_expr(expr);
+        }
+    }
+
+    fn visit_stmts<'a>(&mut self, stmts: impl IntoIterator<Item = &'a Stmt>) {
+        for stmt in stmts {
+            let _ = self.visit_stmt(stmt);
+        }
+    }
+}
+
+impl Visitor<(), ()> for IdentifierCollector {
+    fn visit_expr(&mut self, e: &Expr) -> Result<(), RuntimeError> {
+        match e {
+            Expr::Array { elements, .. } => self.visit_exprs(elements),
+            Expr::Assign { name, value, .. } => {
+                self.identifiers.insert(name.lexeme.clone());
+                self.visit_expr(value)?;
+            }
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.visit_expr(left)?;
+                self.visit_expr(right)?;
+            }
+            Expr::Block {
+                statements, value, ..
+            } => {
+                self.visit_stmts(statements);
+                self.visit_expr(value)?;
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.visit_expr(callee)?;
+                self.visit_exprs(arguments);
+            }
+            Expr::Class {
+                fields,
+                methods,
+                getters,
+                setters,
+                ..
+            } => {
+                self.visit_exprs(fields.iter().map(|(_, value)| value));
+                self.visit_stmts(methods.iter().chain(getters).chain(setters));
+            }
+            Expr::Comma { expressions, .. } => self.visit_exprs(expressions),
+            Expr::Get { object, name, .. } | Expr::OptionalGet { object, name, .. } => {
+                self.identifiers.insert(name.lexeme.clone());
+                self.visit_expr(object)?;
+            }
+            Expr::Grouping { expression, .. } => self.visit_expr(expression)?,
+            Expr::Index { object, index, .. } => {
+                self.visit_expr(object)?;
+                self.visit_expr(index)?;
+            }
+            Expr::Literal { .. } | Expr::This { .. } => {}
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.visit_expr(key)?;
+                    self.visit_expr(value)?;
+                }
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+                ..
+            } => {
+                self.identifiers.insert(name.lexeme.clone());
+                self.visit_expr(object)?;
+                self.visit_expr(value)?;
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.visit_expr(object)?;
+                self.visit_expr(index)?;
+                self.visit_expr(value)?;
+            }
+            Expr::Super { method, .. } => {
+                self.identifiers.insert(method.lexeme.clone());
+            }
+            Expr::Unary { right, .. } => self.visit_expr(right)?,
+            Expr::Variable { name, .. } => {
+                self.identifiers.insert(name.lexeme.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_stmt(&mut self, s: &Stmt) -> Result<(), RuntimeError> {
+        match s {
+            Stmt::Block { statements, .. } => self.visit_stmts(statements),
+            Stmt::Break { .. }
+            | Stmt::Continue { .. }
+            | Stmt::Enum { .. }
+            | Stmt::Import { .. } => {}
+            Stmt::Class {
+                superclass,
+                fields,
+                methods,
+                getters,
+                setters,
+                ..
+            } => {
+                self.visit_exprs(superclass);
+                self.visit_exprs(fields.iter().map(|(_, value)| value));
+                self.visit_stmts(methods.iter().chain(getters).chain(setters));
+            }
+            Stmt::Expr(e, _) | Stmt::Print(e, _) => self.visit_expr(e)?,
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.visit_stmts(initializer.as_deref());
+                self.visit_exprs(condition.iter().chain(increment));
+                self.visit_stmt(body)?;
+            }
+            Stmt::Function { body, .. } => self.visit_stmts(body),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.visit_expr(condition)?;
+                self.visit_stmt(then_branch)?;
+                self.visit_stmts(else_branch.as_deref());
+            }
+            Stmt::Private { declaration, .. } => self.visit_stmt(declaration)?,
+            Stmt::Defer { value, .. } | Stmt::Return { value, .. } | Stmt::Yield { value, .. } => {
+                self.visit_expr(value)?
+            }
+            Stmt::Var { initializer, .. } => self.visit_exprs(initializer),
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.visit_expr(condition)?;
+                self.visit_stmt(body)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How many of each kind of node a program has, for judging how complex it
+/// is. Methods, getters and setters count as functions, and `and`, `or` and
+/// `??` count as branches alongside `if`.
+#[derive(Debug, Default, PartialEq)]
+pub struct ComplexityReport {
+    pub functions: usize,
+    pub classes: usize,
+    pub loops: usize,
+    pub branches: usize,
+    pub calls: usize,
+    /// One more than the number of decision points, that is branches and
+    /// loops, across the whole program.
+    pub cyclomatic: usize,
+}
+
+/// Counts the nodes of `stmts` by kind.
+pub fn complexity(stmts: &[Stmt]) -> ComplexityReport {
+    let mut counter = ComplexityCounter::default();
+    // Counting never fails.
+    counter.visit_stmts(stmts);
+    let mut report = counter.report;
+    report.cyclomatic = 1 + report.branches + report.loops;
+    report
+}
+
+#[derive(Default)]
+struct ComplexityCounter {
+    report: ComplexityReport,
+}
+
+impl ComplexityCounter {
+    fn visit_exprs<'a>(&mut self, exprs: impl IntoIterator<Item = &'a Expr>) {
+        for expr in exprs {
+            let _ = self.visit_expr(expr);
+        }
+    }
+
+    fn visit_stmts<'a>(&mut self, stmts: impl IntoIterator<Item = &'a Stmt>) {
+        for stmt in stmts {
+            let _ = self.visit_stmt(stmt);
+        }
+    }
+}
+
+impl Visitor<(), ()> for ComplexityCounter {
+    fn visit_expr(&mut self, e: &Expr) -> Result<(), RuntimeError> {
+        match e {
+            Expr::Array { elements, .. } => self.visit_exprs(elements),
+            Expr::Assign { value, .. } => self.visit_expr(value)?,
+            Expr::Binary { left, right, .. } => {
+                self.visit_expr(left)?;
+                self.visit_expr(right)?;
+            }
+            Expr::Block {
+                statements, value, ..
+            } => {
+                self.visit_stmts(statements);
+                self.visit_expr(value)?;
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.report.calls += 1;
+                self.visit_expr(callee)?;
+                self.visit_exprs(arguments);
+            }
+            Expr::Class {
+                fields,
+                methods,
+                getters,
+                setters,
+                ..
+            } => {
+                self.report.classes += 1;
+                self.visit_exprs(fields.iter().map(|(_, value)| value));
+                self.visit_stmts(methods.iter().chain(getters).chain(setters));
+            }
+            Expr::Comma { expressions, .. } => self.visit_exprs(expressions),
+            Expr::Get { object, .. } | Expr::OptionalGet { object, .. } => {
+                self.visit_expr(object)?
+            }
+            Expr::Grouping { expression, .. } => self.visit_expr(expression)?,
+            Expr::Index { object, index, .. } => {
+                self.visit_expr(object)?;
+                self.visit_expr(index)?;
+            }
+            Expr::Literal { .. }
+            | Expr::Super { .. }
+            | Expr::This { .. }
+            | Expr::Variable { .. } => {}
+            Expr::Logical { left, right, .. } => {
+                self.report.branches += 1;
+                self.visit_expr(left)?;
+                self.visit_expr(right)?;
+            }
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.visit_expr(key)?;
+                    self.visit_expr(value)?;
+                }
+            }
+            Expr::Set { object, value, .. } => {
+                self.visit_expr(object)?;
+                self.visit_expr(value)?;
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.visit_expr(object)?;
+                self.visit_expr(index)?;
+                self.visit_expr(value)?;
+            }
+            Expr::Unary { right, .. } => self.visit_expr(right)?,
+        }
+        Ok(())
+    }
+
+    fn visit_stmt(&mut self, s: &Stmt) -> Result<(), RuntimeError> {
+        match s {
+            Stmt::Block { statements, .. } => self.visit_stmts(statements),
+            Stmt::Break { .. }
+            | Stmt::Continue { .. }
+            | Stmt::Enum { .. }
+            | Stmt::Import { .. } => {}
+            Stmt::Class {
+                superclass,
+                fields,
+                methods,
+                getters,
+                setters,
+                ..
+            } => {
+                self.report.classes += 1;
+                self.visit_exprs(superclass);
+                self.visit_exprs(fields.iter().map(|(_, value)| value));
+                self.visit_stmts(methods.iter().chain(getters).chain(setters));
+            }
+            Stmt::Expr(e, _) | Stmt::Print(e, _) => self.visit_expr(e)?,
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.report.loops += 1;
+                self.visit_stmts(initializer.as_deref());
+                self.visit_exprs(condition.iter().chain(increment));
+                self.visit_stmt(body)?;
+            }
+            Stmt::Function { body, .. } => {
+                self.report.functions += 1;
+                self.visit_stmts(body);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.report.branches += 1;
+                self.visit_expr(condition)?;
+                self.visit_stmt(then_branch)?;
+                self.visit_stmts(else_branch.as_deref());
+            }
+            Stmt::Private { declaration, .. } => self.visit_stmt(declaration)?,
+            Stmt::Defer { value, .. } | Stmt::Return { value, .. } | Stmt::Yield { value, .. } => {
+                self.visit_expr(value)?
+            }
+            Stmt::Var { initializer, .. } => self.visit_exprs(initializer),
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.report.loops += 1;
+                self.visit_expr(condition)?;
+                self.visit_stmt(body)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -346,10 +1062,16 @@ mod tests {
                 lexeme: String::from("-"),
                 literal: None,
                 line: 0,
+                offset: 0,
+                column: 0,
+                end_column: 0,
             },
             right: Box::new(Expr::Literal {
                 value: Object::Number(0.0),
+                lexeme: None,
+                span: Span::default(),
             }),
+            span: Span::default(),
         };
         assert_eq!(
             ast_printer.visit_expr(&unary_expression).expect(""),
@@ -360,17 +1082,26 @@ mod tests {
     #[test]
     fn unary_expression_statement() {
         let mut ast_printer = AstPrinter;
-        let unary_stmt_expr = Stmt::Expr(Expr::Unary {
-            operator: Token {
-                token_type: TokenType::Minus,
-                lexeme: String::from("-"),
-                literal: None,
-                line: 0,
+        let unary_stmt_expr = Stmt::Expr(
+            Expr::Unary {
+                operator: Token {
+                    token_type: TokenType::Minus,
+                    lexeme: String::from("-"),
+                    literal: None,
+                    line: 0,
+                    offset: 0,
+                    column: 0,
+                    end_column: 0,
+                },
+                right: Box::new(Expr::Literal {
+                    value: Object::Number(0.0),
+                    lexeme: None,
+                    span: Span::default(),
+                }),
+                span: Span::default(),
             },
-            right: Box::new(Expr::Literal {
-                value: Object::Number(0.0),
-            }),
-        });
+            Span::default(),
+        );
         ast_printer.print(vec![unary_stmt_expr])
     }
 
@@ -380,16 +1111,24 @@ mod tests {
         let binary_expr = Expr::Binary {
             left: Box::new(Expr::Literal {
                 value: Object::Number(1.0),
+                lexeme: None,
+                span: Span::default(),
             }),
             operator: Token {
                 token_type: TokenType::Plus,
                 lexeme: String::from("+"),
                 literal: None,
                 line: 0,
+                offset: 0,
+                column: 0,
+                end_column: 0,
             },
             right: Box::new(Expr::Literal {
                 value: Object::Number(1.0),
+                lexeme: None,
+                span: Span::default(),
             }),
+            span: Span::default(),
         };
         assert_eq!(ast_printer.visit_expr(&binary_expr).expect(""), "(+ 1 1)")
     }
@@ -400,7 +1139,10 @@ mod tests {
         let grouping_expr = Expr::Grouping {
             expression: Box::new(Expr::Literal {
                 value: Object::String("hello".into()),
+                lexeme: None,
+                span: Span::default(),
             }),
+            span: Span::default(),
         };
         assert_eq!(
             ast_printer.visit_expr(&grouping_expr).expect(""),
@@ -412,12 +1154,17 @@ mod tests {
     fn variable_expression() {
         let mut ast_printer = AstPrinter;
         let variable_expr = Expr::Variable {
+            id: ExprId::fresh(),
             name: Token {
                 token_type: TokenType::Identifier,
                 lexeme: String::from("x"),
                 literal: None,
                 line: 0,
+                offset: 0,
+                column: 0,
+                end_column: 0,
             },
+            span: Span::default(),
         };
         assert_eq!(ast_printer.visit_expr(&variable_expr).expect(""), "x")
     }
@@ -428,28 +1175,42 @@ mod tests {
         let binary_expr = Expr::Binary {
             left: Box::new(Expr::Literal {
                 value: Object::Number(0.0),
+                lexeme: None,
+                span: Span::default(),
             }),
             operator: Token {
                 token_type: TokenType::Plus,
                 lexeme: String::from("+"),
                 literal: None,
                 line: 0,
+                offset: 0,
+                column: 0,
+                end_column: 0,
             },
             right: Box::new(Expr::Literal {
                 value: Object::Number(1.0),
+                lexeme: None,
+                span: Span::default(),
             }),
+            span: Span::default(),
         };
         let binary_expr_with_binary_expr = Expr::Binary {
             left: Box::new(Expr::Literal {
                 value: Object::Number(0.0),
+                lexeme: None,
+                span: Span::default(),
             }),
             operator: Token {
                 token_type: TokenType::Plus,
                 lexeme: String::from("-"),
                 literal: None,
                 line: 0,
+                offset: 0,
+                column: 0,
+                end_column: 0,
             },
             right: Box::new(binary_expr),
+            span: Span::default(),
         };
 
         assert_eq!(
@@ -466,16 +1227,24 @@ mod tests {
         let logical_expr = Expr::Logical {
             left: Box::new(Expr::Literal {
                 value: Object::Bool(true),
+                lexeme: None,
+                span: Span::default(),
             }),
             operator: Token {
                 token_type: TokenType::And,
                 lexeme: String::from("and"),
                 literal: None,
                 line: 0,
+                offset: 0,
+                column: 0,
+                end_column: 0,
             },
             right: Box::new(Expr::Literal {
                 value: Object::Bool(true),
+                lexeme: None,
+                span: Span::default(),
             }),
+            span: Span::default(),
         };
 
         assert_eq!(
@@ -488,13 +1257,22 @@ mod tests {
     fn assign_expression() {
         let mut ast_printer = AstPrinter;
         let assign_expr = Expr::Assign {
+            id: ExprId::fresh(),
             name: Token {
                 token_type: TokenType::Identifier,
                 lexeme: String::from("x"),
                 literal: None,
                 line: 0,
+                offset: 0,
+                column: 0,
+                end_column: 0,
             },
-            value: Box::new(Expr::Literal { value: Object::Nil }),
+            value: Box::new(Expr::Literal {
+                value: Object::Nil,
+                lexeme: None,
+                span: Span::default(),
+            }),
+            span: Span::default(),
         };
 
         assert_eq!(
@@ -508,49 +1286,74 @@ mod tests {
         let mut ast_printer = AstPrinter;
         let call_expr = Expr::Call {
             callee: Box::new(Expr::Variable {
+                id: ExprId::fresh(),
                 name: Token {
                     token_type: TokenType::Identifier,
                     lexeme: String::from("hello"),
                     literal: None,
                     line: 0,
+                    offset: 0,
+                    column: 0,
+                    end_column: 0,
                 },
+                span: Span::default(),
             }),
             paren: Token {
                 token_type: TokenType::RightParen,
                 lexeme: String::from(")"),
                 literal: None,
                 line: 0,
+                offset: 0,
+                column: 0,
+                end_column: 0,
             },
             arguments: vec![
-                Box::new(Expr::Binary {
+                Expr::Binary {
                     left: Box::new(Expr::Literal {
                         value: Object::Number(0.0),
+                        lexeme: None,
+                        span: Span::default(),
                     }),
                     operator: Token {
                         token_type: TokenType::Plus,
                         lexeme: String::from("+"),
                         literal: None,
                         line: 0,
+                        offset: 0,
+                        column: 0,
+                        end_column: 0,
                     },
                     right: Box::new(Expr::Literal {
                         value: Object::Number(1.0),
+                        lexeme: None,
+                        span: Span::default(),
                     }),
-                }),
-                Box::new(Expr::Binary {
+                    span: Span::default(),
+                },
+                Expr::Binary {
                     left: Box::new(Expr::Literal {
                         value: Object::Number(1.0),
+                        lexeme: None,
+                        span: Span::default(),
                     }),
                     operator: Token {
                         token_type: TokenType::Plus,
                         lexeme: String::from("-"),
                         literal: None,
                         line: 0,
+                        offset: 0,
+                        column: 0,
+                        end_column: 0,
                     },
                     right: Box::new(Expr::Literal {
                         value: Object::Number(1.0),
+                        lexeme: None,
+                        span: Span::default(),
                     }),
-                }),
+                    span: Span::default(),
+                },
             ],
+            span: Span::default(),
         };
         assert_eq!(
             ast_printer.visit_expr(&call_expr).expect(""),
@@ -568,26 +1371,97 @@ mod tests {
                     lexeme: String::from("-"),
                     literal: None,
                     line: 0,
+                    offset: 0,
+                    column: 0,
+                    end_column: 0,
                 },
                 right: Box::new(Expr::Literal {
                     value: Object::Number(123.0),
+                    lexeme: None,
+                    span: Span::default(),
                 }),
+                span: Span::default(),
             }),
             operator: Token {
                 token_type: TokenType::Star,
                 lexeme: String::from("*"),
                 literal: None,
                 line: 0,
+                offset: 0,
+                column: 0,
+                end_column: 0,
             },
             right: Box::new(Expr::Grouping {
                 expression: Box::new(Expr::Literal {
                     value: Object::Number(45.67),
+                    lexeme: None,
+                    span: Span::default(),
                 }),
+                span: Span::default(),
             }),
+            span: Span::default(),
         };
         assert_eq!(
             ast_printer.visit_expr(&expression).expect(""),
             "(* (- 123) (group 45.67))"
         )
     }
+
+    #[test]
+    fn prints_numbers_as_written() {
+        let mut scanner = crate::scanner::Scanner::new(String::from("print 1_000 + 2.50;"));
+        let tokens = scanner.scan_tokens();
+        assert_eq!(tokens[1].literal, Some(Object::Number(1000.0)));
+        let stmts = crate::parser::Parser::new(tokens)
+            .parse()
+            .expect("parse error");
+        let mut ast_printer = AstPrinter;
+        assert_eq!(
+            ast_printer.visit_stmt(&stmts[0]).expect(""),
+            "print (+ 1_000 2.50);"
+        );
+    }
+
+    #[test]
+    fn collects_referenced_identifiers() {
+        let source = "var total = 0; \
+                      fun add(n) { total = total + n; return helper(n); } \
+                      class Point { sum() { return this.x + this.y; } } \
+                      var p = Point(); p.x = add(1); print count;";
+        let mut scanner = crate::scanner::Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = crate::parser::Parser::new(tokens)
+            .parse()
+            .expect("parse error");
+        let identifiers: Vec<String> = IdentifierCollector::collect(&stmts).into_iter().collect();
+        assert_eq!(
+            identifiers,
+            vec!["Point", "add", "count", "helper", "n", "p", "total", "x", "y"]
+        );
+    }
+
+    #[test]
+    fn reports_complexity() {
+        let source = "fun sum(xs) { var total = 0; \
+                        for (var i = 0; i < len(xs); i = i + 1) { total = total + xs[i]; } \
+                        return total; } \
+                      fun sign(n) { if (n < 0 or n == 0) return 0; return 1; } \
+                      print sign(sum([1, 2]));";
+        let mut scanner = crate::scanner::Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = crate::parser::Parser::new(tokens)
+            .parse()
+            .expect("parse error");
+        assert_eq!(
+            complexity(&stmts),
+            ComplexityReport {
+                functions: 2,
+                classes: 0,
+                loops: 1,
+                branches: 2,
+                calls: 3,
+                cyclomatic: 4,
+            }
+        );
+    }
 }
@@ -3,37 +3,147 @@ use std::collections::HashMap;
 use std::fmt;
 use std::rc::Rc;
 
+use crate::ast::Expr;
+use crate::environment::Environment;
 use crate::error::RuntimeError;
-use crate::function::Function;
+use crate::function::{Function, NativeMethod};
 use crate::instance::Instance;
 use crate::interpreter::{Callable, Interpreter, Object};
+use crate::token::Token;
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct Class {
     pub name: String,
+    superclass: Option<Rc<Class>>,
     methods: HashMap<String, Function>,
+    getters: HashMap<String, Function>,
+    setters: HashMap<String, Function>,
+    // methods implemented by the host, with their arity
+    native_methods: HashMap<String, (usize, NativeMethod)>,
+    // field defaults, evaluated in the environment the class was declared in
+    fields: Vec<(Token, Expr)>,
+    closure: Option<Rc<RefCell<Environment>>>,
 }
 
 impl Class {
-    pub fn new(name: String, methods: HashMap<String, Function>) -> Self {
-        Class { name, methods }
+    pub fn new(
+        name: String,
+        superclass: Option<Rc<Class>>,
+        methods: HashMap<String, Function>,
+        getters: HashMap<String, Function>,
+        setters: HashMap<String, Function>,
+    ) -> Self {
+        Class {
+            name,
+            superclass,
+            methods,
+            getters,
+            setters,
+            native_methods: HashMap::new(),
+            fields: Vec::new(),
+            closure: None,
+        }
     }
 
+    /// Gives the class fields that every new instance starts with. Each
+    /// default is evaluated in `closure` when the instance is created.
+    pub fn with_fields(
+        self,
+        fields: Vec<(Token, Expr)>,
+        closure: Rc<RefCell<Environment>>,
+    ) -> Self {
+        Class {
+            fields,
+            closure: Some(closure),
+            ..self
+        }
+    }
+
+    // Superclass fields are set first, so a subclass can override a default.
+    fn define_fields(
+        &self,
+        interpreter: &mut Interpreter,
+        instance: &Instance,
+    ) -> Result<(), RuntimeError> {
+        if let Some(superclass) = &self.superclass {
+            superclass.define_fields(interpreter, instance)?;
+        }
+        if let Some(closure) = &self.closure {
+            for (name, value) in &self.fields {
+                let value = interpreter.evaluate_in(value, Rc::clone(closure))?;
+                instance.define(name.lexeme.clone(), value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates a class whose methods are implemented in Rust, so an embedder
+    /// can expose host objects such as files to scripts. Each method is
+    /// given with its arity.
+    pub fn with_native_methods(
+        name: String,
+        native_methods: HashMap<String, (usize, NativeMethod)>,
+    ) -> Self {
+        Class {
+            native_methods,
+            ..Class::new(name, None, HashMap::new(), HashMap::new(), HashMap::new())
+        }
+    }
+
+    // Members not defined by the class itself are looked up in its superclass.
     pub fn find_method(&self, name: String) -> Option<Function> {
-        return self.methods.get(&name).cloned();
+        match self.methods.get(&name) {
+            Some(method) => Some(method.clone()),
+            None => self.superclass.as_ref()?.find_method(name),
+        }
+    }
+
+    /// Returns the names of the methods the class itself defines, sorted.
+    pub fn method_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .methods
+            .keys()
+            .chain(self.native_methods.keys())
+            .map(String::as_str)
+            .collect();
+        names.sort();
+        names
+    }
+
+    pub fn find_native_method(&self, name: &str) -> Option<(usize, NativeMethod)> {
+        match self.native_methods.get(name) {
+            Some(method) => Some(*method),
+            None => self.superclass.as_ref()?.find_native_method(name),
+        }
+    }
+
+    pub fn find_getter(&self, name: &str) -> Option<Function> {
+        match self.getters.get(name) {
+            Some(getter) => Some(getter.clone()),
+            None => self.superclass.as_ref()?.find_getter(name),
+        }
+    }
+
+    pub fn find_setter(&self, name: &str) -> Option<Function> {
+        match self.setters.get(name) {
+            Some(setter) => Some(setter.clone()),
+            None => self.superclass.as_ref()?.find_setter(name),
+        }
     }
 }
 
 impl Callable for Class {
     fn call(
         &self,
-        _interpreter: &mut Interpreter,
+        interpreter: &mut Interpreter,
+        _paren: &Token,
         _arguments: Vec<Rc<RefCell<Object>>>,
     ) -> Result<Rc<RefCell<Object>>, RuntimeError>
     where
         Self: Sized,
     {
-        let instance = Instance::new(self.clone());
+        let instance = Instance::new(Rc::new(self.clone()));
+        self.define_fields(interpreter, &instance)?;
         Ok(Rc::new(RefCell::new(Object::Instance(instance))))
     }
 
@@ -42,6 +152,21 @@ impl Callable for Class {
     }
 }
 
+// The closure is left out, since it usually holds the class itself.
+impl fmt::Debug for Class {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Class")
+            .field("name", &self.name)
+            .field("superclass", &self.superclass)
+            .field("methods", &self.methods)
+            .field("getters", &self.getters)
+            .field("setters", &self.setters)
+            .field("native_methods", &self.native_methods)
+            .field("fields", &self.fields)
+            .finish()
+    }
+}
+
 impl fmt::Display for Class {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:}", self.name.clone())
@@ -0,0 +1,41 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::interpreter::Object;
+
+/// Guards recursive traversals of values that may refer back to themselves.
+/// Objects are identified by address: the contents of an array or map, or the
+/// fields of an instance.
+#[derive(Default)]
+pub struct CycleGuard {
+    active: Vec<*const ()>,
+    copies: HashMap<*const (), Rc<RefCell<Object>>>,
+}
+
+impl CycleGuard {
+    /// Marks `object` as being traversed. Returns false if it already is, in
+    /// which case the traversal has found a cycle and should stop.
+    pub fn enter(&mut self, object: *const ()) -> bool {
+        if self.active.contains(&object) {
+            return false;
+        }
+        self.active.push(object);
+        true
+    }
+
+    pub fn leave(&mut self, object: *const ()) {
+        self.active.retain(|&o| o != object);
+    }
+
+    /// Returns the copy already made of `object` by a deep copy.
+    pub fn copy_of(&self, object: *const ()) -> Option<Rc<RefCell<Object>>> {
+        self.copies.get(&object).cloned()
+    }
+
+    /// Records `copy` as the copy of `object`. Do this before copying the
+    /// object's contents, so that references back to it reuse the copy.
+    pub fn remember(&mut self, object: *const (), copy: Rc<RefCell<Object>>) {
+        self.copies.insert(object, copy);
+    }
+}
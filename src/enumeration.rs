@@ -0,0 +1,61 @@
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+use crate::error::RuntimeError;
+use crate::interpreter::Object;
+use crate::token::Token;
+
+/// What an `enum` declaration creates, holding one variant per name, read
+/// as `Color.Red`.
+#[derive(Debug)]
+pub struct Enum {
+    pub name: String,
+    variants: HashMap<String, Rc<RefCell<Object>>>,
+}
+
+impl Enum {
+    pub fn new(name: String, variant_names: &[Token]) -> Self {
+        let variants = variant_names
+            .iter()
+            .map(|v| {
+                let variant = Variant {
+                    enum_name: name.clone(),
+                    name: v.lexeme.clone(),
+                };
+                let value = Object::Variant(Rc::new(variant));
+                (v.lexeme.clone(), Rc::new(RefCell::new(value)))
+            })
+            .collect();
+        Enum { name, variants }
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        match self.variants.get(&name.lexeme) {
+            Some(variant) => Ok(Rc::clone(variant)),
+            None => Err(RuntimeError::new(
+                name.clone(),
+                &format!("Enum '{}' has no variant '{}'.", self.name, name.lexeme),
+                None,
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Enum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<enum {}>", self.name)
+    }
+}
+
+/// One of an enum's values. Each is created once, with its enum, so two
+/// variants are equal only if they are the same one.
+#[derive(Debug)]
+pub struct Variant {
+    enum_name: String,
+    name: String,
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.enum_name, self.name)
+    }
+}
@@ -1,4 +1,9 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    mem,
+    rc::{Rc, Weak},
+};
 
 use crate::{error::RuntimeError, interpreter::Object, token::Token};
 
@@ -7,6 +12,44 @@ pub struct Environment {
     values: HashMap<String, Rc<RefCell<Object>>>,
     pub enclosing: Option<Rc<RefCell<Environment>>>,
 }
+/// The environments that functions have closed over. A function stored in an
+/// environment it closes over, directly or through an enclosing one, forms an
+/// `Rc` cycle that is never freed. Rather than weakening closures, which must
+/// keep their environment alive once returned, the tracked environments are
+/// emptied when this is dropped along with the interpreter. That frees every
+/// cycle passing through an environment, though values taken out of the
+/// interpreter lose their closures' variables. Cycles made only of instances,
+/// such as an instance stored in its own field, are not freed.
+#[derive(Default)]
+pub struct ClosedOver(Vec<Weak<RefCell<Environment>>>);
+
+impl ClosedOver {
+    pub fn track(&mut self, environment: &Rc<RefCell<Environment>>) {
+        if self
+            .0
+            .last()
+            .is_some_and(|last| last.as_ptr() == Rc::as_ptr(environment))
+        {
+            return;
+        }
+        // forget freed environments before growing, so tracking stays cheap
+        if self.0.len() == self.0.capacity() {
+            self.0.retain(|e| e.strong_count() > 0);
+        }
+        self.0.push(Rc::downgrade(environment));
+    }
+}
+
+impl Drop for ClosedOver {
+    fn drop(&mut self) {
+        for environment in self.0.drain(..).filter_map(|e| e.upgrade()) {
+            // values are dropped after the borrow ends
+            let values = mem::take(&mut environment.borrow_mut().values);
+            drop(values);
+        }
+    }
+}
+
 impl Environment {
     pub fn new(enclosing: Option<Rc<RefCell<Environment>>>) -> Self {
         Environment {
@@ -16,25 +59,29 @@ impl Environment {
     }
 
     pub fn get(&self, name: Token) -> Result<Rc<RefCell<Object>>, RuntimeError> {
-        match self.values.get(&name.lexeme) {
-            Some(o) => Ok(Rc::clone(o)),
-            None => match &self.enclosing {
-                Some(e) => {
-                    let enc = e.borrow_mut();
-                    enc.get(name)
-                }
-                None => Err(RuntimeError::new(
-                    name.clone(),
-                    &("Get: Undefined variable '".to_owned() + &name.lexeme + "'."),
-                    None,
-                )),
-            },
+        match self.find(&name.lexeme) {
+            Some(o) => Ok(o),
+            None => Err(RuntimeError::new(
+                name.clone(),
+                &("Get: Undefined variable '".to_owned() + &name.lexeme + "'."),
+                None,
+            )),
+        }
+    }
+
+    /// Returns the value of `name` in this environment or the nearest
+    /// enclosing one that defines it. Unlike `get`, this takes the name by
+    /// reference, so hot lookups of globals don't clone a `Token`.
+    pub fn find(&self, name: &str) -> Option<Rc<RefCell<Object>>> {
+        match self.values.get(name) {
+            Some(o) => Some(Rc::clone(o)),
+            None => self.enclosing.as_ref()?.borrow().find(name),
         }
     }
 
     pub fn assign(&mut self, name: Token, value: Rc<RefCell<Object>>) -> Result<(), RuntimeError> {
-        if self.values.contains_key(&name.lexeme) {
-            self.values.insert(name.lexeme, value);
+        if let Some(v) = self.values.get_mut(&name.lexeme) {
+            *v = value;
             return Ok(());
         }
 
@@ -51,47 +98,76 @@ impl Environment {
         }
     }
 
-    pub fn define(&mut self, name: String, value: Rc<RefCell<Object>>) -> () {
+    pub fn define(&mut self, name: String, value: Rc<RefCell<Object>>) {
         self.values.insert(name, value);
     }
 
+    /// Returns the value of `name` in this environment, without looking in
+    /// enclosing ones.
+    pub fn lookup(&self, name: &str) -> Option<Rc<RefCell<Object>>> {
+        self.values.get(name).map(Rc::clone)
+    }
+
+    /// Returns the bindings made in this environment itself.
+    pub(crate) fn values(&self) -> &HashMap<String, Rc<RefCell<Object>>> {
+        &self.values
+    }
+
+    /// Replaces every binding made in this environment itself.
+    pub(crate) fn set_values(&mut self, values: HashMap<String, Rc<RefCell<Object>>>) {
+        self.values = values;
+    }
+
+    /// Returns the outermost environment enclosing `environment`, which holds
+    /// the globals of the module it was created in.
+    pub fn root(environment: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let mut root = Rc::clone(environment);
+        loop {
+            let enclosing = match &root.borrow().enclosing {
+                Some(e) => Rc::clone(e),
+                None => break,
+            };
+            root = enclosing;
+        }
+        root
+    }
+
+    /// Returns how many environments enclose `environment`, which is 0 for
+    /// the globals.
+    pub fn depth(environment: &Rc<RefCell<Environment>>) -> usize {
+        let mut depth = 0;
+        let mut current = Rc::clone(environment);
+        loop {
+            let enclosing = match &current.borrow().enclosing {
+                Some(e) => Rc::clone(e),
+                None => break,
+            };
+            current = enclosing;
+            depth += 1;
+        }
+        depth
+    }
+
     fn ancestor(&self, distance: usize) -> Rc<RefCell<Environment>> {
-        let enclosing = Rc::clone(&self.enclosing.as_ref().unwrap());
+        let enclosing = Rc::clone(self.enclosing.as_ref().unwrap());
         let mut environment = enclosing;
         for _ in 1..distance {
-            let enclosing = Rc::clone(&environment.borrow().enclosing.as_ref().unwrap());
+            let enclosing = Rc::clone(environment.borrow().enclosing.as_ref().unwrap());
             environment = enclosing;
         }
         environment
     }
 
-    pub fn get_at(
-        &self,
-        distance: usize,
-        name: String,
-    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+    pub fn get_at(&self, distance: usize, name: &str) -> Result<Rc<RefCell<Object>>, RuntimeError> {
         if distance == 0 {
-            return Ok(Rc::clone(self.values.get(&name).unwrap()));
-        } else {
-            println!("[ENVIRONMENT] current environment: {:?}", self);
-            let ancestor = self.ancestor(distance);
-            let ancestor = ancestor.borrow_mut();
-            println!(
-                "[ENVIRONMENT] got ancestor {:?} at distance {:?}",
-                ancestor, distance
-            );
-            let object = ancestor.values.get(&name);
-            if let Some(o) = object {
-                return Ok(Rc::clone(o));
-            } else {
-                println!("[ENVIRONMENT] cannot get object");
-            }
+            return Ok(Rc::clone(self.values.get(name).unwrap()));
         }
-        panic!()
+        let ancestor = self.ancestor(distance);
+        let ancestor = ancestor.borrow();
+        Ok(Rc::clone(ancestor.values.get(name).unwrap()))
     }
 
     pub fn assign_at(&mut self, distance: usize, name: Token, value: Rc<RefCell<Object>>) {
-        println!("assign_at {}", distance);
         if distance == 0 {
             self.values.insert(name.lexeme, value);
         } else {
@@ -116,6 +192,9 @@ mod tests {
             .insert(String::from("test_key"), Rc::clone(&bool_obj));
         let token = Token {
             line: 0,
+            offset: 0,
+            column: 0,
+            end_column: 0,
             lexeme: String::from("test_key"),
             literal: None,
             token_type: TokenType::Identifier,
@@ -136,6 +215,9 @@ mod tests {
         let env = Environment::new(None);
         let token = Token {
             line: 0,
+            offset: 0,
+            column: 0,
+            end_column: 0,
             lexeme: String::from("test_key"),
             literal: None,
             token_type: TokenType::Identifier,
@@ -154,6 +236,9 @@ mod tests {
         let env = Environment::new(Some(Rc::new(RefCell::new(enclosing))));
         let token = Token {
             line: 0,
+            offset: 0,
+            column: 0,
+            end_column: 0,
             lexeme: String::from("test_key"),
             literal: None,
             token_type: TokenType::Identifier,
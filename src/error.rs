@@ -2,43 +2,75 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::interpreter::Object;
-use crate::report;
 use crate::Token;
+use crate::report_message;
 
 #[derive(Debug)]
 pub struct RuntimeError {
-    token: Token,
-    message: String,
+    pub token: Token,
+    pub message: String,
     pub value: Option<Rc<RefCell<Object>>>,
+    // the loop a `break` or `continue` targets, if it names one
+    pub label: Option<String>,
 }
 
 impl RuntimeError {
+    // Runtime errors are reported by whoever handles them, so that an
+    // interpreter can send them to its own error writer.
     pub fn new(token: Token, message: &str, value: Option<Rc<RefCell<Object>>>) -> Self {
-        if value.is_none() {
-            report(token.line, &token.lexeme, &message);
-        }
-
         Self {
             token,
             message: message.into(),
             value,
+            label: None,
+        }
+    }
+
+    pub fn report(&self) -> String {
+        report_message(self.token.line, &self.token.lexeme, &self.message)
+    }
+
+    /// Creates the unreported error that unwinds loop bodies for `break` or
+    /// `continue`, which is told apart by the keyword's token type, up to the
+    /// loop named by `label` or else the innermost one.
+    pub fn jump(keyword: Token, label: Option<&Token>) -> Self {
+        Self {
+            token: keyword,
+            message: String::new(),
+            value: None,
+            label: label.map(|l| l.lexeme.clone()),
         }
     }
 }
 
 #[derive(Debug)]
 pub struct SyntaxError {
-    token: Token,
-    message: String,
+    pub token: Token,
+    pub message: String,
 }
 
 impl SyntaxError {
+    // Like runtime errors, syntax errors are left for the caller to report.
     pub fn new(token: Token, message: &str) -> Self {
-        report(token.line, &token.lexeme, &message);
-
-        return Self {
+        Self {
             token,
             message: message.into(),
-        };
+        }
+    }
+}
+
+/// A problem found before a program runs, while parsing or resolving it.
+#[derive(Debug)]
+pub enum LoxError {
+    Syntax(SyntaxError),
+    Resolve(RuntimeError),
+}
+
+impl LoxError {
+    pub fn report(&self) -> String {
+        match self {
+            LoxError::Syntax(e) => report_message(e.token.line, &e.token.lexeme, &e.message),
+            LoxError::Resolve(e) => e.report(),
+        }
     }
 }
@@ -1,29 +1,56 @@
 use std::cell::RefCell;
+use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::mem;
 use std::rc::Rc;
 
 use crate::environment::Environment;
 use crate::error::RuntimeError;
+use crate::generator::Generator;
 use crate::instance::Instance;
 use crate::interpreter::{Callable, Interpreter, Object};
 use crate::stmt::Stmt;
+use crate::token::Token;
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Function {
     pub declaration: Stmt,
     closure: Rc<RefCell<Environment>>,
+    // whether the body yields, making calls return a generator
+    generator: bool,
 }
 
+// The closure is left out since it usually holds the function itself.
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("declaration", &self.declaration)
+            .finish_non_exhaustive()
+    }
+}
+
+// A function is identified by its declaration and the environment it closed
+// over, so each evaluation of a declaration gives a distinct function.
+impl PartialEq for Function {
+    fn eq(&self, other: &Function) -> bool {
+        self.declaration == other.declaration && Rc::ptr_eq(&self.closure, &other.closure)
+    }
+}
+
+// Hashing the span rather than the whole declaration keeps functions cheap to
+// use as map keys.
 impl Hash for Function {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.declaration.hash(state);
+        self.declaration.span().hash(state);
+        Rc::as_ptr(&self.closure).hash(state);
     }
 }
 
 impl Function {
     pub fn new(declaration: Stmt, environment: Rc<RefCell<Environment>>) -> Function {
-        if let Stmt::Function { .. } = declaration {
+        if let Stmt::Function { body, .. } = &declaration {
             return Function {
+                generator: body.iter().any(yields),
                 declaration,
                 closure: environment,
             };
@@ -39,7 +66,25 @@ impl Function {
             "this".into(),
             Rc::new(RefCell::new(Object::Instance(instance))),
         );
-        return Function::new(self.declaration.clone(), environment);
+        Function::new(self.declaration.clone(), environment)
+    }
+}
+
+/// Returns whether `stmt` yields, not counting functions declared in it.
+fn yields(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Yield { .. } => true,
+        Stmt::Block { statements, .. } => statements.iter().any(yields),
+        Stmt::If {
+            then_branch,
+            else_branch,
+            ..
+        } => yields(then_branch) || else_branch.as_deref().is_some_and(yields),
+        Stmt::For {
+            initializer, body, ..
+        } => initializer.as_deref().is_some_and(yields) || yields(body),
+        Stmt::While { body, .. } => yields(body),
+        _ => false,
     }
 }
 
@@ -47,6 +92,7 @@ impl Callable for Function {
     fn call(
         &self,
         interpreter: &mut Interpreter,
+        _paren: &Token,
         arguments: Vec<Rc<RefCell<Object>>>,
     ) -> Result<Rc<RefCell<Object>>, RuntimeError>
     where
@@ -55,12 +101,7 @@ impl Callable for Function {
         let environment = Rc::new(RefCell::new(Environment::new(Some(Rc::clone(
             &self.closure,
         )))));
-        if let Stmt::Function {
-            name: _,
-            params,
-            body,
-        } = &self.declaration
-        {
+        if let Stmt::Function { params, body, .. } = &self.declaration {
             let mut arguments_iter = arguments.iter();
             for p in params {
                 let arg = arguments_iter
@@ -70,8 +111,25 @@ impl Callable for Function {
                     .borrow_mut()
                     .define(p.lexeme.clone(), Rc::clone(arg))
             }
-            let result = interpreter.interpret_block(&body, environment);
+            // Unresolved names refer to the globals of the module that
+            // defined the function, not those of the caller.
+            let globals = mem::replace(&mut interpreter.globals, Environment::root(&self.closure));
+            let enclosing = interpreter.collect_yields(self.generator.then(Vec::new));
+            let deferred = interpreter.collect_deferred();
+            let result = interpreter.interpret_block(body, environment);
+            let result = interpreter.run_deferred(deferred, result);
+            let yielded = interpreter.collect_yields(enclosing);
+            interpreter.globals = globals;
 
+            if let (Some(values), Stmt::Function { name, .. }) = (yielded, &self.declaration) {
+                return match result {
+                    Err(e) if e.value.is_none() => Err(e),
+                    _ => Ok(Rc::new(RefCell::new(Object::Generator(Generator::new(
+                        name.lexeme.clone(),
+                        values,
+                    ))))),
+                };
+            }
             if let Err(e) = result {
                 match e.value {
                     Some(v) => return Ok(v),
@@ -83,29 +141,79 @@ impl Callable for Function {
     }
 
     fn arity(&self) -> usize {
-        if let Stmt::Function {
-            name: _,
-            params,
-            body: _,
-        } = &self.declaration
-        {
+        if let Stmt::Function { params, .. } = &self.declaration {
             return params.len();
         }
         0
     }
 }
 
-#[derive(Debug, Clone, Hash)]
+pub type NativeFn = fn(
+    &mut Interpreter,
+    &Token,
+    Vec<Rc<RefCell<Object>>>,
+) -> Result<Rc<RefCell<Object>>, RuntimeError>;
+
+/// A method of a host-defined class, called with the instance it was
+/// accessed on.
+pub type NativeMethod = fn(
+    &mut Interpreter,
+    &Instance,
+    &Token,
+    Vec<Rc<RefCell<Object>>>,
+) -> Result<Rc<RefCell<Object>>, RuntimeError>;
+
+#[derive(Debug, Clone)]
 pub struct NativeFunction {
     arity: usize,
-    native_function: fn(),
+    native_function: Native,
+}
+
+#[derive(Debug, Clone)]
+enum Native {
+    Function(NativeFn),
+    Method(NativeMethod, Instance),
 }
 
 impl NativeFunction {
-    pub fn new(arity: usize, native_function: fn()) -> NativeFunction {
+    pub fn new(arity: usize, native_function: NativeFn) -> NativeFunction {
         NativeFunction {
             arity,
-            native_function,
+            native_function: Native::Function(native_function),
+        }
+    }
+
+    /// Binds a host class's method to `instance`.
+    pub fn method(arity: usize, method: NativeMethod, instance: Instance) -> NativeFunction {
+        NativeFunction {
+            arity,
+            native_function: Native::Method(method, instance),
+        }
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &NativeFunction) -> bool {
+        self.arity == other.arity
+            && match (&self.native_function, &other.native_function) {
+                (Native::Function(l), Native::Function(r)) => std::ptr::fn_addr_eq(*l, *r),
+                (Native::Method(l, this), Native::Method(r, other)) => {
+                    std::ptr::fn_addr_eq(*l, *r) && this == other
+                }
+                _ => false,
+            }
+    }
+}
+
+impl Hash for NativeFunction {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.arity.hash(state);
+        match &self.native_function {
+            Native::Function(f) => f.hash(state),
+            Native::Method(m, instance) => {
+                m.hash(state);
+                instance.hash(state);
+            }
         }
     }
 }
@@ -117,14 +225,73 @@ impl Callable for NativeFunction {
 
     fn call(
         &self,
-        _interpreter: &mut Interpreter,
-        _arguments: Vec<Rc<RefCell<Object>>>,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Rc<RefCell<Object>>>,
     ) -> Result<Rc<RefCell<Object>>, RuntimeError>
     where
         Self: Sized,
     {
-        (self.native_function)();
+        match &self.native_function {
+            Native::Function(f) => f(interpreter, paren, arguments),
+            Native::Method(m, instance) => m(interpreter, instance, paren, arguments),
+        }
+    }
+}
 
-        Ok(Rc::new(RefCell::new(Object::Nil)))
+/// A callable with its first arguments already supplied, made by the
+/// `partial` native. Calling it calls the underlying callable with the bound
+/// arguments followed by the ones given.
+#[derive(Debug, Clone)]
+pub struct PartialFunction {
+    callee: Rc<RefCell<Object>>,
+    // shared between clones, so a clone is the same partial function
+    bound: Rc<Vec<Rc<RefCell<Object>>>>,
+}
+
+impl PartialFunction {
+    pub fn new(callee: Rc<RefCell<Object>>, bound: Vec<Rc<RefCell<Object>>>) -> Self {
+        PartialFunction {
+            callee,
+            bound: Rc::new(bound),
+        }
+    }
+}
+
+impl PartialEq for PartialFunction {
+    fn eq(&self, other: &PartialFunction) -> bool {
+        Rc::ptr_eq(&self.bound, &other.bound)
+    }
+}
+
+impl Hash for PartialFunction {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.bound).hash(state);
+    }
+}
+
+impl fmt::Display for PartialFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Partial<{}>", self.callee.borrow())
+    }
+}
+
+impl Callable for PartialFunction {
+    fn arity(&self) -> usize {
+        let arity = self.callee.borrow().arity().unwrap_or(0);
+        arity.saturating_sub(self.bound.len())
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Rc<RefCell<Object>>>,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError>
+    where
+        Self: Sized,
+    {
+        let arguments = self.bound.iter().cloned().chain(arguments).collect();
+        interpreter.call_value(&self.callee.borrow(), paren, arguments)
     }
 }
@@ -0,0 +1,63 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+use crate::interpreter::Object;
+
+/// What calling a function containing `yield` returns, handing out the values
+/// it yielded in order through the `next` native.
+///
+/// For now the function runs to completion when called, collecting every
+/// value it yields, so a generator that never returns never produces one.
+#[derive(Clone)]
+pub struct Generator {
+    name: String,
+    // shared between clones, so a clone refers to the same generator
+    values: Rc<RefCell<VecDeque<Rc<RefCell<Object>>>>>,
+}
+
+impl Generator {
+    pub fn new(name: String, values: Vec<Rc<RefCell<Object>>>) -> Self {
+        Generator {
+            name,
+            values: Rc::new(RefCell::new(values.into())),
+        }
+    }
+
+    /// Returns the next yielded value, or `None` once all have been taken.
+    pub fn next(&self) -> Option<Rc<RefCell<Object>>> {
+        self.values.borrow_mut().pop_front()
+    }
+}
+
+impl PartialEq for Generator {
+    fn eq(&self, other: &Generator) -> bool {
+        Rc::ptr_eq(&self.values, &other.values)
+    }
+}
+
+impl Hash for Generator {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.values).hash(state);
+    }
+}
+
+// The values are left out, since they may refer back to the generator.
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Generator")
+            .field("name", &self.name)
+            .field("remaining", &self.values.borrow().len())
+            .finish()
+    }
+}
+
+impl fmt::Display for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Generator<{}>", self.name)
+    }
+}
@@ -1,27 +1,45 @@
-use crate::{class::Class, error::RuntimeError, interpreter::Object, token::Token};
-use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+use crate::{
+    class::Class,
+    error::RuntimeError,
+    function::{Function, NativeFunction},
+    interpreter::Object,
+    token::Token,
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
 
-#[derive(Clone, Debug)]
+// Fields are shared between clones, so a clone refers to the same instance.
+#[derive(Clone)]
 pub struct Instance {
-    klass: Class,
-    fields: HashMap<String, Rc<RefCell<Object>>>,
+    klass: Rc<Class>,
+    fields: Rc<RefCell<HashMap<String, Rc<RefCell<Object>>>>>,
 }
 
 impl Instance {
-    pub fn new(klass: Class) -> Self {
+    pub fn new(klass: Rc<Class>) -> Self {
         Instance {
             klass,
-            fields: HashMap::new(),
+            fields: Rc::new(RefCell::new(HashMap::new())),
         }
     }
 
     pub fn get(&self, name: &Token) -> Result<Rc<RefCell<Object>>, RuntimeError> {
-        if self.fields.contains_key(&name.lexeme) {
-            return Ok(self.fields.get(&name.lexeme).unwrap().clone());
+        if let Some(field) = self.fields.borrow().get(&name.lexeme) {
+            return Ok(Rc::clone(field));
         }
         let method = self.klass.find_method(name.lexeme.clone());
         if let Some(m) = method {
-            return Ok(Rc::new(RefCell::new(Object::Function(Box::new(m)))));
+            let bound = m.bind(self.clone());
+            return Ok(Rc::new(RefCell::new(Object::Function(Box::new(bound)))));
+        }
+        if let Some((arity, method)) = self.klass.find_native_method(&name.lexeme) {
+            let bound = NativeFunction::method(arity, method, self.clone());
+            return Ok(Rc::new(RefCell::new(Object::NativeFunction(bound))));
         }
         Err(RuntimeError::new(
             name.clone(),
@@ -30,13 +48,80 @@ impl Instance {
         ))
     }
 
+    /// Returns the class's method `name`, bound to this instance.
+    pub fn method(&self, name: &str) -> Option<Function> {
+        let method = self.klass.find_method(name.to_string())?;
+        Some(method.bind(self.clone()))
+    }
+
+    /// Returns the class's getter for `name`, bound to this instance.
+    pub fn getter(&self, name: &Token) -> Option<Function> {
+        let getter = self.klass.find_getter(&name.lexeme)?;
+        Some(getter.bind(self.clone()))
+    }
+
+    /// Returns the class's setter for `name`, bound to this instance.
+    pub fn setter(&self, name: &Token) -> Option<Function> {
+        let setter = self.klass.find_setter(&name.lexeme)?;
+        Some(setter.bind(self.clone()))
+    }
+
     pub fn set(&mut self, name: &Token, value: Rc<RefCell<Object>>) {
-        self.fields.insert(name.lexeme.clone(), value);
+        self.fields.borrow_mut().insert(name.lexeme.clone(), value);
+    }
+
+    /// Sets the field `name`, which is visible through every clone of this
+    /// instance.
+    pub fn define(&self, name: String, value: Rc<RefCell<Object>>) {
+        self.fields.borrow_mut().insert(name, value);
+    }
+
+    pub fn class(&self) -> &Rc<Class> {
+        &self.klass
+    }
+
+    /// Returns the fields sorted by name.
+    pub fn fields(&self) -> Vec<(String, Rc<RefCell<Object>>)> {
+        let mut fields: Vec<_> = self
+            .fields
+            .borrow()
+            .iter()
+            .map(|(name, value)| (name.clone(), Rc::clone(value)))
+            .collect();
+        fields.sort_by(|(l, _), (r, _)| l.cmp(r));
+        fields
+    }
+
+    /// Identifies the instance; clones of an instance share the same id.
+    pub fn id(&self) -> *const () {
+        Rc::as_ptr(&self.fields) as *const ()
+    }
+}
+
+impl PartialEq for Instance {
+    fn eq(&self, other: &Instance) -> bool {
+        Rc::ptr_eq(&self.fields, &other.fields)
+    }
+}
+
+impl Hash for Instance {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.fields).hash(state);
+    }
+}
+
+// Only field names are shown, since fields may refer back to the instance.
+impl fmt::Debug for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Instance")
+            .field("klass", &self.klass.name)
+            .field("fields", &self.fields.borrow().keys())
+            .finish()
     }
 }
 
 impl fmt::Display for Instance {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:}", format!("{} instance", self.klass.name.clone()))
+        write!(f, "{} instance", self.klass.name)
     }
 }
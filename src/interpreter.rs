@@ -1,41 +1,70 @@
-use crate::ast::{Expr, Visitor};
+use crate::ast::{Expr, ExprId, Visitor};
 use crate::class::Class;
-use crate::environment::Environment;
+use crate::cycle::CycleGuard;
+use crate::enumeration::{Enum, Variant};
+use crate::environment::{ClosedOver, Environment};
 use crate::error::RuntimeError;
-use crate::function::{Function, NativeFunction};
+use crate::function::{Function, NativeFn, NativeFunction, PartialFunction};
+use crate::generator::Generator;
 use crate::instance::Instance;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
 use crate::stmt::Stmt;
 use crate::token::{Token, TokenType};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::fs;
 use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::mem;
 use std::rc::Rc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Clone)]
 pub enum Object {
-    Class(Class),
+    Class(Rc<Class>),
     Instance(Instance),
-    String(String),
-    Number(f32),
+    // Shared so that cloning a string value doesn't copy it.
+    String(Rc<str>),
+    Number(f64),
     Bool(bool),
     Nil,
     Function(Box<Function>),
     NativeFunction(NativeFunction),
+    Array(Vec<Rc<RefCell<Object>>>),
+    /// Keys compare as they do with `==`. Functions and instances are keys
+    /// by identity, so a function finds its entry only through the same
+    /// function value. Each access of a method binds a new function.
+    Map(HashMap<Object, Rc<RefCell<Object>>>),
+    Generator(Generator),
+    Partial(PartialFunction),
+    Enum(Rc<Enum>),
+    Variant(Rc<Variant>),
 }
 
 impl Hash for Object {
     fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
         match self {
             Object::String(s) => s.hash(state),
-            Object::Number(n) => n.to_bits().hash(state),
+            // 0.0 and -0.0 are equal, so they must hash the same
+            Object::Number(n) => (if *n == 0.0 { 0.0 } else { *n }).to_bits().hash(state),
             Object::Bool(b) => b.hash(state),
-            Object::Class(_c) => self.hash(state),
-            Object::Instance(_i) => self.hash(state),
-            Object::Nil => self.hash(state),
+            Object::Class(c) => Rc::as_ptr(c).hash(state),
+            Object::Instance(i) => i.hash(state),
+            Object::Nil => {}
             Object::Function(f) => f.hash(state),
             Object::NativeFunction(f) => f.hash(state),
+            Object::Array(elements) => elements.len().hash(state),
+            Object::Map(entries) => entries.len().hash(state),
+            Object::Generator(g) => g.hash(state),
+            Object::Partial(p) => p.hash(state),
+            Object::Enum(e) => Rc::as_ptr(e).hash(state),
+            Object::Variant(v) => Rc::as_ptr(v).hash(state),
         }
     }
 }
@@ -44,6 +73,7 @@ pub trait Callable {
     fn call(
         &self,
         interpreter: &mut Interpreter,
+        paren: &Token,
         arguments: Vec<Rc<RefCell<Object>>>,
     ) -> Result<Rc<RefCell<Object>>, RuntimeError>
     where
@@ -71,23 +101,276 @@ impl fmt::Display for Object {
                 write!(f, "{:}", n)
             }
             Object::Nil => {
-                write!(f, "{:}", "nil")
+                write!(f, "nil")
             }
             Object::Function(func) => {
-                if let Stmt::Function {
-                    name,
-                    params: _,
-                    body: _,
-                } = &func.declaration
-                {
+                if let Stmt::Function { name, .. } = &func.declaration {
                     return write!(f, "{:}", "Function<".to_owned() + &name.lexeme + ">");
                 }
-                write!(f, "{:}", "Anonymous Function")
+                write!(f, "Anonymous Function")
             }
             Object::NativeFunction(_) => {
-                write!(f, "{:}", "Native Function")
+                write!(f, "Native Function")
             }
+            Object::Generator(g) => {
+                write!(f, "{:}", g)
+            }
+            Object::Partial(p) => {
+                write!(f, "{:}", p)
+            }
+            Object::Enum(e) => {
+                write!(f, "{:}", e)
+            }
+            Object::Variant(v) => {
+                write!(f, "{:}", v)
+            }
+            Object::Array(_) | Object::Map(_) => {
+                write_nested(f, self, &mut CycleGuard::default(), Style::Plain)
+            }
+        }
+    }
+}
+
+/// How much detail formatting a value shows.
+#[derive(Clone, Copy, PartialEq)]
+enum Style {
+    Plain,
+    // instances show their fields, classes their methods and functions their
+    // parameters
+    Verbose,
+    // as verbose, and strings are quoted and escaped wherever they appear
+    Debug,
+}
+
+/// Writes an array or map, quoting any strings inside it. Unless the style is
+/// plain, instances show their fields as well. Cycles print as `[...]`, `{...}`
+/// or `Name {...}`.
+fn write_nested(
+    f: &mut fmt::Formatter,
+    value: &Object,
+    guard: &mut CycleGuard,
+    style: Style,
+) -> fmt::Result {
+    let id = match value {
+        Object::Class(class) if style != Style::Plain => {
+            return match &class.method_names()[..] {
+                [] => write!(f, "<class {}>", class.name),
+                names => write!(f, "<class {}: {}>", class.name, names.join(", ")),
+            };
+        }
+        Object::Function(function) if style != Style::Plain => {
+            if let Stmt::Function { name, params, .. } = &function.declaration {
+                let params: Vec<&str> = params.iter().map(|p| p.lexeme.as_str()).collect();
+                return write!(f, "<fn {}({})>", name.lexeme, params.join(", "));
+            }
+            return write!(f, "{}", value);
+        }
+        Object::Instance(i) if style != Style::Plain => i.id(),
+        Object::Array(_) | Object::Map(_) => value as *const Object as *const (),
+        _ => return write!(f, "{}", value),
+    };
+    if !guard.enter(id) {
+        return match value {
+            Object::Array(_) => write!(f, "[...]"),
+            Object::Map(_) => write!(f, "{{...}}"),
+            _ => write!(f, "{} {{...}}", value.class_name()),
+        };
+    }
+    match value {
+        Object::Array(elements) => {
+            write!(f, "[")?;
+            for (i, element) in elements.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_element(f, &element.borrow(), guard, style)?;
+            }
+            write!(f, "]")?;
+        }
+        Object::Map(entries) => {
+            write!(f, "{{")?;
+            for (i, (key, value)) in entries.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_element(f, key, guard, style)?;
+                write!(f, ": ")?;
+                write_element(f, &value.borrow(), guard, style)?;
+            }
+            write!(f, "}}")?;
+        }
+        Object::Instance(instance) => {
+            let fields = instance.fields();
+            write!(f, "{} {{", value.class_name())?;
+            for (i, (name, field)) in fields.iter().enumerate() {
+                write!(f, "{}{}: ", if i > 0 { ", " } else { " " }, name)?;
+                write_element(f, &field.borrow(), guard, style)?;
+            }
+            if !fields.is_empty() {
+                write!(f, " ")?;
+            }
+            write!(f, "}}")?;
+        }
+        _ => unreachable!(),
+    }
+    guard.leave(id);
+    Ok(())
+}
+
+fn write_element(
+    f: &mut fmt::Formatter,
+    element: &Object,
+    guard: &mut CycleGuard,
+    style: Style,
+) -> fmt::Result {
+    match element {
+        Object::String(s) if style == Style::Debug => write!(f, "{:?}", s),
+        Object::String(s) => write!(f, "\"{}\"", s),
+        _ => write_nested(f, element, guard, style),
+    }
+}
+
+// Formats an object in the given style.
+struct Styled<'a>(&'a Object, Style);
+
+impl fmt::Display for Styled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let guard = &mut CycleGuard::default();
+        match self.1 {
+            Style::Debug => write_element(f, self.0, guard, self.1),
+            _ => write_nested(f, self.0, guard, self.1),
+        }
+    }
+}
+
+impl Object {
+    /// Formats the object like `Display`, except that instances show their
+    /// fields, e.g. `Point { x: 1, y: 2 }`, classes their methods, e.g.
+    /// `<class Animal: move, speak>`, and functions their parameters, e.g.
+    /// `<fn add(a, b)>`.
+    pub fn verbose(&self) -> String {
+        Styled(self, Style::Verbose).to_string()
+    }
+
+    /// Formats the object in verbose form with strings quoted and escaped,
+    /// so that `"5"` can be told apart from `5`.
+    pub fn debug(&self) -> String {
+        Styled(self, Style::Debug).to_string()
+    }
+
+    /// Returns how many arguments the object takes if it can be called.
+    pub fn arity(&self) -> Option<usize> {
+        match self {
+            Object::Function(f) => Some(f.arity()),
+            Object::NativeFunction(f) => Some(f.arity()),
+            Object::Class(c) => Some(c.arity()),
+            Object::Partial(p) => Some(p.arity()),
+            _ => None,
+        }
+    }
+
+    fn class_name(&self) -> String {
+        match self {
+            Object::Instance(i) => i.class().name.clone(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Compares arrays element by element, maps entry by entry and instances of
+/// classes with the same name field by field, recursing into what they hold.
+/// Anything else compares as with `==`. `comparing` holds the pairs being compared further
+/// up, so a pair met again through a cycle is taken to be equal.
+fn deep_equals(a: &Object, b: &Object, comparing: &mut HashSet<(*const (), *const ())>) -> bool {
+    let pair = match (a, b) {
+        (Object::Array(_), Object::Array(_)) | (Object::Map(_), Object::Map(_)) => (
+            a as *const Object as *const (),
+            b as *const Object as *const (),
+        ),
+        (Object::Instance(l), Object::Instance(r)) => (l.id(), r.id()),
+        _ => return a == b,
+    };
+    if !comparing.insert(pair) {
+        return true;
+    }
+    let equal = match (a, b) {
+        (Object::Array(l), Object::Array(r)) => {
+            l.len() == r.len()
+                && l.iter()
+                    .zip(r)
+                    .all(|(l, r)| deep_equals(&l.borrow(), &r.borrow(), comparing))
+        }
+        (Object::Map(l), Object::Map(r)) => {
+            l.len() == r.len()
+                && l.iter().all(|(key, l)| match r.get(key) {
+                    Some(r) => deep_equals(&l.borrow(), &r.borrow(), comparing),
+                    None => false,
+                })
+        }
+        (Object::Instance(l), Object::Instance(r)) => {
+            let (l_fields, r_fields) = (l.fields(), r.fields());
+            l.class().name == r.class().name
+                && l_fields.len() == r_fields.len()
+                && l_fields
+                    .iter()
+                    .zip(&r_fields)
+                    .all(|((l_name, l), (r_name, r))| {
+                        l_name == r_name && deep_equals(&l.borrow(), &r.borrow(), comparing)
+                    })
+        }
+        _ => unreachable!(),
+    };
+    comparing.remove(&pair);
+    equal
+}
+
+/// Copies `value` along with the arrays, maps and instances it refers to.
+/// Values reached more than once, including through cycles, are copied once
+/// and the copy is shared in the same way.
+pub fn deep_clone(value: &Rc<RefCell<Object>>) -> Rc<RefCell<Object>> {
+    deep_clone_guarded(value, &mut CycleGuard::default())
+}
+
+fn deep_clone_guarded(value: &Rc<RefCell<Object>>, guard: &mut CycleGuard) -> Rc<RefCell<Object>> {
+    let object = value.borrow();
+    let id = match &*object {
+        Object::Instance(i) => i.id(),
+        _ => Rc::as_ptr(value) as *const (),
+    };
+    if let Some(copy) = guard.copy_of(id) {
+        return copy;
+    }
+    match &*object {
+        Object::Array(elements) => {
+            let copy = Rc::new(RefCell::new(Object::Nil));
+            guard.remember(id, Rc::clone(&copy));
+            let elements = elements
+                .iter()
+                .map(|e| deep_clone_guarded(e, guard))
+                .collect();
+            *copy.borrow_mut() = Object::Array(elements);
+            copy
+        }
+        Object::Map(entries) => {
+            let copy = Rc::new(RefCell::new(Object::Nil));
+            guard.remember(id, Rc::clone(&copy));
+            let entries = entries
+                .iter()
+                .map(|(k, v)| (k.clone(), deep_clone_guarded(v, guard)))
+                .collect();
+            *copy.borrow_mut() = Object::Map(entries);
+            copy
         }
+        Object::Instance(instance) => {
+            let fields = Instance::new(Rc::clone(instance.class()));
+            let copy = Rc::new(RefCell::new(Object::Instance(fields.clone())));
+            guard.remember(id, Rc::clone(&copy));
+            for (name, field) in instance.fields() {
+                fields.define(name, deep_clone_guarded(&field, guard));
+            }
+            copy
+        }
+        other => Rc::new(RefCell::new(other.clone())),
     }
 }
 
@@ -95,9 +378,22 @@ impl PartialEq for Object {
     fn eq(&self, other: &Object) -> bool {
         match (self, other) {
             (&Object::Number(l), &Object::Number(r)) => l == r,
-            (Object::String(l), Object::String(r)) => l == r,
+            (Object::String(l), Object::String(r)) => Rc::ptr_eq(l, r) || l == r,
             (Object::Bool(l), Object::Bool(r)) => l == r,
             (Object::Nil, Object::Nil) => true,
+            (Object::Class(l), Object::Class(r)) => Rc::ptr_eq(l, r),
+            (Object::Instance(l), Object::Instance(r)) => l == r,
+            (Object::Function(l), Object::Function(r)) => l == r,
+            (Object::NativeFunction(l), Object::NativeFunction(r)) => l == r,
+            // Values live in their own cells, so this is whether both are
+            // the same array or map. `deep_equals` compares what they hold.
+            (Object::Array(_), Object::Array(_)) | (Object::Map(_), Object::Map(_)) => {
+                std::ptr::eq(self, other)
+            }
+            (Object::Generator(l), Object::Generator(r)) => l == r,
+            (Object::Partial(l), Object::Partial(r)) => l == r,
+            (Object::Enum(l), Object::Enum(r)) => Rc::ptr_eq(l, r),
+            (Object::Variant(l), Object::Variant(r)) => Rc::ptr_eq(l, r),
             (_, _) => false,
         }
     }
@@ -108,36 +404,983 @@ impl Eq for Object {}
 pub struct Interpreter {
     pub globals: Rc<RefCell<Environment>>,
     environment: Rc<RefCell<Environment>>,
-    locals: HashMap<Expr, usize>,
+    // how many scopes out each resolved local is, keyed by the node using it
+    locals: HashMap<ExprId, usize>,
+    source: Option<String>,
+    // xorshift state behind random(), never zero
+    rng: u64,
+    // where `print` output and runtime errors are written
+    out: Box<dyn Write>,
+    err: Box<dyn Write>,
+    closed_over: ClosedOver,
+    // reads the source of an imported module from its path
+    loader: ModuleLoader,
+    // values yielded so far by the generator function being called, if any
+    yielded: Option<Vec<Rc<RefCell<Object>>>>,
+    // expressions deferred by the function being called, with the
+    // environment each was deferred in
+    deferred: Option<Vec<(Expr, Rc<RefCell<Environment>>)>>,
+    // whether `print` shows values in verbose form
+    verbose: bool,
+    // how far apart two numbers may be and still be `==`, if not exactly
+    tolerance: Option<f64>,
+    // what read_file() and write_file() use, and whether scripts may call
+    // them at all
+    file_system: Box<dyn FileSystem>,
+    allow_fs: bool,
 }
 
-impl Interpreter {
-    pub fn new() -> Self {
-        let globals = Rc::new(RefCell::new(Environment::new(None)));
+/// How far apart two numbers may be and still be `==` once tolerant equality
+/// is turned on.
+pub const DEFAULT_TOLERANCE: f64 = 1e-9;
+
+/// The variables visible at some point in a run, taken by
+/// `Interpreter::checkpoint` so `Interpreter::restore` can roll back to them.
+///
+/// Taking or restoring a checkpoint copies every array, map and instance
+/// reachable from those variables, so it costs time and memory in proportion
+/// to the program's data. Functions and classes are shared rather than
+/// copied. Variables only reachable through a closure, such as the locals of
+/// a function that has returned, are not part of a checkpoint.
+pub struct Checkpoint {
+    environment: Rc<RefCell<Environment>>,
+    // each environment from the current one out to the globals, with copies
+    // of its bindings
+    scopes: Vec<Scope>,
+}
+
+type Scope = (
+    Rc<RefCell<Environment>>,
+    HashMap<String, Rc<RefCell<Object>>>,
+);
+
+/// Copies the bindings of each environment together, so a value bound to
+/// several variables stays shared between them in the copy.
+fn copy_scopes(scopes: &[Scope]) -> Vec<Scope> {
+    let guard = &mut CycleGuard::default();
+    scopes
+        .iter()
+        .map(|(environment, values)| {
+            let values = values
+                .iter()
+                .map(|(name, value)| (name.clone(), deep_clone_guarded(value, guard)))
+                .collect();
+            (Rc::clone(environment), values)
+        })
+        .collect()
+}
+
+/// Reads the source of the module at a path, or describes why it can't.
+pub type ModuleLoader = Box<dyn Fn(&str) -> Result<String, String>>;
+
+/// The files `read_file` and `write_file` see. Errors describe why a file
+/// couldn't be read or written.
+pub trait FileSystem {
+    fn read(&self, path: &str) -> Result<String, String>;
+    fn write(&mut self, path: &str, contents: &str) -> Result<(), String>;
+}
+
+/// The filesystem of the machine running the interpreter.
+pub struct OsFileSystem;
+
+impl FileSystem for OsFileSystem {
+    fn read(&self, path: &str) -> Result<String, String> {
+        fs::read_to_string(path).map_err(|e| e.to_string())
+    }
+
+    fn write(&mut self, path: &str, contents: &str) -> Result<(), String> {
+        fs::write(path, contents).map_err(|e| e.to_string())
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Creates a global environment holding the native functions.
+fn global_environment() -> Rc<RefCell<Environment>> {
+    let globals = Rc::new(RefCell::new(Environment::new(None)));
+    globals.borrow_mut().define(
+        String::from("clock"),
+        Rc::new(RefCell::new(Object::NativeFunction(NativeFunction::new(
+            0,
+            |interpreter, _, _| {
+                let start = SystemTime::now();
+                let since_the_epoch = start
+                    .duration_since(UNIX_EPOCH)
+                    .expect("Time went backwards")
+                    .as_millis();
+                writeln!(interpreter.out, "{:?}", since_the_epoch).expect("Failed to write output");
+                Ok(Rc::new(RefCell::new(Object::Nil)))
+            },
+        )))),
+    );
+    globals.borrow_mut().define(
+        String::from("hash"),
+        Rc::new(RefCell::new(Object::NativeFunction(NativeFunction::new(
+            1,
+            |_, _, arguments| {
+                // DefaultHasher::new() always uses the same keys, so hashes
+                // are stable between runs. Keep 53 bits so the result is
+                // exactly representable as a number.
+                let mut hasher = DefaultHasher::new();
+                arguments[0].borrow().hash(&mut hasher);
+                let hash = (hasher.finish() & 0x1F_FFFF_FFFF_FFFF) as f64;
+                Ok(Rc::new(RefCell::new(Object::Number(hash))))
+            },
+        )))),
+    );
+    define_math(&globals);
+    define_random(&globals);
+    define_strings(&globals);
+    define_arrays(&globals);
+    define_files(&globals);
+    define_native(
+        &globals,
+        "next",
+        1,
+        |_, paren, arguments| match &*arguments[0].borrow() {
+            Object::Generator(generator) => Ok(generator
+                .next()
+                .unwrap_or_else(|| Rc::new(RefCell::new(Object::Nil)))),
+            _ => Err(RuntimeError::new(
+                paren.clone(),
+                "Argument must be a generator.",
+                None,
+            )),
+        },
+    );
+    define_native(&globals, "try_call", 2, |interpreter, paren, arguments| {
+        // Cloned so the callee is free to reassign the variable holding it.
+        let callee = arguments[0].borrow().clone();
+        let result = match &callee {
+            Object::Function(f) if f.arity() == 0 => f.call(interpreter, paren, vec![]),
+            Object::NativeFunction(f) if f.arity() == 0 => f.call(interpreter, paren, vec![]),
+            _ => {
+                return Err(RuntimeError::new(
+                    paren.clone(),
+                    "Argument must be a function that takes no arguments.",
+                    None,
+                ))
+            }
+        };
+        match result {
+            Err(e) if e.value.is_none() => Ok(Rc::clone(&arguments[1])),
+            result => result,
+        }
+    });
+    define_native(&globals, "partial", 2, |_, paren, arguments| {
+        if arguments[0].borrow().arity().is_none_or(|arity| arity == 0) {
+            return Err(RuntimeError::new(
+                paren.clone(),
+                "Argument must be a function that takes arguments.",
+                None,
+            ));
+        }
+        let bound = vec![Rc::clone(&arguments[1])];
+        let partial = PartialFunction::new(Rc::clone(&arguments[0]), bound);
+        Ok(Rc::new(RefCell::new(Object::Partial(partial))))
+    });
+    define_native(
+        &globals,
+        "call_method",
+        3,
+        |interpreter, paren, arguments| {
+            let instance = match &*arguments[0].borrow() {
+                Object::Instance(instance) => instance.clone(),
+                _ => {
+                    return Err(RuntimeError::new(
+                        paren.clone(),
+                        "First argument must be an instance.",
+                        None,
+                    ))
+                }
+            };
+            let name = string_argument(paren, &arguments[1])?;
+            let arguments = array_argument(paren, &arguments[2])?;
+            let name = Token {
+                lexeme: name.to_string(),
+                ..paren.clone()
+            };
+            let method = instance.get(&name)?.borrow().clone();
+            if method.arity().is_none() {
+                return Err(RuntimeError::new(
+                    paren.clone(),
+                    &format!("'{}' is not a method.", name.lexeme),
+                    None,
+                ));
+            }
+            interpreter.call_value(&method, paren, arguments)
+        },
+    );
+    define_native(&globals, "assert", 1, |_, paren, arguments| {
+        if !is_truthy(&arguments[0].borrow()) {
+            return Err(RuntimeError::new(
+                paren.clone(),
+                &format!("Assertion failed at line {}.", paren.line),
+                None,
+            ));
+        }
+        Ok(Rc::new(RefCell::new(Object::Nil)))
+    });
+    define_native(&globals, "deep_equals", 2, |_, _, arguments| {
+        let equal = deep_equals(
+            &arguments[0].borrow(),
+            &arguments[1].borrow(),
+            &mut HashSet::new(),
+        );
+        Ok(Rc::new(RefCell::new(Object::Bool(equal))))
+    });
+    define_native(&globals, "debug", 1, |interpreter, _, arguments| {
+        let text = arguments[0].borrow().debug();
+        writeln!(interpreter.out, "{}", text).expect("Failed to write output");
+        Ok(Rc::new(RefCell::new(Object::Nil)))
+    });
+    // for checking which scope a closure runs in; a resolver bug shows up as
+    // a variable looked up at the wrong depth
+    #[cfg(debug_assertions)]
+    define_native(&globals, "scope_depth", 0, |interpreter, _, _| {
+        let depth = Environment::depth(&interpreter.environment);
+        Ok(Rc::new(RefCell::new(Object::Number(depth as f64))))
+    });
+    define_native(&globals, "eval", 1, |interpreter, paren, arguments| {
+        let source = match &*arguments[0].borrow() {
+            Object::String(source) => source.to_string(),
+            _ => {
+                return Err(RuntimeError::new(
+                    paren.clone(),
+                    "Argument must be a string.",
+                    None,
+                ))
+            }
+        };
+        interpreter.eval(paren, &source)
+    });
+    globals
+}
+
+fn define_native(globals: &Rc<RefCell<Environment>>, name: &str, arity: usize, native: NativeFn) {
+    globals.borrow_mut().define(
+        String::from(name),
+        Rc::new(RefCell::new(Object::NativeFunction(NativeFunction::new(
+            arity, native,
+        )))),
+    );
+}
+
+fn define_math(globals: &Rc<RefCell<Environment>>) {
+    for (name, value) in [("PI", std::f64::consts::PI), ("E", std::f64::consts::E)] {
         globals.borrow_mut().define(
-            String::from("clock"),
-            Rc::new(RefCell::new(Object::NativeFunction(NativeFunction::new(
-                0,
-                || {
-                    let start = SystemTime::now();
-                    let since_the_epoch = start
-                        .duration_since(UNIX_EPOCH)
-                        .expect("Time went backwards")
-                        .as_millis();
-                    println!("{:?}", since_the_epoch);
-                },
-            )))),
+            String::from(name),
+            Rc::new(RefCell::new(Object::Number(value))),
         );
+    }
+    define_native(globals, "pow", 2, |_, paren, arguments| {
+        let base = number_argument(paren, &arguments[0])?;
+        let exponent = number_argument(paren, &arguments[1])?;
+        Ok(Rc::new(RefCell::new(Object::Number(base.powf(exponent)))))
+    });
+    define_native(globals, "log", 1, |_, paren, arguments| {
+        math(paren, &arguments[0], f64::ln)
+    });
+    define_native(globals, "log10", 1, |_, paren, arguments| {
+        math(paren, &arguments[0], f64::log10)
+    });
+    define_native(globals, "exp", 1, |_, paren, arguments| {
+        math(paren, &arguments[0], f64::exp)
+    });
+    define_native(globals, "sin", 1, |_, paren, arguments| {
+        math(paren, &arguments[0], f64::sin)
+    });
+    define_native(globals, "cos", 1, |_, paren, arguments| {
+        math(paren, &arguments[0], f64::cos)
+    });
+    define_native(globals, "tan", 1, |_, paren, arguments| {
+        math(paren, &arguments[0], f64::tan)
+    });
+    define_native(globals, "approx_eq", 3, |_, paren, arguments| {
+        let a = number_argument(paren, &arguments[0])?;
+        let b = number_argument(paren, &arguments[1])?;
+        let epsilon = number_argument(paren, &arguments[2])?;
+        if epsilon.is_nan() || epsilon < 0.0 {
+            return Err(RuntimeError::new(
+                paren.clone(),
+                "Epsilon must not be negative.",
+                None,
+            ));
+        }
+        Ok(Rc::new(RefCell::new(Object::Bool(approx_eq(
+            a, b, epsilon,
+        )))))
+    });
+    // Rounds to the nearest value with `digits` decimals, ties to even. The
+    // tie is judged on the stored binary value, so 2.675, which is stored as
+    // slightly less, rounds down to "2.67".
+    define_native(globals, "to_fixed", 2, |_, paren, arguments| {
+        let n = number_argument(paren, &arguments[0])?;
+        let digits = number_argument(paren, &arguments[1])?;
+        if digits.fract() != 0.0 || !(0.0..=100.0).contains(&digits) {
+            return Err(RuntimeError::new(
+                paren.clone(),
+                "Digits must be an integer from 0 to 100.",
+                None,
+            ));
+        }
+        Ok(string(&format!("{:.*}", digits as usize, n)))
+    });
+}
+
+// 2^53, past which numbers can't hold every integer.
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+fn define_random(globals: &Rc<RefCell<Environment>>) {
+    define_native(globals, "seed", 1, |interpreter, paren, arguments| {
+        let seed = number_argument(paren, &arguments[0])?;
+        interpreter.rng = seed_state(seed);
+        Ok(Rc::new(RefCell::new(Object::Nil)))
+    });
+    define_native(globals, "random", 0, |interpreter, _, _| {
+        // 53 random bits fill a number's mantissa exactly.
+        let bits = interpreter.next_random() >> 11;
+        Ok(Rc::new(RefCell::new(Object::Number(
+            bits as f64 / (1u64 << 53) as f64,
+        ))))
+    });
+    define_native(globals, "random_int", 2, |interpreter, paren, arguments| {
+        let lo = number_argument(paren, &arguments[0])?;
+        let hi = number_argument(paren, &arguments[1])?;
+        if lo.fract() != 0.0 || hi.fract() != 0.0 || lo > hi {
+            return Err(RuntimeError::new(
+                paren.clone(),
+                "Bounds must be integers with the lower one first.",
+                None,
+            ));
+        }
+        // beyond 2^53 not every integer is a number, and the span could
+        // overflow
+        if lo < -MAX_SAFE_INTEGER || hi > MAX_SAFE_INTEGER {
+            return Err(RuntimeError::new(
+                paren.clone(),
+                "Bounds must be between -2^53 and 2^53.",
+                None,
+            ));
+        }
+        let (lo, hi) = (lo as i64, hi as i64);
+        let range = (hi - lo) as u64 + 1;
+        let n = lo + (interpreter.next_random() % range) as i64;
+        Ok(Rc::new(RefCell::new(Object::Number(n as f64))))
+    });
+}
+
+fn define_files(globals: &Rc<RefCell<Environment>>) {
+    define_native(globals, "read_file", 1, |interpreter, paren, arguments| {
+        let path = string_argument(paren, &arguments[0])?;
+        check_fs_allowed(interpreter, paren)?;
+        match interpreter.file_system.read(&path) {
+            Ok(contents) => Ok(string(&contents)),
+            Err(e) => Err(RuntimeError::new(
+                paren.clone(),
+                &format!("Could not read file '{}': {}", path, e),
+                None,
+            )),
+        }
+    });
+    define_native(globals, "write_file", 2, |interpreter, paren, arguments| {
+        let path = string_argument(paren, &arguments[0])?;
+        let contents = string_argument(paren, &arguments[1])?;
+        check_fs_allowed(interpreter, paren)?;
+        match interpreter.file_system.write(&path, &contents) {
+            Ok(()) => Ok(Rc::new(RefCell::new(Object::Nil))),
+            Err(e) => Err(RuntimeError::new(
+                paren.clone(),
+                &format!("Could not write file '{}': {}", path, e),
+                None,
+            )),
+        }
+    });
+}
+
+fn check_fs_allowed(interpreter: &Interpreter, paren: &Token) -> Result<(), RuntimeError> {
+    if interpreter.allow_fs {
+        Ok(())
+    } else {
+        Err(RuntimeError::new(
+            paren.clone(),
+            "File access is not allowed.",
+            None,
+        ))
+    }
+}
+
+// len() counts Unicode scalar values, while grapheme_len() counts what a
+// reader sees as characters. The emoji "👍🏽" is a thumbs up followed by a
+// skin tone modifier, so its len is 2 but its grapheme_len is 1.
+fn define_strings(globals: &Rc<RefCell<Environment>>) {
+    define_native(globals, "len", 1, |_, paren, arguments| {
+        let len = match &*arguments[0].borrow() {
+            Object::String(s) => s.chars().count(),
+            Object::Array(elements) => elements.len(),
+            Object::Map(entries) => entries.len(),
+            _ => {
+                return Err(RuntimeError::new(
+                    paren.clone(),
+                    "Argument must be a string, array or map.",
+                    None,
+                ))
+            }
+        };
+        Ok(Rc::new(RefCell::new(Object::Number(len as f64))))
+    });
+    #[cfg(feature = "graphemes")]
+    define_native(globals, "grapheme_len", 1, |_, paren, arguments| {
+        use unicode_segmentation::UnicodeSegmentation;
+        let s = string_argument(paren, &arguments[0])?;
+        let len = s.graphemes(true).count();
+        Ok(Rc::new(RefCell::new(Object::Number(len as f64))))
+    });
+    define_native(globals, "num", 1, |_, paren, arguments| {
+        let s = string_argument(paren, &arguments[0])?;
+        match parse_number(&s) {
+            Some(n) => Ok(Rc::new(RefCell::new(Object::Number(n)))),
+            None => Err(RuntimeError::new(
+                paren.clone(),
+                &format!("Cannot convert '{}' to a number.", s),
+                None,
+            )),
+        }
+    });
+    define_native(globals, "trim_start", 1, |_, paren, arguments| {
+        let s = string_argument(paren, &arguments[0])?;
+        Ok(string(s.trim_start()))
+    });
+    define_native(globals, "trim_end", 1, |_, paren, arguments| {
+        let s = string_argument(paren, &arguments[0])?;
+        Ok(string(s.trim_end()))
+    });
+    define_native(globals, "replace", 3, |_, paren, arguments| {
+        let s = string_argument(paren, &arguments[0])?;
+        let from = string_argument(paren, &arguments[1])?;
+        let to = string_argument(paren, &arguments[2])?;
+        // Rust would insert `to` between every character for an empty `from`.
+        if from.is_empty() {
+            return Ok(string(&s));
+        }
+        Ok(string(&s.replace(&*from, &to)))
+    });
+    define_native(globals, "contains", 2, |_, paren, arguments| {
+        let s = string_argument(paren, &arguments[0])?;
+        let sub = string_argument(paren, &arguments[1])?;
+        Ok(Rc::new(RefCell::new(Object::Bool(s.contains(&*sub)))))
+    });
+    define_native(globals, "matches", 2, |_, paren, arguments| {
+        let text = string_argument(paren, &arguments[0])?;
+        let pattern = string_argument(paren, &arguments[1])?;
+        let matched = glob_matches(&text, &pattern);
+        Ok(Rc::new(RefCell::new(Object::Bool(matched))))
+    });
+    define_native(globals, "pad_left", 2, |_, paren, arguments| {
+        pad(paren, &arguments[0], &arguments[1], ' ', true)
+    });
+    define_native(globals, "pad_right", 2, |_, paren, arguments| {
+        pad(paren, &arguments[0], &arguments[1], ' ', false)
+    });
+    define_native(globals, "pad_left_char", 3, |_, paren, arguments| {
+        let fill = string_argument(paren, &arguments[2])?;
+        let mut chars = fill.chars();
+        match (chars.next(), chars.next()) {
+            (Some(fill), None) => pad(paren, &arguments[0], &arguments[1], fill, true),
+            _ => Err(RuntimeError::new(
+                paren.clone(),
+                "Padding must be a single character.",
+                None,
+            )),
+        }
+    });
+}
+
+// sort() orders numbers or strings ascending. sort_by() takes a comparator
+// returning a negative number, zero or a positive number, as a - b would.
+// Both return a new array and keep equal elements in their original order.
+// min(), max() and sum() reduce a non-empty array of numbers.
+fn define_arrays(globals: &Rc<RefCell<Environment>>) {
+    define_native(globals, "min", 1, |_, paren, arguments| {
+        let numbers = numbers_argument(paren, &arguments[0])?;
+        let min = numbers.into_iter().reduce(f64::min).unwrap_or_default();
+        Ok(Rc::new(RefCell::new(Object::Number(min))))
+    });
+    define_native(globals, "max", 1, |_, paren, arguments| {
+        let numbers = numbers_argument(paren, &arguments[0])?;
+        let max = numbers.into_iter().reduce(f64::max).unwrap_or_default();
+        Ok(Rc::new(RefCell::new(Object::Number(max))))
+    });
+    define_native(globals, "sum", 1, |_, paren, arguments| {
+        let numbers = numbers_argument(paren, &arguments[0])?;
+        Ok(Rc::new(RefCell::new(Object::Number(numbers.iter().sum()))))
+    });
+    define_native(globals, "sort", 1, |_, paren, arguments| {
+        let elements = array_argument(paren, &arguments[0])?;
+        let sorted = merge_sort(elements, &mut |a, b| match (&*a.borrow(), &*b.borrow()) {
+            (Object::Number(a), Object::Number(b)) => Ok(a.total_cmp(b)),
+            (Object::String(a), Object::String(b)) => Ok(a.cmp(b)),
+            _ => Err(RuntimeError::new(
+                paren.clone(),
+                "Elements must all be numbers or all be strings to sort without a comparator.",
+                None,
+            )),
+        })?;
+        Ok(Rc::new(RefCell::new(Object::Array(sorted))))
+    });
+    define_native(globals, "sort_by", 2, |interpreter, paren, arguments| {
+        let elements = array_argument(paren, &arguments[0])?;
+        let compare = arguments[1].borrow().clone();
+        let sorted = merge_sort(elements, &mut |a, b| {
+            let order =
+                interpreter.call_value(&compare, paren, vec![Rc::clone(a), Rc::clone(b)])?;
+            let order = match &*order.borrow() {
+                Object::Number(n) => n.partial_cmp(&0.0).unwrap_or(Ordering::Equal),
+                _ => {
+                    return Err(RuntimeError::new(
+                        paren.clone(),
+                        "Comparator must return a number.",
+                        None,
+                    ))
+                }
+            };
+            Ok(order)
+        })?;
+        Ok(Rc::new(RefCell::new(Object::Array(sorted))))
+    });
+}
+
+fn array_argument(
+    paren: &Token,
+    argument: &Rc<RefCell<Object>>,
+) -> Result<Vec<Rc<RefCell<Object>>>, RuntimeError> {
+    match &*argument.borrow() {
+        Object::Array(elements) => Ok(elements.clone()),
+        _ => Err(RuntimeError::new(
+            paren.clone(),
+            "Argument must be an array.",
+            None,
+        )),
+    }
+}
+
+fn numbers_argument(
+    paren: &Token,
+    argument: &Rc<RefCell<Object>>,
+) -> Result<Vec<f64>, RuntimeError> {
+    let elements = array_argument(paren, argument)?;
+    if elements.is_empty() {
+        return Err(RuntimeError::new(
+            paren.clone(),
+            "Array must not be empty.",
+            None,
+        ));
+    }
+    elements
+        .iter()
+        .map(|element| match &*element.borrow() {
+            Object::Number(n) => Ok(*n),
+            _ => Err(RuntimeError::new(
+                paren.clone(),
+                "Array elements must be numbers.",
+                None,
+            )),
+        })
+        .collect()
+}
+
+type Comparator<'a> =
+    dyn FnMut(&Rc<RefCell<Object>>, &Rc<RefCell<Object>>) -> Result<Ordering, RuntimeError> + 'a;
+
+/// Sorts stably, stopping at the first error `compare` returns. Unlike
+/// `sort_by` in the standard library, a comparator that isn't a total order
+/// gives some order rather than a panic.
+fn merge_sort(
+    mut elements: Vec<Rc<RefCell<Object>>>,
+    compare: &mut Comparator,
+) -> Result<Vec<Rc<RefCell<Object>>>, RuntimeError> {
+    if elements.len() < 2 {
+        return Ok(elements);
+    }
+    let right = elements.split_off(elements.len() / 2);
+    let left = merge_sort(elements, compare)?;
+    let right = merge_sort(right, compare)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if compare(r, l)? == Ordering::Less {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+/// Pads a string with `fill` until it is `width` characters long, counting
+/// Unicode scalar values. Longer strings are returned unchanged.
+fn pad(
+    paren: &Token,
+    argument: &Rc<RefCell<Object>>,
+    width: &Rc<RefCell<Object>>,
+    fill: char,
+    left: bool,
+) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+    let s = string_argument(paren, argument)?;
+    let width = number_argument(paren, width)?;
+    if width.fract() != 0.0 || width < 0.0 {
+        return Err(RuntimeError::new(
+            paren.clone(),
+            "Width must be a non-negative integer.",
+            None,
+        ));
+    }
+    let padding: String =
+        std::iter::repeat_n(fill, (width as usize).saturating_sub(s.chars().count())).collect();
+    if left {
+        Ok(string(&format!("{padding}{s}")))
+    } else {
+        Ok(string(&format!("{s}{padding}")))
+    }
+}
+
+/// Parses a number written as in source, like `1_000` or `2.5`, allowing
+/// surrounding whitespace, a sign and an exponent such as `e3`. Forms Rust
+/// accepts but Lox doesn't, like `inf` or `.5`, are rejected.
+fn parse_number(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let unsigned = s.strip_prefix(['-', '+']).unwrap_or(s);
+    let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+        Some(i) => (&unsigned[..i], Some(&unsigned[i + 1..])),
+        None => (unsigned, None),
+    };
+    let (whole, fraction) = match mantissa.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (mantissa, None),
+    };
+    // digits may be separated by single underscores, as the scanner allows
+    let digits = |d: &str| {
+        !d.is_empty()
+            && d.split('_')
+                .all(|run| !run.is_empty() && run.chars().all(|c| c.is_ascii_digit()))
+    };
+    let exponent_ok = exponent.is_none_or(|e| digits(e.strip_prefix(['-', '+']).unwrap_or(e)));
+    if !digits(whole) || !fraction.is_none_or(digits) || !exponent_ok {
+        return None;
+    }
+    s.replace('_', "").parse().ok()
+}
+
+/// Returns whether the whole of `text` matches `pattern`, in which `*` stands
+/// for any run of characters and `?` for exactly one.
+fn glob_matches(text: &str, pattern: &str) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    let (mut t, mut p) = (0, 0);
+    // the last `*` seen, and where in the text it started matching
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            t += 1;
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // let the `*` take one more character and try again
+            star = Some((star_p, star_t + 1));
+            p = star_p + 1;
+            t = star_t + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn string_argument(paren: &Token, argument: &Rc<RefCell<Object>>) -> Result<Rc<str>, RuntimeError> {
+    match &*argument.borrow() {
+        Object::String(s) => Ok(Rc::clone(s)),
+        _ => Err(RuntimeError::new(
+            paren.clone(),
+            "Argument must be a string.",
+            None,
+        )),
+    }
+}
+
+fn string(s: &str) -> Rc<RefCell<Object>> {
+    Rc::new(RefCell::new(Object::String(Rc::from(s))))
+}
+
+fn seed_state(seed: f64) -> u64 {
+    // Xorshift never leaves a zero state, which only one seed would give.
+    match seed.to_bits() ^ 0x9E37_79B9_7F4A_7C15 {
+        0 => 0x9E37_79B9_7F4A_7C15,
+        state => state,
+    }
+}
+
+fn number_argument(paren: &Token, argument: &Rc<RefCell<Object>>) -> Result<f64, RuntimeError> {
+    match &*argument.borrow() {
+        Object::Number(n) => Ok(*n),
+        _ => Err(RuntimeError::new(
+            paren.clone(),
+            "Argument must be a number.",
+            None,
+        )),
+    }
+}
+
+fn math(
+    paren: &Token,
+    argument: &Rc<RefCell<Object>>,
+    function: fn(f64) -> f64,
+) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+    let n = number_argument(paren, argument)?;
+    Ok(Rc::new(RefCell::new(Object::Number(function(n)))))
+}
+
+fn unary(operator: &Token, obj: &Rc<RefCell<Object>>) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+    match operator.token_type {
+        TokenType::Bang => Ok(Rc::new(RefCell::new(Object::Bool(is_truthy(
+            &obj.borrow(),
+        ))))),
+        TokenType::Minus => match &*obj.borrow() {
+            Object::Number(n) => Ok(Rc::new(RefCell::new(Object::Number(-n)))),
+            _ => Err(RuntimeError::new(
+                operator.clone(),
+                "Operand must be a number",
+                None,
+            )),
+        },
+        _ => Ok(Rc::new(RefCell::new(Object::Nil))),
+    }
+}
+
+/// Turns a `return` that unwound all the way to the top level into an error,
+/// in case the resolver let one through.
+fn escaped_return(error: RuntimeError) -> RuntimeError {
+    match error.value {
+        Some(_) => RuntimeError::new(error.token, "Cannot return from top-level code.", None),
+        None => error,
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        let globals = global_environment();
         Interpreter {
             globals: Rc::clone(&globals),
             environment: Rc::clone(&globals),
             locals: HashMap::new(),
+            source: None,
+            rng: seed_state(0.0),
+            out: Box::new(io::stdout()),
+            err: Box::new(io::stderr()),
+            closed_over: ClosedOver::default(),
+            loader: Box::new(|path| fs::read_to_string(path).map_err(|e| e.to_string())),
+            yielded: None,
+            deferred: None,
+            verbose: false,
+            tolerance: None,
+            file_system: Box::new(OsFileSystem),
+            allow_fs: false,
+        }
+    }
+
+    /// Creates an interpreter that writes `print` output to `out` and runtime
+    /// errors to `err`.
+    pub fn with_writers(out: Box<dyn Write>, err: Box<dyn Write>) -> Self {
+        Interpreter {
+            out,
+            err,
+            ..Self::new()
+        }
+    }
+
+    /// Creates an interpreter that shows the offending line of `source`
+    /// when reporting a runtime error.
+    pub fn with_source(source: &str) -> Self {
+        Interpreter {
+            source: Some(String::from(source)),
+            ..Self::new()
+        }
+    }
+
+    /// Sets the source shown when reporting a runtime error, for an
+    /// interpreter that runs several sources in turn, like the REPL.
+    pub fn set_source(&mut self, source: &str) {
+        self.source = Some(String::from(source));
+    }
+
+    /// Sets how `import` reads modules, so hosts without a filesystem can
+    /// serve them from memory instead.
+    pub fn set_loader(&mut self, loader: ModuleLoader) {
+        self.loader = loader;
+    }
+
+    /// Sets whether scripts may call `read_file` and `write_file`. Off by
+    /// default, so a script can only touch files if its host allows it.
+    pub fn set_allow_fs(&mut self, allow: bool) {
+        self.allow_fs = allow;
+    }
+
+    /// Sets the files `read_file` and `write_file` see, e.g. an in-memory
+    /// filesystem for hosts without a real one.
+    pub fn set_file_system(&mut self, file_system: Box<dyn FileSystem>) {
+        self.file_system = file_system;
+    }
+
+    /// Sets whether `print` shows values as `Object::verbose` formats them,
+    /// which helps when debugging a script.
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    /// Takes a copy of the variables visible from the current scope, which
+    /// `restore` can later roll back to. See `Checkpoint` for what this costs.
+    pub fn checkpoint(&self) -> Checkpoint {
+        let mut scopes: Vec<Scope> = vec![];
+        let mut next = Some(Rc::clone(&self.environment));
+        while let Some(environment) = next {
+            next = environment.borrow().enclosing.clone();
+            let values = environment.borrow().values().clone();
+            scopes.push((environment, values));
+        }
+        if !scopes.iter().any(|(e, _)| Rc::ptr_eq(e, &self.globals)) {
+            let values = self.globals.borrow().values().clone();
+            scopes.push((Rc::clone(&self.globals), values));
+        }
+        Checkpoint {
+            environment: Rc::clone(&self.environment),
+            scopes: copy_scopes(&scopes),
+        }
+    }
+
+    /// Sets the variables saved in `checkpoint` back to the values they had
+    /// then, and returns to the scope it was taken in. Variables defined since
+    /// are removed. A checkpoint can be restored more than once.
+    pub fn restore(&mut self, checkpoint: &Checkpoint) {
+        for (environment, values) in copy_scopes(&checkpoint.scopes) {
+            environment.borrow_mut().set_values(values);
+        }
+        self.environment = Rc::clone(&checkpoint.environment);
+    }
+
+    /// Sets whether `==` and `!=` treat numbers within `DEFAULT_TOLERANCE` of
+    /// each other as equal, so that `0.1 + 0.2 == 0.3`. Off by default, when
+    /// numbers must be exactly equal.
+    pub fn set_tolerant_equality(&mut self, tolerant: bool) {
+        self.tolerance = tolerant.then_some(DEFAULT_TOLERANCE);
+    }
+
+    fn is_equal(&self, l_obj: Rc<RefCell<Object>>, r_obj: Rc<RefCell<Object>>) -> bool {
+        match (self.tolerance, &*l_obj.borrow(), &*r_obj.borrow()) {
+            (Some(epsilon), Object::Number(l), Object::Number(r)) => approx_eq(*l, *r, epsilon),
+            (_, l, r) => l == r,
+        }
+    }
+
+    /// Returns the writer that `print` output goes to.
+    pub fn out(&mut self) -> &mut dyn Write {
+        &mut *self.out
+    }
+
+    /// Returns the writer that errors go to.
+    pub fn err(&mut self) -> &mut dyn Write {
+        &mut *self.err
+    }
+
+    /// Forgets everything the program defined, leaving only the natives.
+    pub fn reset(&mut self) {
+        self.globals = global_environment();
+        self.environment = Rc::clone(&self.globals);
+        self.locals.clear();
+    }
+
+    /// Returns the value of the global `name`, e.g. to read a result out after
+    /// running a script.
+    pub fn get_global(&self, name: &str) -> Option<Rc<RefCell<Object>>> {
+        self.globals.borrow().lookup(name)
+    }
+
+    /// Defines the global `args` as an array of the script's command-line
+    /// arguments.
+    pub fn define_args(&mut self, args: &[String]) {
+        let args = args
+            .iter()
+            .map(|arg| Rc::new(RefCell::new(Object::String(Rc::from(arg.as_str())))))
+            .collect();
+        self.globals.borrow_mut().define(
+            String::from("args"),
+            Rc::new(RefCell::new(Object::Array(args))),
+        );
+    }
+
+    /// Runs `stmts`, stopping at the first error. Returns the value of the
+    /// final statement if it is an expression statement, and nil otherwise.
+    pub fn interpret(&mut self, stmts: &Vec<Stmt>) -> Result<Object, RuntimeError> {
+        let mut value = Object::Nil;
+        for stmt in stmts {
+            value = match stmt {
+                Stmt::Expr(e, _) => self.visit_expr(e)?.borrow().clone(),
+                _ => {
+                    self.visit_stmt(stmt).map_err(escaped_return)?;
+                    Object::Nil
+                }
+            };
         }
+        Ok(value)
     }
-    pub fn interpret(&mut self, stmts: &Vec<Stmt>) -> () {
+
+    /// Evaluates `expr` against the current state, e.g. a debugger's watch
+    /// expression parsed with `Parser::parse_expression`.
+    pub fn evaluate_expr(&mut self, expr: &Expr) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        self.visit_expr(expr)
+    }
+
+    /// Runs every statement in `stmts`, reporting errors to the error writer
+    /// and carrying on with the next statement. A statement that fails leaves
+    /// the interpreter in the scope it started in, so the REPL stays usable.
+    /// Returns whether every statement ran without an error.
+    pub fn interpret_all(&mut self, stmts: &Vec<Stmt>) -> bool {
+        let environment = Rc::clone(&self.environment);
+        let mut succeeded = true;
         for stmt in stmts {
-            let _ = self.visit_stmt(stmt);
+            if let Err(e) = self.visit_stmt(stmt).map_err(escaped_return) {
+                succeeded = false;
+                self.environment = Rc::clone(&environment);
+                self.yielded = None;
+                // so that output printed before the error comes before it
+                self.out.flush().expect("Failed to flush output");
+                writeln!(self.err, "{}", e.report()).expect("Failed to write error");
+                if let Some(snippet) = self.snippet(&e) {
+                    writeln!(self.err, "{}", snippet).expect("Failed to write error");
+                }
+            }
         }
+        self.out.flush().expect("Failed to flush output");
+        succeeded
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    /// Returns the source line `error` occurred on, if the source is known.
+    pub fn snippet(&self, error: &RuntimeError) -> Option<String> {
+        let source = self.source.as_ref()?;
+        Some(crate::snippet(source, &error.token))
     }
     pub fn interpret_block(
         &mut self,
@@ -147,7 +1390,7 @@ impl Interpreter {
         let previous = Rc::clone(&self.environment);
         self.environment = environment;
         for stmt in stmts {
-            let s = self.visit_stmt(&stmt);
+            let s = self.visit_stmt(stmt);
             if let Err(e) = s {
                 self.environment = previous;
                 return Err(e);
@@ -156,455 +1399,2502 @@ impl Interpreter {
         self.environment = previous;
         Ok(())
     }
-    pub fn resolve(&mut self, expr: &Expr, depth: usize) {
-        self.locals.insert(expr.clone(), depth);
-    }
-    fn look_up_variable(
+    /// Sets where `yield` collects values while a function runs, returning
+    /// where it collected them before.
+    pub(crate) fn collect_yields(
         &mut self,
-        name: &Token,
-        expr: &Expr,
-    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
-        println!("[INTERPRETER] look_up_variable token: {:?}", name);
-        println!("[INTERPRETER] look_up_variable expr: {:?}", expr);
-        println!("[INTERPRETER] self.locals: {:?}", self.locals);
+        yielded: Option<Vec<Rc<RefCell<Object>>>>,
+    ) -> Option<Vec<Rc<RefCell<Object>>>> {
+        mem::replace(&mut self.yielded, yielded)
+    }
 
-        let distance = self.locals.get(expr);
-        if let Some(d) = distance {
-            println!("[INTERPRETER] distance at {:?}", d);
-            return self.environment.borrow().get_at(*d, name.lexeme.clone());
-        } else {
-            self.globals.borrow().get(name.clone())
+    /// Starts collecting deferred expressions for a function call, returning
+    /// those of the call it was made from.
+    pub(crate) fn collect_deferred(&mut self) -> Option<Vec<(Expr, Rc<RefCell<Environment>>)>> {
+        self.deferred.replace(Vec::new())
+    }
+
+    /// Evaluates the expressions deferred during a call, last deferred first,
+    /// then goes back to collecting for `enclosing`. Each runs even if one
+    /// before it fails. The first failure replaces the call's result, unless
+    /// the body itself failed.
+    pub(crate) fn run_deferred(
+        &mut self,
+        enclosing: Option<Vec<(Expr, Rc<RefCell<Environment>>)>>,
+        mut result: Result<(), RuntimeError>,
+    ) -> Result<(), RuntimeError> {
+        let deferred = mem::replace(&mut self.deferred, enclosing).unwrap_or_default();
+        for (value, environment) in deferred.into_iter().rev() {
+            if let Err(e) = self.evaluate_in(&value, environment) {
+                // a `return` unwinds as an error carrying its value
+                if result.as_ref().err().is_none_or(|r| r.value.is_some()) {
+                    result = Err(e);
+                }
+            }
         }
+        result
     }
-}
 
-impl Visitor<Rc<RefCell<Object>>, ()> for Interpreter {
-    fn visit_expr(&mut self, e: &Expr) -> Result<Rc<RefCell<Object>>, RuntimeError> {
-        match e {
-            Expr::Assign { name, value } => {
-                let object = self.visit_expr(value)?;
-
-                println!("[INTERPRETER] Expr::Assign value {:?}", value);
-                println!("[INTERPRETER] self.locals {:?}", self.locals);
-                let distance = self.locals.get(e);
-                println!("[INTERPRETER] Expr::Assign distance {:?}", distance);
-                match distance {
-                    Some(d) => {
-                        self.environment.borrow_mut().assign_at(
-                            *d,
-                            name.clone(),
-                            Rc::clone(&object),
-                        );
-                    }
-                    None => {
-                        self.globals
-                            .borrow_mut()
-                            .assign(name.clone(), Rc::clone(&object))?;
-                    }
-                };
-
-                Ok(object)
-            }
-            Expr::Binary {
-                left,
-                operator,
-                right,
-            } => {
-                let left_obj = self.visit_expr(left)?;
-                let right_obj = self.visit_expr(right)?;
+    /// Creates a function closing over the current environment.
+    fn closure(&mut self, declaration: &Stmt) -> Function {
+        self.closed_over.track(&self.environment);
+        Function::new(declaration.clone(), Rc::clone(&self.environment))
+    }
 
-                match operator.token_type {
-                    TokenType::BangEqual => Ok(Rc::new(RefCell::new(Object::Bool(!is_equal(
-                        left_obj, right_obj,
-                    ))))),
-                    TokenType::EqualEqual => Ok(Rc::new(RefCell::new(Object::Bool(is_equal(
-                        left_obj, right_obj,
-                    ))))),
-                    TokenType::Greater => match (&*left_obj.borrow(), &*right_obj.borrow()) {
-                        (Object::Number(l), Object::Number(r)) => {
-                            Ok(Rc::new(RefCell::new(Object::Bool(l > r))))
-                        }
-                        (_, _) => Err(RuntimeError::new(
-                            operator.clone(),
-                            "Operands must be numbers.",
-                            None,
-                        )),
-                    },
-                    TokenType::GreaterEqual => match (&*left_obj.borrow(), &*right_obj.borrow()) {
-                        (Object::Number(l), Object::Number(r)) => {
-                            Ok(Rc::new(RefCell::new(Object::Bool(l >= r))))
-                        }
-                        (_, _) => Err(RuntimeError::new(
-                            operator.clone(),
-                            "Operands must be numbers.",
-                            None,
-                        )),
-                    },
-                    TokenType::Less => match (&*left_obj.borrow(), &*right_obj.borrow()) {
-                        (Object::Number(l), Object::Number(r)) => {
-                            Ok(Rc::new(RefCell::new(Object::Bool(l < r))))
-                        }
-                        (_, _) => Err(RuntimeError::new(
-                            operator.clone(),
-                            "Operands must be numbers.",
-                            None,
-                        )),
-                    },
-                    TokenType::LessEqual => match (&*left_obj.borrow(), &*right_obj.borrow()) {
-                        (Object::Number(l), Object::Number(r)) => {
-                            Ok(Rc::new(RefCell::new(Object::Bool(l <= r))))
-                        }
-                        (_, _) => Err(RuntimeError::new(
-                            operator.clone(),
-                            "Operands must be numbers.",
-                            None,
-                        )),
-                    },
-                    TokenType::Minus => match (&*left_obj.borrow(), &*right_obj.borrow()) {
-                        (Object::Number(l), Object::Number(r)) => {
-                            Ok(Rc::new(RefCell::new(Object::Number(l - r))))
-                        }
-                        (_, _) => Err(RuntimeError::new(
-                            operator.clone(),
-                            "Operands must be numbers.",
-                            None,
-                        )),
-                    },
-                    TokenType::Plus => match (&*left_obj.borrow(), &*right_obj.borrow()) {
-                        (Object::Number(l), Object::Number(r)) => {
-                            Ok(Rc::new(RefCell::new(Object::Number(l + r))))
-                        }
-                        (Object::String(l), Object::String(r)) => {
-                            Ok(Rc::new(RefCell::new(Object::String(l.to_owned() + r))))
-                        }
-                        (_, _) => Err(RuntimeError::new(
-                            operator.clone(),
-                            "Operands must be two numbers or two strings.",
-                            None,
-                        )),
-                    },
-                    TokenType::Slash => match (&*left_obj.borrow(), &*right_obj.borrow()) {
-                        (Object::Number(l), Object::Number(r)) => {
-                            Ok(Rc::new(RefCell::new(Object::Number(l / r))))
-                        }
-                        (_, _) => Err(RuntimeError::new(
-                            operator.clone(),
-                            "Operands must be numbers.",
-                            None,
-                        )),
-                    },
-                    TokenType::Star => match (&*left_obj.borrow(), &*right_obj.borrow()) {
-                        (Object::Number(l), Object::Number(r)) => {
-                            Ok(Rc::new(RefCell::new(Object::Number(l * r))))
-                        }
-                        (_, _) => Err(RuntimeError::new(
-                            operator.clone(),
-                            "Operands must be numbers.",
-                            None,
-                        )),
-                    },
-                    _ => Err(RuntimeError::new(
-                        operator.clone(),
-                        "Invalid use of operator.",
-                        None,
-                    )),
-                }
-            }
-            Expr::Call {
-                callee: c,
-                paren: p,
-                arguments: a,
-            } => {
-                let callee = self.visit_expr(c)?;
+    // Field defaults close over the environment the class is declared in.
+    fn with_fields(
+        &mut self,
+        klass: Class,
+        fields: &[(Token, Expr)],
+        environment: Rc<RefCell<Environment>>,
+    ) -> Class {
+        if fields.is_empty() {
+            return klass;
+        }
+        self.closed_over.track(&environment);
+        klass.with_fields(fields.to_vec(), environment)
+    }
 
-                let mut arguments = vec![];
-                for argument in a {
-                    arguments.push(self.visit_expr(argument)?)
-                }
+    /// Evaluates `expr` in `environment`, restoring the current one after.
+    pub(crate) fn evaluate_in(
+        &mut self,
+        expr: &Expr,
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let globals = mem::replace(&mut self.globals, Environment::root(&environment));
+        let previous = mem::replace(&mut self.environment, environment);
+        let result = self.visit_expr(expr);
+        self.environment = previous;
+        self.globals = globals;
+        result
+    }
 
-                let x = match &*callee.borrow() {
-                    Object::Function(func) => {
-                        if arguments.len() != func.arity() {
-                            return Err(RuntimeError::new(
-                                p.clone(),
-                                &("Expected ".to_owned()
-                                    + &func.arity().to_string()
-                                    + " arguments but got "
-                                    + &arguments.len().to_string()
-                                    + "."),
-                                None,
-                            ));
-                        }
-                        func.call(self, arguments)
-                    }
-                    Object::NativeFunction(func) => {
-                        if arguments.len() != func.arity() {
-                            return Err(RuntimeError::new(
-                                p.clone(),
-                                &("Expected ".to_owned()
-                                    + &func.arity().to_string()
-                                    + " arguments but got "
-                                    + &arguments.len().to_string()
-                                    + "."),
-                                None,
-                            ));
-                        }
-                        func.call(self, arguments)
-                    }
-                    Object::Class(class) => {
-                        if arguments.len() != class.arity() {
-                            return Err(RuntimeError::new(
-                                p.clone(),
-                                &("Expected ".to_owned()
-                                    + &class.arity().to_string()
-                                    + " arguments but got "
-                                    + &arguments.len().to_string()
-                                    + "."),
-                                None,
-                            ));
-                        }
-                        class.call(self, arguments)
-                    }
-                    _ => Err(RuntimeError::new(
-                        p.clone(),
-                        "Can only call functions and classes",
-                        None,
-                    )),
-                };
-                return x;
+    fn functions(&mut self, declarations: &Vec<Stmt>) -> HashMap<String, Function> {
+        let mut functions = HashMap::new();
+        for declaration in declarations {
+            let function = self.closure(declaration);
+            if let Stmt::Function { name, .. } = declaration {
+                functions.insert(name.lexeme.clone(), function);
             }
-            Expr::Get { object, name } => {
-                let object = self.visit_expr(&object)?;
-                if let Object::Instance(i) = &*object.borrow() {
-                    return Ok(i.get(name)?);
+        }
+        functions
+    }
+    /// Runs `source` as if its top level were the current scope, returning
+    /// the value of its final expression, or nil if it ends in a statement.
+    fn eval(&mut self, paren: &Token, source: &str) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let eval_error = |message: &str| {
+            RuntimeError::new(paren.clone(), &format!("Error in eval: {}", message), None)
+        };
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let parsed = if parser.ends_with_statement() {
+            parser.parse().map(|mut stmts| match stmts.pop() {
+                Some(Stmt::Expr(expr, _)) => (stmts, Some(expr)),
+                Some(stmt) => {
+                    stmts.push(stmt);
+                    (stmts, None)
                 }
-                Err(RuntimeError::new(
-                    name.clone(),
-                    "Only instances have properties.",
-                    None,
-                ))
-            }
-            Expr::Grouping { expression } => self.visit_expr(expression),
-            Expr::Literal { value } => Ok(Rc::new(RefCell::new(value.clone()))),
-            Expr::Logical {
-                left,
-                operator,
-                right,
-            } => {
-                let left = self.visit_expr(left)?;
+                None => (stmts, None),
+            })
+        } else {
+            parser.parse_expression().map(|expr| (vec![], Some(expr)))
+        };
+        let (stmts, last) = parsed.map_err(|e| eval_error(&e.message))?;
 
-                if operator.token_type == TokenType::Or {
-                    if is_truthy(&*left.borrow()) {
-                        return Ok(left);
-                    }
-                } else {
-                    if !is_truthy(&*left.borrow()) {
-                        return Ok(left);
-                    }
-                }
-                self.visit_expr(right)
-            }
-            Expr::Set {
-                object,
-                name,
-                value,
-            } => {
-                let object = self.visit_expr(&object)?;
-                if let Object::Instance(i) = &mut *object.borrow_mut() {
-                    let value = self.visit_expr(value)?;
-                    i.set(name, Rc::clone(&value));
-                    return Ok(value);
-                }
-                Err(RuntimeError::new(
-                    name.clone(),
-                    "Only instances have fields.",
-                    None,
-                ))
-            }
-            Expr::This { keyword } => self.look_up_variable(keyword, e),
+        let mut resolver = Resolver::new(mem::take(self));
+        let resolved = resolver.resolve_stmts(&stmts).and_then(|_| match &last {
+            Some(expr) => resolver.visit_expr(expr),
+            None => Ok(()),
+        });
+        *self = resolver.interpreter;
+        resolved.map_err(|e| eval_error(&e.message))?;
 
-            Expr::Unary { operator, right } => {
-                let obj = self.visit_expr(right)?;
-                match operator.token_type {
-                    TokenType::Bang => Ok(Rc::new(RefCell::new(Object::Bool(is_truthy(
-                        &*obj.borrow(),
-                    ))))),
-                    TokenType::Minus => match &*obj.borrow() {
-                        Object::Number(n) => Ok(Rc::new(RefCell::new(Object::Number(-n)))),
-                        _ => Err(RuntimeError::new(
-                            operator.clone(),
-                            "Operand must be a number",
-                            None,
-                        )),
-                    },
-                    _ => Ok(Rc::new(RefCell::new(Object::Nil))),
-                }
-            }
-            Expr::Variable { name } => self.look_up_variable(name, e),
+        // Names left unresolved are looked up starting from the current scope.
+        let globals = mem::replace(&mut self.globals, Rc::clone(&self.environment));
+        let result = self.eval_stmts(&stmts, last.as_ref());
+        self.globals = globals;
+        result.map_err(|e| eval_error(&e.message))
+    }
+
+    fn eval_stmts(
+        &mut self,
+        stmts: &Vec<Stmt>,
+        last: Option<&Expr>,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        for stmt in stmts {
+            self.visit_stmt(stmt)?;
+        }
+        match last {
+            Some(expr) => self.visit_expr(expr),
+            None => Ok(Rc::new(RefCell::new(Object::Nil))),
         }
     }
-    fn visit_stmt(&mut self, s: &Stmt) -> Result<(), RuntimeError> {
-        match s {
-            Stmt::Expr(e) => {
-                self.visit_expr(e)?;
-            }
-            Stmt::If {
-                condition,
-                then_branch,
-                else_branch,
-            } => {
-                if is_truthy(&*self.visit_expr(condition)?.borrow()) {
-                    self.visit_stmt(&then_branch)?;
-                } else {
-                    match else_branch {
-                        Some(s) => {
-                            self.visit_stmt(s)?;
-                        }
-                        None => {}
-                    }
+
+    /// Runs the module at `path` in its own globals and returns a namespace
+    /// holding its public top-level declarations.
+    fn import(
+        &mut self,
+        keyword: &Token,
+        path: &Token,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let file = match &path.literal {
+            Some(Object::String(file)) => file.to_string(),
+            _ => path.lexeme.clone(),
+        };
+        let source = (self.loader)(&file).map_err(|e| {
+            RuntimeError::new(
+                keyword.clone(),
+                &format!("Could not read module '{}': {}", file, e),
+                None,
+            )
+        })?;
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().map_err(|_| {
+            RuntimeError::new(
+                keyword.clone(),
+                &format!("Could not parse module '{}'.", file),
+                None,
+            )
+        })?;
+        let mut resolver = Resolver::new(mem::take(self));
+        let resolved = resolver.resolve_stmts(&stmts);
+        *self = resolver.interpreter;
+        resolved?;
+
+        let module = global_environment();
+        let globals = mem::replace(&mut self.globals, Rc::clone(&module));
+        let result = self.interpret_block(&stmts, Rc::clone(&module));
+        self.globals = globals;
+        result?;
+
+        let mut namespace = Instance::new(Rc::new(Class::new(
+            file,
+            None,
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+        )));
+        for stmt in &stmts {
+            let name = match stmt {
+                Stmt::Class { name, .. }
+                | Stmt::Enum { name, .. }
+                | Stmt::Function { name, .. }
+                | Stmt::Var { name, .. } => name,
+                _ => continue,
+            };
+            let value = module.borrow().get(name.clone())?;
+            namespace.set(name, value);
+        }
+        Ok(Rc::new(RefCell::new(Object::Instance(namespace))))
+    }
+    /// Runs `body` while `condition` holds, evaluating `increment` after each
+    /// iteration, including ones cut short by `continue`. A `break` or
+    /// `continue` naming another loop's label is passed on to that loop.
+    fn run_loop(
+        &mut self,
+        label: &Option<Token>,
+        condition: Option<&Expr>,
+        body: &Stmt,
+        increment: Option<&Expr>,
+    ) -> Result<(), RuntimeError> {
+        loop {
+            if let Some(c) = condition {
+                if !is_truthy(&self.visit_expr(c)?.borrow()) {
+                    break;
                 }
             }
-            Stmt::Print(e) => {
-                let obj = self.visit_expr(e)?;
-                println!("{}", obj.borrow());
-            }
-            Stmt::Return { keyword, value } => {
-                let ret = self.visit_expr(value);
-                match ret {
-                    Ok(o) => {
-                        return Err(RuntimeError::new(keyword.clone(), "", Some(Rc::clone(&o))));
-                    }
-                    Err(e) if e.value.is_some() => {
-                        return Err(e);
-                    }
-                    Err(e) => return Err(e),
+            if let Err(e) = self.visit_stmt(body) {
+                let jump = matches!(e.token.token_type, TokenType::Break | TokenType::Continue);
+                let targets_this_loop = match &e.label {
+                    Some(target) => label.as_ref().is_some_and(|l| &l.lexeme == target),
+                    None => true,
+                };
+                if !jump || !targets_this_loop {
+                    return Err(e);
                 }
-            }
-            Stmt::Var { name, initializer } => {
-                let mut value = Rc::new(RefCell::new(Object::Nil));
-                match initializer {
-                    Some(i) => {
-                        value = self.visit_expr(i)?;
-                    }
-                    None => {}
+                if e.token.token_type == TokenType::Break {
+                    break;
                 }
-                self.environment
-                    .borrow_mut()
-                    .define(name.lexeme.clone(), value);
             }
-            Stmt::Block { statements } => {
-                self.interpret_block(
-                    statements,
-                    Rc::new(RefCell::new(Environment::new(Some(Rc::clone(
-                        &(self.environment),
-                    ))))),
-                )?;
+            if let Some(i) = increment {
+                self.visit_expr(i)?;
             }
-            Stmt::Class {
-                name,
-                methods: stmt_methods,
-            } => {
-                self.environment
-                    .borrow_mut()
-                    .define(name.lexeme.clone(), Rc::new(RefCell::new(Object::Nil)));
-                let mut methods = HashMap::new();
-                for method in stmt_methods {
-                    let function = Function::new(method.clone(), Rc::clone(&self.environment));
-                    if let Stmt::Function {
-                        name,
-                        params: _,
-                        body: _,
-                    } = method
-                    {
-                        methods.insert(name.lexeme.clone(), function);
-                    }
-                }
+        }
+        Ok(())
+    }
 
-                let klass = Rc::new(RefCell::new(Object::Class(Class::new(
-                    name.lexeme.clone(),
-                    methods,
-                ))));
-                self.environment.borrow_mut().assign(name.clone(), klass)?;
-            }
-            Stmt::While { condition, body } => {
-                while is_truthy(&self.visit_expr(condition)?.borrow()) {
-                    self.visit_stmt(body)?;
-                }
-            }
-            Stmt::Function {
-                name,
-                params: _,
-                body: _,
-            } => {
-                let function = Function::new(s.clone(), Rc::clone(&self.environment));
-                self.environment.borrow_mut().define(
-                    name.lexeme.clone(),
-                    Rc::new(RefCell::new(Object::Function(Box::new(function)))),
-                );
+    /// Converts an instance added to a string with its `toString` method, so
+    /// `"user: " + user` concatenates. Other operands are returned unchanged.
+    fn concat_operand(
+        &mut self,
+        operand: &Rc<RefCell<Object>>,
+        other: &Rc<RefCell<Object>>,
+        operator: &Token,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let instance = match (&*operand.borrow(), &*other.borrow()) {
+            (Object::Instance(instance), Object::String(_)) => instance.clone(),
+            _ => return Ok(Rc::clone(operand)),
+        };
+        let method = match instance.method("toString") {
+            Some(method) if method.arity() == 0 => method,
+            _ => return Ok(Rc::clone(operand)),
+        };
+        let string = method.call(self, operator, vec![])?;
+        if !matches!(&*string.borrow(), Object::String(_)) {
+            return Err(RuntimeError::new(
+                operator.clone(),
+                "'toString' must return a string.",
+                None,
+            ));
+        }
+        Ok(string)
+    }
+
+    /// Calls the `getItem` or `setItem` method that indexing an instance
+    /// dispatches to.
+    fn call_item_method(
+        &mut self,
+        instance: &Instance,
+        bracket: &Token,
+        name: &str,
+        arguments: Vec<Rc<RefCell<Object>>>,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        match instance.method(name) {
+            Some(method) if method.arity() == arguments.len() => {
+                method.call(self, bracket, arguments)
             }
+            Some(_) => Err(RuntimeError::new(
+                bracket.clone(),
+                &format!("'{}' must take {} parameters.", name, arguments.len()),
+                None,
+            )),
+            None => Err(RuntimeError::new(
+                bracket.clone(),
+                &format!("Indexing an instance requires a '{}' method.", name),
+                None,
+            )),
+        }
+    }
 
-            _ => {}
+    /// Calls `callee` with `arguments`, after checking it is callable and
+    /// takes that many.
+    pub(crate) fn call_value(
+        &mut self,
+        callee: &Object,
+        paren: &Token,
+        arguments: Vec<Rc<RefCell<Object>>>,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let Some(arity) = callee.arity() else {
+            return Err(RuntimeError::new(
+                paren.clone(),
+                "Can only call functions and classes",
+                None,
+            ));
         };
-        Ok(())
+        if arguments.len() != arity {
+            return Err(RuntimeError::new(
+                paren.clone(),
+                &("Expected ".to_owned()
+                    + &arity.to_string()
+                    + " arguments but got "
+                    + &arguments.len().to_string()
+                    + "."),
+                None,
+            ));
+        }
+        match callee {
+            Object::Function(func) => func.call(self, paren, arguments),
+            Object::NativeFunction(func) => func.call(self, paren, arguments),
+            Object::Class(class) => class.call(self, paren, arguments),
+            Object::Partial(partial) => partial.call(self, paren, arguments),
+            _ => unreachable!(),
+        }
     }
-}
 
-fn is_truthy(obj: &Object) -> bool {
-    match obj {
-        Object::Nil => false,
-        Object::Bool(b) => *b,
-        _ => true,
+    // The arms of `visit_expr` that take more than a line. Its frame is on
+    // the stack once for every level of nesting, so it only dispatches.
+    fn array(&mut self, elements: &[Expr]) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let mut values = vec![];
+        for element in elements {
+            values.push(self.visit_expr(element)?);
+        }
+        Ok(Rc::new(RefCell::new(Object::Array(values))))
     }
-}
 
-fn is_equal(l_obj: Rc<RefCell<Object>>, r_obj: Rc<RefCell<Object>>) -> bool {
-    match (&*l_obj.borrow(), &*r_obj.borrow()) {
-        (Object::Number(l), Object::Number(r)) => l == r,
-        (Object::String(l), Object::String(r)) => l == r,
-        (Object::Bool(l), Object::Bool(r)) => l == r,
-        (Object::Nil, Object::Nil) => true,
-        (_, _) => false,
+    fn assign(
+        &mut self,
+        id: ExprId,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let object = self.visit_expr(value)?;
+        let distance = self.locals.get(&id);
+        match distance {
+            Some(d) => {
+                self.environment
+                    .borrow_mut()
+                    .assign_at(*d, name.clone(), Rc::clone(&object));
+            }
+            None => {
+                self.globals
+                    .borrow_mut()
+                    .assign(name.clone(), Rc::clone(&object))?;
+            }
+        };
+
+        Ok(object)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::token::{Token, TokenType};
+    fn call(
+        &mut self,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Expr],
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let callee = self.visit_expr(callee)?;
 
-    #[test]
-    fn unary() {
-        let mut interpreter = Interpreter::new();
-        let unary_expression = Expr::Unary {
-            operator: Token {
-                token_type: TokenType::Minus,
-                lexeme: String::from("-"),
-                literal: None,
-                line: 0,
-            },
-            right: Box::new(Expr::Literal {
-                value: Object::Number(1.0),
-            }),
-        };
-        match interpreter.visit_expr(&unary_expression) {
-            Ok(r) => assert_eq!(*r.borrow(), Object::Number(-1.0)),
-            Err(_) => panic!(),
+        let mut values = vec![];
+        for argument in arguments {
+            values.push(self.visit_expr(argument)?)
         }
+
+        let x = self.call_value(&callee.borrow(), paren, values);
+        x
     }
 
-    #[test]
-    fn assignment() {
-        let mut _interpreter = Interpreter::new();
-        let _assignment_expression = Expr::Assign {
-            name: Token {
-                token_type: TokenType::Identifier,
-                lexeme: String::from("a"),
-                literal: None,
-                line: 0,
+    fn block(
+        &mut self,
+        statements: &[Stmt],
+        value: &Expr,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let environment = Rc::new(RefCell::new(Environment::new(Some(Rc::clone(
+            &self.environment,
+        )))));
+        let previous = mem::replace(&mut self.environment, environment);
+        let result = statements
+            .iter()
+            .try_for_each(|statement| self.visit_stmt(statement))
+            .and_then(|_| self.visit_expr(value));
+        self.environment = previous;
+        result
+    }
+
+    fn class(
+        &mut self,
+        fields: &[(Token, Expr)],
+        methods: &Vec<Stmt>,
+        getters: &Vec<Stmt>,
+        setters: &Vec<Stmt>,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let klass = Class::new(
+            String::from("<anonymous class>"),
+            None,
+            self.functions(methods),
+            self.functions(getters),
+            self.functions(setters),
+        );
+        let klass = self.with_fields(klass, fields, Rc::clone(&self.environment));
+        Ok(Rc::new(RefCell::new(Object::Class(Rc::new(klass)))))
+    }
+
+    fn comma(&mut self, expressions: &[Expr]) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let mut value = Rc::new(RefCell::new(Object::Nil));
+        for expression in expressions {
+            value = self.visit_expr(expression)?;
+        }
+        Ok(value)
+    }
+
+    fn logical(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let left = self.visit_expr(left)?;
+
+        let short_circuit = match operator.token_type {
+            TokenType::Or => is_truthy(&left.borrow()),
+            TokenType::QuestionQuestion => !matches!(*left.borrow(), Object::Nil),
+            _ => !is_truthy(&left.borrow()),
+        };
+        if short_circuit {
+            return Ok(left);
+        }
+        self.visit_expr(right)
+    }
+
+    fn map(
+        &mut self,
+        brace: &Token,
+        entries: &[(Expr, Expr)],
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let mut values = HashMap::new();
+        for (key, value) in entries {
+            let key = self.visit_expr(key)?;
+            let key = map_key(brace, &key.borrow())?;
+            values.insert(key, self.visit_expr(value)?);
+        }
+        Ok(Rc::new(RefCell::new(Object::Map(values))))
+    }
+
+    fn set_property(
+        &mut self,
+        object: &Expr,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let object = self.visit_expr(object)?;
+        let mut instance = match &*object.borrow() {
+            Object::Instance(i) => i.clone(),
+            _ => {
+                return Err(RuntimeError::new(
+                    name.clone(),
+                    "Only instances have fields.",
+                    None,
+                ))
+            }
+        };
+        let value = self.visit_expr(value)?;
+        match instance.setter(name) {
+            Some(setter) => {
+                setter.call(self, name, vec![Rc::clone(&value)])?;
+            }
+            None => instance.set(name, Rc::clone(&value)),
+        }
+        Ok(value)
+    }
+
+    fn binary(
+        &mut self,
+        operator: &Token,
+        left_obj: Rc<RefCell<Object>>,
+        right_obj: Rc<RefCell<Object>>,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        match operator.token_type {
+            TokenType::BangEqual => Ok(Rc::new(RefCell::new(Object::Bool(
+                !self.is_equal(left_obj, right_obj),
+            )))),
+            TokenType::EqualEqual => Ok(Rc::new(RefCell::new(Object::Bool(
+                self.is_equal(left_obj, right_obj),
+            )))),
+            TokenType::Greater => match (&*left_obj.borrow(), &*right_obj.borrow()) {
+                (Object::Number(l), Object::Number(r)) => {
+                    Ok(Rc::new(RefCell::new(Object::Bool(l > r))))
+                }
+                (_, _) => Err(RuntimeError::new(
+                    operator.clone(),
+                    "Operands must be numbers.",
+                    None,
+                )),
             },
-            value: Box::new(Expr::Literal {
-                value: Object::Number(1.0),
-            }),
+            TokenType::GreaterEqual => match (&*left_obj.borrow(), &*right_obj.borrow()) {
+                (Object::Number(l), Object::Number(r)) => {
+                    Ok(Rc::new(RefCell::new(Object::Bool(l >= r))))
+                }
+                (_, _) => Err(RuntimeError::new(
+                    operator.clone(),
+                    "Operands must be numbers.",
+                    None,
+                )),
+            },
+            TokenType::Less => match (&*left_obj.borrow(), &*right_obj.borrow()) {
+                (Object::Number(l), Object::Number(r)) => {
+                    Ok(Rc::new(RefCell::new(Object::Bool(l < r))))
+                }
+                (_, _) => Err(RuntimeError::new(
+                    operator.clone(),
+                    "Operands must be numbers.",
+                    None,
+                )),
+            },
+            TokenType::LessEqual => match (&*left_obj.borrow(), &*right_obj.borrow()) {
+                (Object::Number(l), Object::Number(r)) => {
+                    Ok(Rc::new(RefCell::new(Object::Bool(l <= r))))
+                }
+                (_, _) => Err(RuntimeError::new(
+                    operator.clone(),
+                    "Operands must be numbers.",
+                    None,
+                )),
+            },
+            TokenType::Minus => match (&*left_obj.borrow(), &*right_obj.borrow()) {
+                (Object::Number(l), Object::Number(r)) => {
+                    Ok(Rc::new(RefCell::new(Object::Number(l - r))))
+                }
+                (_, _) => Err(RuntimeError::new(
+                    operator.clone(),
+                    "Operands must be numbers.",
+                    None,
+                )),
+            },
+            TokenType::Plus => {
+                let left_obj = self.concat_operand(&left_obj, &right_obj, operator)?;
+                let right_obj = self.concat_operand(&right_obj, &left_obj, operator)?;
+                let sum = match (&*left_obj.borrow(), &*right_obj.borrow()) {
+                    (Object::Number(l), Object::Number(r)) => {
+                        Ok(Rc::new(RefCell::new(Object::Number(l + r))))
+                    }
+                    (Object::String(l), Object::String(r)) => Ok(Rc::new(RefCell::new(
+                        Object::String(Rc::from(format!("{l}{r}"))),
+                    ))),
+                    (_, _) => Err(RuntimeError::new(
+                        operator.clone(),
+                        "Operands must be two numbers or two strings.",
+                        None,
+                    )),
+                };
+                sum
+            }
+            TokenType::Slash => match (&*left_obj.borrow(), &*right_obj.borrow()) {
+                (Object::Number(l), Object::Number(r)) => {
+                    Ok(Rc::new(RefCell::new(Object::Number(l / r))))
+                }
+                (_, _) => Err(RuntimeError::new(
+                    operator.clone(),
+                    "Operands must be numbers.",
+                    None,
+                )),
+            },
+            TokenType::Star => match (&*left_obj.borrow(), &*right_obj.borrow()) {
+                (Object::Number(l), Object::Number(r)) => {
+                    Ok(Rc::new(RefCell::new(Object::Number(l * r))))
+                }
+                (_, _) => Err(RuntimeError::new(
+                    operator.clone(),
+                    "Operands must be numbers.",
+                    None,
+                )),
+            },
+            _ => Err(RuntimeError::new(
+                operator.clone(),
+                "Invalid use of operator.",
+                None,
+            )),
+        }
+    }
+
+    fn get_property(
+        &mut self,
+        object: &Rc<RefCell<Object>>,
+        name: &Token,
+        optional: bool,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let instance = match &*object.borrow() {
+            Object::Instance(i) => i.clone(),
+            Object::Enum(e) => return e.get(name),
+            Object::Nil if optional => return Ok(Rc::new(RefCell::new(Object::Nil))),
+            Object::Nil => {
+                return Err(RuntimeError::new(
+                    name.clone(),
+                    &format!("Cannot read property '{}' of nil.", name.lexeme),
+                    None,
+                ))
+            }
+            _ => {
+                return Err(RuntimeError::new(
+                    name.clone(),
+                    &format!(
+                        "Only instances have properties, so '{}' can't be read.",
+                        name.lexeme
+                    ),
+                    None,
+                ))
+            }
+        };
+        match instance.getter(name) {
+            Some(getter) => getter.call(self, name, vec![]),
+            None => instance.get(name),
+        }
+    }
+
+    fn index(
+        &mut self,
+        bracket: &Token,
+        object: Rc<RefCell<Object>>,
+        index: Rc<RefCell<Object>>,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        if let Object::Instance(instance) = &*object.borrow() {
+            let instance = instance.clone();
+            return self.call_item_method(&instance, bracket, "getItem", vec![index]);
+        }
+        let index = &*index.borrow();
+        let value = match &*object.borrow() {
+            Object::Array(elements) => {
+                let i = array_index(bracket, index, elements.len())?;
+                Ok(Rc::clone(&elements[i]))
+            }
+            Object::Map(entries) => match entries.get(index) {
+                Some(value) => Ok(Rc::clone(value)),
+                None => Ok(Rc::new(RefCell::new(Object::Nil))),
+            },
+            _ => Err(RuntimeError::new(
+                bracket.clone(),
+                "Only arrays and maps can be indexed.",
+                None,
+            )),
+        };
+        value
+    }
+
+    fn set_index(
+        &mut self,
+        bracket: &Token,
+        object: Rc<RefCell<Object>>,
+        index: Rc<RefCell<Object>>,
+        value: Rc<RefCell<Object>>,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        if let Object::Instance(instance) = &*object.borrow() {
+            let instance = instance.clone();
+            let arguments = vec![index, Rc::clone(&value)];
+            self.call_item_method(&instance, bracket, "setItem", arguments)?;
+            return Ok(value);
+        }
+        // copied out first, since the index may be the container itself
+        let index = index.borrow().clone();
+        match &mut *object.borrow_mut() {
+            Object::Array(elements) => {
+                let i = array_index(bracket, &index, elements.len())?;
+                elements[i] = Rc::clone(&value);
+            }
+            Object::Map(entries) => {
+                let key = map_key(bracket, &index)?;
+                entries.insert(key, Rc::clone(&value));
+            }
+            _ => {
+                return Err(RuntimeError::new(
+                    bracket.clone(),
+                    "Only arrays and maps can be indexed.",
+                    None,
+                ))
+            }
+        }
+        Ok(value)
+    }
+
+    fn super_method(
+        &mut self,
+        id: ExprId,
+        method: &Token,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let distance = *self.locals.get(&id).expect("'super' is always resolved");
+        let superclass = self.environment.borrow().get_at(distance, "super")?;
+        let object = self.environment.borrow().get_at(distance - 1, "this")?;
+        let (Object::Class(superclass), Object::Instance(instance)) =
+            (&*superclass.borrow(), &*object.borrow())
+        else {
+            unreachable!()
+        };
+        match superclass.find_method(method.lexeme.clone()) {
+            Some(m) => Ok(Rc::new(RefCell::new(Object::Function(Box::new(
+                m.bind(instance.clone()),
+            ))))),
+            None => Err(RuntimeError::new(
+                method.clone(),
+                &format!("Undefined property '{}'.", method.lexeme),
+                None,
+            )),
+        }
+    }
+
+    pub fn resolve(&mut self, id: ExprId, depth: usize) {
+        self.locals.insert(id, depth);
+    }
+    fn look_up_variable(
+        &mut self,
+        name: &Token,
+        id: ExprId,
+    ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        let distance = self.locals.get(&id);
+        if let Some(d) = distance {
+            return self.environment.borrow().get_at(*d, &name.lexeme);
+        }
+        // globals are found by name directly, without cloning the token
+        match self.globals.borrow().find(&name.lexeme) {
+            Some(o) => Ok(o),
+            None => Err(RuntimeError::new(
+                name.clone(),
+                &("Get: Undefined variable '".to_owned() + &name.lexeme + "'."),
+                None,
+            )),
+        }
+    }
+}
+
+impl Visitor<Rc<RefCell<Object>>, ()> for Interpreter {
+    fn visit_expr(&mut self, e: &Expr) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+        match e {
+            Expr::Array { elements, .. } => self.array(elements),
+            Expr::Assign {
+                id, name, value, ..
+            } => self.assign(*id, name, value),
+            Expr::Binary {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                let left = self.visit_expr(left)?;
+                let right = self.visit_expr(right)?;
+                self.binary(operator, left, right)
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                ..
+            } => self.call(callee, paren, arguments),
+            Expr::Block {
+                statements, value, ..
+            } => self.block(statements, value),
+            Expr::Class {
+                fields,
+                methods,
+                getters,
+                setters,
+                ..
+            } => self.class(fields, methods, getters, setters),
+            Expr::Comma { expressions, .. } => self.comma(expressions),
+            Expr::Get { object, name, .. } | Expr::OptionalGet { object, name, .. } => {
+                let optional = matches!(e, Expr::OptionalGet { .. });
+                let object = self.visit_expr(object)?;
+                self.get_property(&object, name, optional)
+            }
+            Expr::Grouping { expression, .. } => self.visit_expr(expression),
+            Expr::Index {
+                object,
+                bracket,
+                index,
+                ..
+            } => {
+                let object = self.visit_expr(object)?;
+                let index = self.visit_expr(index)?;
+                self.index(bracket, object, index)
+            }
+            Expr::Literal { value, .. } => Ok(Rc::new(RefCell::new(value.clone()))),
+            Expr::Logical {
+                left,
+                operator,
+                right,
+                ..
+            } => self.logical(left, operator, right),
+            Expr::Map { brace, entries, .. } => self.map(brace, entries),
+            Expr::Set {
+                object,
+                name,
+                value,
+                ..
+            } => self.set_property(object, name, value),
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+                ..
+            } => {
+                let object = self.visit_expr(object)?;
+                let index = self.visit_expr(index)?;
+                let value = self.visit_expr(value)?;
+                self.set_index(bracket, object, index, value)
+            }
+            Expr::Super { id, method, .. } => self.super_method(*id, method),
+            Expr::This { id, keyword, .. } => self.look_up_variable(keyword, *id),
+
+            Expr::Unary {
+                operator, right, ..
+            } => {
+                let obj = self.visit_expr(right)?;
+                unary(operator, &obj)
+            }
+            Expr::Variable { id, name, .. } => self.look_up_variable(name, *id),
+        }
+    }
+    fn visit_stmt(&mut self, s: &Stmt) -> Result<(), RuntimeError> {
+        match s {
+            Stmt::Expr(e, _) => {
+                self.visit_expr(e)?;
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                if is_truthy(&self.visit_expr(condition)?.borrow()) {
+                    self.visit_stmt(then_branch)?;
+                } else {
+                    if let Some(s) = else_branch {
+                        self.visit_stmt(s)?;
+                    }
+                }
+            }
+            Stmt::Import {
+                keyword,
+                path,
+                name,
+                ..
+            } => {
+                let module = self.import(keyword, path)?;
+                self.environment
+                    .borrow_mut()
+                    .define(name.lexeme.clone(), module);
+            }
+            Stmt::Private { declaration, .. } => self.visit_stmt(declaration)?,
+            Stmt::Print(e, _) => {
+                let obj = self.visit_expr(e)?;
+                if self.verbose {
+                    writeln!(self.out, "{}", obj.borrow().verbose())
+                } else {
+                    writeln!(self.out, "{}", obj.borrow())
+                }
+                .expect("Failed to write output");
+            }
+            Stmt::Return { keyword, value, .. } => {
+                let ret = self.visit_expr(value);
+                match ret {
+                    Ok(o) => {
+                        return Err(RuntimeError::new(keyword.clone(), "", Some(Rc::clone(&o))));
+                    }
+                    Err(e) if e.value.is_some() => {
+                        return Err(e);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Stmt::Enum { name, variants, .. } => {
+                let value = Object::Enum(Rc::new(Enum::new(name.lexeme.clone(), variants)));
+                self.environment
+                    .borrow_mut()
+                    .define(name.lexeme.clone(), Rc::new(RefCell::new(value)));
+            }
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                let mut value = Rc::new(RefCell::new(Object::Nil));
+                if let Some(i) = initializer {
+                    value = self.visit_expr(i)?;
+                }
+                self.environment
+                    .borrow_mut()
+                    .define(name.lexeme.clone(), value);
+            }
+            Stmt::Block { statements, .. } => {
+                self.interpret_block(
+                    statements,
+                    Rc::new(RefCell::new(Environment::new(Some(Rc::clone(
+                        &(self.environment),
+                    ))))),
+                )?;
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                fields,
+                methods,
+                getters,
+                setters,
+                ..
+            } => {
+                let superclass = match superclass {
+                    Some(expr) => match &*self.visit_expr(expr)?.borrow() {
+                        Object::Class(class) => Some(Rc::clone(class)),
+                        _ => {
+                            let Expr::Variable { name, .. } = expr else {
+                                unreachable!()
+                            };
+                            return Err(RuntimeError::new(
+                                name.clone(),
+                                "Superclass must be a class.",
+                                None,
+                            ));
+                        }
+                    },
+                    None => None,
+                };
+                self.environment
+                    .borrow_mut()
+                    .define(name.lexeme.clone(), Rc::new(RefCell::new(Object::Nil)));
+                // methods close over a scope holding `super`
+                let enclosing = Rc::clone(&self.environment);
+                if let Some(superclass) = &superclass {
+                    let environment = Environment::new(Some(Rc::clone(&enclosing)));
+                    self.environment = Rc::new(RefCell::new(environment));
+                    self.environment.borrow_mut().define(
+                        String::from("super"),
+                        Rc::new(RefCell::new(Object::Class(Rc::clone(superclass)))),
+                    );
+                }
+                let klass = Class::new(
+                    name.lexeme.clone(),
+                    superclass,
+                    self.functions(methods),
+                    self.functions(getters),
+                    self.functions(setters),
+                );
+                let klass = self.with_fields(klass, fields, Rc::clone(&enclosing));
+                self.environment = enclosing;
+                let klass = Rc::new(RefCell::new(Object::Class(Rc::new(klass))));
+                self.environment.borrow_mut().assign(name.clone(), klass)?;
+            }
+            Stmt::While {
+                label,
+                condition,
+                body,
+                ..
+            } => self.run_loop(label, Some(condition), body, None)?,
+            Stmt::Defer { keyword, value, .. } => match &mut self.deferred {
+                Some(deferred) => deferred.push((value.clone(), Rc::clone(&self.environment))),
+                None => {
+                    return Err(RuntimeError::new(
+                        keyword.clone(),
+                        "Can't use 'defer' outside of a function.",
+                        None,
+                    ))
+                }
+            },
+            Stmt::Yield { keyword, value, .. } => {
+                let value = self.visit_expr(value)?;
+                match &mut self.yielded {
+                    Some(values) => values.push(value),
+                    None => {
+                        return Err(RuntimeError::new(
+                            keyword.clone(),
+                            "Can't yield outside of a function.",
+                            None,
+                        ))
+                    }
+                }
+            }
+            Stmt::For {
+                label,
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                let environment = Rc::new(RefCell::new(Environment::new(Some(Rc::clone(
+                    &self.environment,
+                )))));
+                let previous = mem::replace(&mut self.environment, environment);
+                let result = match initializer {
+                    Some(i) => self.visit_stmt(i),
+                    None => Ok(()),
+                }
+                .and_then(|_| self.run_loop(label, condition.as_ref(), body, increment.as_ref()));
+                self.environment = previous;
+                result?;
+            }
+            Stmt::Break { keyword, label, .. } | Stmt::Continue { keyword, label, .. } => {
+                return Err(RuntimeError::jump(keyword.clone(), label.as_ref()));
+            }
+            Stmt::Function { name, .. } => {
+                let function = self.closure(s);
+                self.environment.borrow_mut().define(
+                    name.lexeme.clone(),
+                    Rc::new(RefCell::new(Object::Function(Box::new(function)))),
+                );
+            }
         };
+        Ok(())
+    }
+}
+
+fn is_truthy(obj: &Object) -> bool {
+    match obj {
+        Object::Nil => false,
+        Object::Bool(b) => *b,
+        _ => true,
+    }
+}
+
+// A negative index counts back from the end, so -1 is the last element.
+fn array_index(bracket: &Token, index: &Object, len: usize) -> Result<usize, RuntimeError> {
+    match index {
+        Object::Number(n) if n.fract() == 0.0 => {
+            let i = if *n < 0.0 { n + len as f64 } else { *n };
+            if i >= 0.0 && i < len as f64 {
+                return Ok(i as usize);
+            }
+            Err(RuntimeError::new(
+                bracket.clone(),
+                "Array index out of bounds.",
+                None,
+            ))
+        }
+        _ => Err(RuntimeError::new(
+            bracket.clone(),
+            "Array index must be an integer.",
+            None,
+        )),
+    }
+}
+
+fn map_key(brace: &Token, key: &Object) -> Result<Object, RuntimeError> {
+    match key {
+        Object::String(_)
+        | Object::Number(_)
+        | Object::Bool(_)
+        | Object::Function(_)
+        | Object::NativeFunction(_)
+        | Object::Instance(_)
+        | Object::Variant(_) => Ok(key.clone()),
+        _ => Err(RuntimeError::new(
+            brace.clone(),
+            "Map keys must be strings, numbers, booleans, functions, instances or enum variants.",
+            None,
+        )),
+    }
+}
+
+/// Returns whether `a` and `b` differ by at most `epsilon`. Equal infinities
+/// are equal, and `NaN` is never equal to anything.
+fn approx_eq(a: f64, b: f64, epsilon: f64) -> bool {
+    a == b || (a - b).abs() <= epsilon
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Span;
+    use crate::function::NativeMethod;
+    use crate::token::{Token, TokenType};
+
+    fn interpret(source: &str) -> Interpreter {
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut resolver = Resolver::new(Interpreter::new());
+        resolver.resolve_stmts(&stmts).expect("resolve error");
+        let mut interpreter = resolver.interpreter;
+        interpreter.interpret_all(&stmts);
+        interpreter
+    }
+
+    fn value(interpreter: &Interpreter, name: &str) -> Object {
+        let token = Token {
+            token_type: TokenType::Identifier,
+            lexeme: String::from(name),
+            literal: None,
+            line: 0,
+            offset: 0,
+            column: 0,
+            end_column: 0,
+        };
+        let value = interpreter.globals.borrow().get(token).expect("undefined");
+        let value = value.borrow().clone();
+        value
+    }
+
+    // Formats the value in place, since cycle detection relies on addresses.
+    fn global(interpreter: &Interpreter, name: &str) -> String {
+        let token = Token {
+            token_type: TokenType::Identifier,
+            lexeme: String::from(name),
+            literal: None,
+            line: 0,
+            offset: 0,
+            column: 0,
+            end_column: 0,
+        };
+        let value = interpreter.globals.borrow().get(token).expect("undefined");
+        let string = value.borrow().to_string();
+        string
+    }
+
+    #[test]
+    fn unary() {
+        let mut interpreter = Interpreter::new();
+        let unary_expression = Expr::Unary {
+            operator: Token {
+                token_type: TokenType::Minus,
+                lexeme: String::from("-"),
+                literal: None,
+                line: 0,
+                offset: 0,
+                column: 0,
+                end_column: 0,
+            },
+            right: Box::new(Expr::Literal {
+                value: Object::Number(1.0),
+                lexeme: None,
+                span: Span::default(),
+            }),
+            span: Span::default(),
+        };
+        match interpreter.visit_expr(&unary_expression) {
+            Ok(r) => assert_eq!(*r.borrow(), Object::Number(-1.0)),
+            Err(_) => panic!(),
+        }
+    }
+
+    #[test]
+    fn assignment() {
+        let mut _interpreter = Interpreter::new();
+        let _assignment_expression = Expr::Assign {
+            id: ExprId::fresh(),
+            name: Token {
+                token_type: TokenType::Identifier,
+                lexeme: String::from("a"),
+                literal: None,
+                line: 0,
+                offset: 0,
+                column: 0,
+                end_column: 0,
+            },
+            value: Box::new(Expr::Literal {
+                value: Object::Number(1.0),
+                lexeme: None,
+                span: Span::default(),
+            }),
+            span: Span::default(),
+        };
+    }
+
+    #[test]
+    fn negative_indices_count_from_the_end() {
+        let interpreter = interpret(
+            "var last = [1, 2, 3][-1]; var a = [1, 2, 3]; var second = a[-2]; \
+             a[-1] = 4; var set = a[2]; var first = a[-3]; \
+             var failed = true; failed = a[-4]; failed = a[3]; a[-4] = 0;",
+        );
+        assert_eq!(global(&interpreter, "last"), "3");
+        assert_eq!(global(&interpreter, "second"), "2");
+        assert_eq!(global(&interpreter, "set"), "4");
+        assert_eq!(global(&interpreter, "first"), "1");
+        assert_eq!(global(&interpreter, "failed"), "true");
+        assert_eq!(global(&interpreter, "a"), "[1, 2, 4]");
+
+        let mut scanner = Scanner::new(String::from("[1, 2, 3][-4];"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let error = Interpreter::new().interpret(&stmts).unwrap_err();
+        assert_eq!(error.message, "Array index out of bounds.");
+    }
+
+    #[test]
+    fn print_nested_arrays() {
+        let interpreter = interpret("var a = [1, [2, \"two\", []], \"one\"]; var s = \"top\";");
+        assert_eq!(global(&interpreter, "a"), "[1, [2, \"two\", []], \"one\"]");
+        assert_eq!(global(&interpreter, "s"), "top");
+    }
+
+    #[test]
+    fn print_map() {
+        let interpreter = interpret("var m = {\"a\": [1, \"b\"]};");
+        assert_eq!(global(&interpreter, "m"), "{\"a\": [1, \"b\"]}");
+    }
+
+    #[test]
+    fn print_cyclic_array() {
+        let interpreter = interpret("var a = [1, 2]; a[1] = a;");
+        assert_eq!(global(&interpreter, "a"), "[1, [...]]");
+    }
+
+    #[test]
+    fn hash_native() {
+        let interpreter = interpret(
+            "var same = hash(\"a\") == hash(\"a\"); \
+             var different = hash(\"a\") != hash(\"b\"); \
+             var zero = hash(0) == hash(-0);",
+        );
+        assert_eq!(global(&interpreter, "same"), "true");
+        assert_eq!(global(&interpreter, "different"), "true");
+        assert_eq!(global(&interpreter, "zero"), "true");
+    }
+
+    #[test]
+    fn hash_instances_by_identity() {
+        let interpreter = interpret(
+            "class A {} var a = A(); var b = a; \
+             var same = hash(a) == hash(b); var different = hash(a) != hash(A());",
+        );
+        assert_eq!(global(&interpreter, "same"), "true");
+        assert_eq!(global(&interpreter, "different"), "true");
+    }
+
+    #[test]
+    fn anonymous_class_expression() {
+        let interpreter = interpret(
+            "var Greeter = class { greet() { return \"hi \" + this.name; } }; \
+             var greeter = Greeter(); greeter.name = \"lox\"; \
+             var greeting = greeter.greet();",
+        );
+        assert_eq!(global(&interpreter, "greeting"), "hi lox");
+        assert_eq!(global(&interpreter, "Greeter"), "<anonymous class>");
+    }
+
+    #[test]
+    fn setter_is_called_on_assignment() {
+        let interpreter = interpret(
+            "class Rect { \
+               get area() { return this.w * this.h; } \
+               set area(value) { this.w = value / this.h; } \
+             } \
+             var rect = Rect(); rect.w = 2; rect.h = 5; \
+             rect.area = 10; var w = rect.w; var area = rect.area;",
+        );
+        assert_eq!(global(&interpreter, "w"), "2");
+        assert_eq!(global(&interpreter, "area"), "10");
+    }
+
+    #[test]
+    fn set_field_from_own_value() {
+        let interpreter = interpret("class P {} var p = P(); p.x = 1; p.x = p.x + 1; var x = p.x;");
+        assert_eq!(global(&interpreter, "x"), "2");
+    }
+
+    #[test]
+    fn import_hides_private_declarations() {
+        let path = std::env::temp_dir().join("rlox_import_private.lox");
+        fs::write(
+            &path,
+            "private var greeting = \"hi\"; \
+             fun greet() { return greeting; } \
+             private fun secret() { return 1; }",
+        )
+        .unwrap();
+        let interpreter = interpret(&format!(
+            "import \"{}\" as m; var greeting = m.greet(); var hidden = m.secret();",
+            path.display()
+        ));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(global(&interpreter, "greeting"), "hi");
+        let hidden = Token {
+            token_type: TokenType::Identifier,
+            lexeme: String::from("hidden"),
+            literal: None,
+            line: 0,
+            offset: 0,
+            column: 0,
+            end_column: 0,
+        };
+        assert!(interpreter.globals.borrow().get(hidden).is_err());
+    }
+
+    #[test]
+    fn import_uses_the_loader() {
+        let source = "import \"m.lox\" as m; var answer = m.answer(); import \"missing.lox\" as x;";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut interpreter = Interpreter::new();
+        let modules = HashMap::from([("m.lox", "fun answer() { return 42; }")]);
+        interpreter.set_loader(Box::new(move |path| match modules.get(path) {
+            Some(source) => Ok(source.to_string()),
+            None => Err(format!("no module {path}")),
+        }));
+        let mut resolver = Resolver::new(interpreter);
+        resolver.resolve_stmts(&stmts).expect("resolve error");
+        let mut interpreter = resolver.interpreter;
+        let error = interpreter.interpret(&stmts).unwrap_err();
+        assert_eq!(
+            error.message,
+            "Could not read module 'missing.lox': no module missing.lox"
+        );
+        assert_eq!(global(&interpreter, "answer"), "42");
+    }
+
+    #[test]
+    fn file_natives_use_the_file_system_when_allowed() {
+        #[derive(Default)]
+        struct MemoryFileSystem(HashMap<String, String>);
+
+        impl FileSystem for MemoryFileSystem {
+            fn read(&self, path: &str) -> Result<String, String> {
+                self.0.get(path).cloned().ok_or(format!("no file {path}"))
+            }
+
+            fn write(&mut self, path: &str, contents: &str) -> Result<(), String> {
+                self.0.insert(path.to_string(), contents.to_string());
+                Ok(())
+            }
+        }
+
+        fn run(allow_fs: bool, source: &str) -> Interpreter {
+            let mut scanner = Scanner::new(String::from(source));
+            let tokens = scanner.scan_tokens();
+            let stmts = Parser::new(tokens).parse().expect("parse error");
+            let mut interpreter =
+                Interpreter::with_writers(Box::new(io::sink()), Box::new(io::sink()));
+            interpreter.set_file_system(Box::<MemoryFileSystem>::default());
+            interpreter.set_allow_fs(allow_fs);
+            interpreter.interpret_all(&stmts);
+            interpreter
+        }
+
+        let interpreter = run(
+            true,
+            "var written = write_file(\"notes.txt\", \"hello\"); \
+             var read = read_file(\"notes.txt\"); \
+             var failed = true; failed = read_file(\"missing.txt\");",
+        );
+        assert_eq!(global(&interpreter, "written"), "nil");
+        assert_eq!(global(&interpreter, "read"), "hello");
+        assert_eq!(global(&interpreter, "failed"), "true");
+
+        let interpreter = run(
+            false,
+            "var read = true; var written = true; \
+             written = write_file(\"notes.txt\", \"hello\"); read = read_file(\"notes.txt\");",
+        );
+        assert_eq!(global(&interpreter, "written"), "true");
+        assert_eq!(global(&interpreter, "read"), "true");
+    }
+
+    #[test]
+    fn values_work_as_map_keys() {
+        let interpreter = interpret("class A {} var a = A(); fun f() {}");
+        let keys = [
+            Object::Number(1.0),
+            Object::String(Rc::from("s")),
+            Object::Bool(true),
+            Object::Nil,
+            value(&interpreter, "A"),
+            value(&interpreter, "a"),
+            value(&interpreter, "f"),
+            value(&interpreter, "clock"),
+        ];
+        let mut map = HashMap::new();
+        for (i, key) in keys.iter().enumerate() {
+            map.insert(key.clone(), i);
+        }
+        assert_eq!(map.len(), keys.len());
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(map.get(key), Some(&i));
+        }
+        assert_ne!(value(&interpreter, "a"), value(&interpreter, "A"));
+        let other = interpret("class A {} var a = A();");
+        assert_ne!(value(&interpreter, "a"), value(&other, "a"));
+        assert_ne!(value(&interpreter, "A"), value(&other, "A"));
+    }
+
+    #[test]
+    fn arrays_maps_and_classes_are_equal_only_to_themselves() {
+        let interpreter = interpret(
+            "var a = [1]; var b = [a]; a[0] = b; var cyclic = a == b; var same = a == a; \
+             var arrays = [1] == [1]; var maps = {} == {}; \
+             var C = class {}; var D = class {}; var classes = C == D; var one_class = C == C;",
+        );
+        assert_eq!(global(&interpreter, "cyclic"), "false");
+        assert_eq!(global(&interpreter, "same"), "true");
+        assert_eq!(global(&interpreter, "arrays"), "false");
+        assert_eq!(global(&interpreter, "maps"), "false");
+        assert_eq!(global(&interpreter, "classes"), "false");
+        assert_eq!(global(&interpreter, "one_class"), "true");
+    }
+
+    #[test]
+    fn indexing_a_container_with_itself_is_a_runtime_error() {
+        let interpreter = interpret(
+            "var a = [1]; var in_array = \"unset\"; in_array = a[a] = 2; \
+             var m = {}; var in_map = \"unset\"; in_map = m[m] = 1;",
+        );
+        assert_eq!(global(&interpreter, "in_array"), "unset");
+        assert_eq!(global(&interpreter, "a"), "[1]");
+        assert_eq!(global(&interpreter, "in_map"), "unset");
+        assert_eq!(global(&interpreter, "m"), "{}");
+    }
+
+    #[test]
+    fn runtime_error_shows_source_line() {
+        let source = "var y = 1;\n  x + 1;";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut interpreter = Interpreter::with_source(source);
+        let error = interpreter.visit_stmt(&stmts[1]).unwrap_err();
+        assert_eq!(interpreter.snippet(&error).unwrap(), "  x + 1;\n  ^");
+    }
+
+    #[test]
+    fn continue_runs_for_increment() {
+        let interpreter = interpret(
+            "var sum = 0; var count = 0; \
+             for (var i = 0; i < 10; i = i + 1) { \
+                 count = count + 1; \
+                 if (i == 3) continue; \
+                 if (i == 7) break; \
+                 sum = sum + i; \
+             }",
+        );
+        assert_eq!(global(&interpreter, "count"), "8");
+        assert_eq!(global(&interpreter, "sum"), "18");
+    }
+
+    #[test]
+    fn equal_string_literals_compare_equal() {
+        let interpreter =
+            interpret("var same = \"lox\" == \"lox\"; var joined = \"lo\" + \"x\" == \"lox\";");
+        assert_eq!(global(&interpreter, "same"), "true");
+        assert_eq!(global(&interpreter, "joined"), "true");
+    }
+
+    #[test]
+    fn math_natives() {
+        let interpreter = interpret(
+            "var sine = sin(0); var logarithm = log(E); var power = pow(2, 8); \
+             var failed = true; failed = sin(\"a\");",
+        );
+        assert_eq!(global(&interpreter, "sine"), "0");
+        // E is rounded to a finite number, so the result is only close to 1.
+        match value(&interpreter, "logarithm") {
+            Object::Number(n) => assert!((n - 1.0).abs() <= f64::EPSILON),
+            other => panic!("expected a number, got {other}"),
+        }
+        assert_eq!(global(&interpreter, "power"), "256");
+        assert_eq!(global(&interpreter, "failed"), "true");
+    }
+
+    #[test]
+    fn to_fixed_rounds_to_digits() {
+        let interpreter = interpret(
+            "var up = to_fixed(2.718, 2); var down = to_fixed(3.14159, 2); \
+             var whole = to_fixed(7.6, 0); var tie = to_fixed(2.5, 0); \
+             var padded = to_fixed(1, 3); \
+             var failed = true; failed = to_fixed(1, 1.5); failed = to_fixed(1, -1);",
+        );
+        assert_eq!(global(&interpreter, "up"), "2.72");
+        assert_eq!(global(&interpreter, "down"), "3.14");
+        assert_eq!(global(&interpreter, "whole"), "8");
+        assert_eq!(global(&interpreter, "tie"), "2");
+        assert_eq!(global(&interpreter, "padded"), "1.000");
+        assert_eq!(global(&interpreter, "failed"), "true");
+    }
+
+    #[test]
+    fn sort_orders_arrays() {
+        let interpreter = interpret(
+            "var numbers = [3, -1, 10, 2]; var sorted = sort(numbers); \
+             var words = sort([\"pear\", \"apple\", \"fig\"]); \
+             fun descending(a, b) { return b - a; } var down = sort_by(numbers, descending); \
+             fun by_first(a, b) { return a[0] - b[0]; } \
+             var pairs = sort_by([[2, \"a\"], [1, \"b\"], [2, \"c\"], [1, \"d\"]], by_first); \
+             var failed = true; failed = sort([1, \"a\"]); \
+             fun bad(a, b) { return \"no\"; } failed = sort_by([1, 2], bad); \
+             fun boom(a, b) { return nil + 1; } failed = sort_by([1, 2], boom);",
+        );
+        assert_eq!(global(&interpreter, "sorted"), "[-1, 2, 3, 10]");
+        assert_eq!(global(&interpreter, "numbers"), "[3, -1, 10, 2]");
+        assert_eq!(
+            global(&interpreter, "words"),
+            "[\"apple\", \"fig\", \"pear\"]"
+        );
+        assert_eq!(global(&interpreter, "down"), "[10, 3, 2, -1]");
+        assert_eq!(
+            global(&interpreter, "pairs"),
+            "[[1, \"b\"], [1, \"d\"], [2, \"a\"], [2, \"c\"]]"
+        );
+        assert_eq!(global(&interpreter, "failed"), "true");
+    }
+
+    #[test]
+    fn array_reductions() {
+        let interpreter = interpret(
+            "var total = sum([1, 2, 3]); var largest = max([3, 1, 2]); \
+             var smallest = min([3, -1, 2]); var one = sum([0.5]); \
+             var failed = true; failed = sum([]); failed = max([1, \"2\"]); failed = min(1);",
+        );
+        assert_eq!(global(&interpreter, "total"), "6");
+        assert_eq!(global(&interpreter, "largest"), "3");
+        assert_eq!(global(&interpreter, "smallest"), "-1");
+        assert_eq!(global(&interpreter, "one"), "0.5");
+        assert_eq!(global(&interpreter, "failed"), "true");
+    }
+
+    #[test]
+    fn call_method_calls_by_name() {
+        let interpreter = interpret(
+            "class Calc { add(a, b) { return a + b; } twice(n) { return this.add(n, n); } } \
+             var calc = Calc(); calc.value = 1; var name = \"ad\" + \"d\"; \
+             var sum = call_method(calc, name, [2, 3]); \
+             var doubled = call_method(calc, \"twice\", [4]); \
+             var failed = true; failed = call_method(calc, \"missing\", []); \
+             failed = call_method(calc, \"value\", []); failed = call_method(calc, \"add\", [1]); \
+             failed = call_method(1, \"add\", []);",
+        );
+        assert_eq!(global(&interpreter, "sum"), "5");
+        assert_eq!(global(&interpreter, "doubled"), "8");
+        assert_eq!(global(&interpreter, "failed"), "true");
+    }
+
+    #[test]
+    fn approx_eq_allows_small_differences() {
+        let interpreter = interpret(
+            "var close = approx_eq(0.1 + 0.2, 0.3, 0.000_000_001); var far = approx_eq(1, 1.1, 0.01); \
+             var nan = approx_eq(0/0, 0/0, 1); var exact = 0.1 + 0.2 == 0.3; \
+             var failed = true; failed = approx_eq(1, 1, -1);",
+        );
+        assert_eq!(global(&interpreter, "close"), "true");
+        assert_eq!(global(&interpreter, "far"), "false");
+        assert_eq!(global(&interpreter, "nan"), "false");
+        assert_eq!(global(&interpreter, "exact"), "false");
+        assert_eq!(global(&interpreter, "failed"), "true");
+    }
+
+    #[test]
+    fn tolerant_equality_is_opt_in() {
+        let source = "var equal = 0.1 + 0.2 == 0.3; var unequal = 0.1 + 0.2 != 0.3; \
+                      var nan = 0/0 == 0/0; var apart = 1 == 1.001;";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut interpreter = Interpreter::new();
+        interpreter.set_tolerant_equality(true);
+        interpreter.interpret_all(&stmts);
+        assert_eq!(global(&interpreter, "equal"), "true");
+        assert_eq!(global(&interpreter, "unequal"), "false");
+        assert_eq!(global(&interpreter, "nan"), "false");
+        assert_eq!(global(&interpreter, "apart"), "false");
+    }
+
+    #[test]
+    fn string_natives() {
+        let interpreter = interpret(
+            "var start = trim_start(\"  a \"); var end = trim_end(\" a  \"); \
+             var replaced = replace(\"aaa\", \"a\", \"b\"); var empty = replace(\"ab\", \"\", \"-\"); \
+             var yes = contains(\"hello\", \"ell\"); var no = contains(\"hello\", \"z\"); \
+             var failed = true; failed = contains(1, \"a\");",
+        );
+        assert_eq!(global(&interpreter, "start"), "a ");
+        assert_eq!(global(&interpreter, "end"), " a");
+        assert_eq!(global(&interpreter, "replaced"), "bbb");
+        assert_eq!(global(&interpreter, "empty"), "ab");
+        assert_eq!(global(&interpreter, "yes"), "true");
+        assert_eq!(global(&interpreter, "no"), "false");
+        assert_eq!(global(&interpreter, "failed"), "true");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn scope_depth_counts_enclosing_environments() {
+        let interpreter = interpret(
+            "var top = scope_depth(); var block; { block = scope_depth(); } \
+             var inner; fun outer() { fun nested() { { inner = scope_depth(); } } nested(); \
+             return scope_depth(); } var function = outer();",
+        );
+        assert_eq!(global(&interpreter, "top"), "0");
+        assert_eq!(global(&interpreter, "block"), "1");
+        assert_eq!(global(&interpreter, "function"), "1");
+        assert_eq!(global(&interpreter, "inner"), "3");
+    }
+
+    #[test]
+    fn num_parses_numbers() {
+        let interpreter = interpret(
+            "var exponent = num(\" -1.5e3 \"); var plain = num(\"42\"); \
+             var separated = num(\"1_000.5\"); var small = num(\"+2E-2\"); \
+             var failed = 0; failed = num(\"abc\"); failed = num(\"inf\"); \
+             failed = num(\".5\"); failed = num(\"1e\"); failed = num(\"1__0\");",
+        );
+        assert_eq!(global(&interpreter, "exponent"), "-1500");
+        assert_eq!(global(&interpreter, "plain"), "42");
+        assert_eq!(global(&interpreter, "separated"), "1000.5");
+        assert_eq!(global(&interpreter, "small"), "0.02");
+        assert_eq!(global(&interpreter, "failed"), "0");
+
+        let mut scanner = Scanner::new(String::from("num(\"abc\");"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let error = Interpreter::new().interpret(&stmts).unwrap_err();
+        assert_eq!(error.message, "Cannot convert 'abc' to a number.");
+    }
+
+    #[test]
+    fn partial_binds_first_argument() {
+        let interpreter = interpret(
+            "fun add(a, b) { return a + b; } var increment = partial(add, 1); \
+             var two = increment(1); var ten = increment(9); \
+             fun greet(greeting, name, mark) { return greeting + \" \" + name + mark; } \
+             var hello = partial(partial(greet, \"hello\"), \"lox\"); var greeted = hello(\"!\"); \
+             var failed = true; failed = increment(1, 2); failed = partial(clock, 1);",
+        );
+        assert_eq!(global(&interpreter, "two"), "2");
+        assert_eq!(global(&interpreter, "ten"), "10");
+        assert_eq!(global(&interpreter, "greeted"), "hello lox!");
+        assert_eq!(global(&interpreter, "increment"), "Partial<Function<add>>");
+        assert_eq!(global(&interpreter, "failed"), "true");
+    }
+
+    #[test]
+    fn matches_native() {
+        let interpreter = interpret(
+            "var txt = matches(\"hello.txt\", \"*.txt\"); var other = matches(\"a\", \"b\"); \
+             var one = matches(\"cat\", \"c?t\"); var short = matches(\"ct\", \"c?t\"); \
+             var anchored = matches(\"hello.txt.bak\", \"*.txt\"); \
+             var stars = matches(\"abcbd\", \"a*b*d\"); var empty = matches(\"\", \"*\"); \
+             var failed = true; failed = matches(1, \"*\");",
+        );
+        assert_eq!(global(&interpreter, "txt"), "true");
+        assert_eq!(global(&interpreter, "other"), "false");
+        assert_eq!(global(&interpreter, "one"), "true");
+        assert_eq!(global(&interpreter, "short"), "false");
+        assert_eq!(global(&interpreter, "anchored"), "false");
+        assert_eq!(global(&interpreter, "stars"), "true");
+        assert_eq!(global(&interpreter, "empty"), "true");
+        assert_eq!(global(&interpreter, "failed"), "true");
+    }
+
+    #[test]
+    fn pad_natives() {
+        let interpreter = interpret(
+            "var left = pad_left(\"7\", 3); var right = pad_right(\"ab\", 4); \
+             var zeros = pad_left_char(\"42\", 5, \"0\"); var long = pad_left(\"abc\", 2); \
+             var row = \"|\" + pad_right(\"id\", 4) + \"|\" + pad_left(\"12\", 4) + \"|\"; \
+             var failed = true; failed = pad_left(\"a\", 1.5); failed = pad_left(\"a\", -1); \
+             failed = pad_left_char(\"a\", 3, \"ab\");",
+        );
+        assert_eq!(global(&interpreter, "left"), "  7");
+        assert_eq!(global(&interpreter, "right"), "ab  ");
+        assert_eq!(global(&interpreter, "zeros"), "00042");
+        assert_eq!(global(&interpreter, "long"), "abc");
+        assert_eq!(global(&interpreter, "row"), "|id  |  12|");
+        assert_eq!(global(&interpreter, "failed"), "true");
+
+        let interpreter = interpret("var padded = pad_left(\"é\", 4);");
+        assert_eq!(global(&interpreter, "padded"), "   é");
+    }
+
+    #[test]
+    fn host_classes_have_native_methods() {
+        fn count(this: &Instance) -> f64 {
+            match this.fields().iter().find(|(name, _)| name == "count") {
+                Some((_, value)) => match &*value.borrow() {
+                    Object::Number(n) => *n,
+                    _ => 0.0,
+                },
+                None => 0.0,
+            }
+        }
+        fn add(
+            _: &mut Interpreter,
+            this: &Instance,
+            paren: &Token,
+            arguments: Vec<Rc<RefCell<Object>>>,
+        ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+            let n = number_argument(paren, &arguments[0])?;
+            let total = Rc::new(RefCell::new(Object::Number(count(this) + n)));
+            this.define(String::from("count"), Rc::clone(&total));
+            Ok(total)
+        }
+        fn total(
+            _: &mut Interpreter,
+            this: &Instance,
+            _: &Token,
+            _: Vec<Rc<RefCell<Object>>>,
+        ) -> Result<Rc<RefCell<Object>>, RuntimeError> {
+            Ok(Rc::new(RefCell::new(Object::Number(count(this)))))
+        }
+
+        let methods = HashMap::from([
+            (String::from("add"), (1, add as NativeMethod)),
+            (String::from("total"), (0, total as NativeMethod)),
+        ]);
+        let counter = Class::with_native_methods(String::from("Counter"), methods);
+        let source = "var c = Counter(); c.add(2); var add = c.add; add(3); \
+                      var other = Counter(); other.add(10); \
+                      var total = c.total(); var kind = c; \
+                      var failed = true; failed = c.add();";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let interpreter = Interpreter::new();
+        interpreter.globals.borrow_mut().define(
+            String::from("Counter"),
+            Rc::new(RefCell::new(Object::Class(Rc::new(counter)))),
+        );
+        let mut resolver = Resolver::new(interpreter);
+        resolver.resolve_stmts(&stmts).expect("resolve error");
+        let mut interpreter = resolver.interpreter;
+        interpreter.interpret_all(&stmts);
+
+        assert_eq!(global(&interpreter, "total"), "5");
+        assert_eq!(global(&interpreter, "kind"), "Counter instance");
+        assert_eq!(global(&interpreter, "failed"), "true");
+    }
+
+    #[test]
+    fn len_counts_scalar_values() {
+        let interpreter = interpret(
+            "var combined = len(\"e\u{301}\"); var word = len(\"hello\"); \
+             var elements = len([1, 2, 3]); var entries = len({\"a\": 1});",
+        );
+        assert_eq!(global(&interpreter, "combined"), "2");
+        assert_eq!(global(&interpreter, "word"), "5");
+        assert_eq!(global(&interpreter, "elements"), "3");
+        assert_eq!(global(&interpreter, "entries"), "1");
+    }
+
+    #[cfg(feature = "graphemes")]
+    #[test]
+    fn grapheme_len_counts_combined_characters() {
+        let interpreter = interpret(
+            "var s = \"e\u{301}\"; var scalars = len(s); var graphemes = grapheme_len(s);",
+        );
+        assert_eq!(global(&interpreter, "scalars"), "2");
+        assert_eq!(global(&interpreter, "graphemes"), "1");
+    }
+
+    #[test]
+    fn seeded_random_is_reproducible() {
+        let source = "seed(42); var rolls = [random_int(1, 6), random_int(1, 6), \
+                      random_int(1, 6), random_int(1, 6), random_int(1, 6)]; \
+                      var r = random(); var in_range = r >= 0 and r < 1;";
+        let interpreter = interpret(source);
+        assert_eq!(global(&interpreter, "rolls"), "[6, 3, 1, 3, 3]");
+        assert_eq!(global(&interpreter, "in_range"), "true");
+        assert_eq!(
+            global(&interpret(source), "rolls"),
+            global(&interpreter, "rolls")
+        );
+    }
+
+    #[test]
+    fn large_seeds_give_different_sequences() {
+        let first = interpret("seed(16777216); var r = random();");
+        let second = interpret("seed(16777217); var r = random();");
+        assert_ne!(global(&first, "r"), global(&second, "r"));
+    }
+
+    #[test]
+    fn random_int_rejects_bounds_past_2_53() {
+        let interpreter = interpret(
+            "var huge = \"unset\"; huge = random_int(0, 1e20); \
+             var widest = random_int(-9007199254740992, 9007199254740992); \
+             var in_range = widest >= -9007199254740992 and widest <= 9007199254740992;",
+        );
+        assert_eq!(global(&interpreter, "huge"), "unset");
+        assert_eq!(global(&interpreter, "in_range"), "true");
+    }
+
+    #[test]
+    fn index_instance_through_item_methods() {
+        let interpreter = interpret(
+            "class Pair { \
+                 getItem(key) { if (key == 0) return this.first; return this.second; } \
+                 setItem(key, value) { if (key == 0) this.first = value; else this.second = value; } \
+             } \
+             var p = Pair(); p[0] = \"a\"; p[1] = \"b\"; var first = p[0]; var second = p[1]; \
+             class Empty {} var e = Empty(); var missing = \"unset\"; missing = e[0];",
+        );
+        assert_eq!(global(&interpreter, "first"), "a");
+        assert_eq!(global(&interpreter, "second"), "b");
+        assert_eq!(global(&interpreter, "missing"), "unset");
+    }
+
+    #[test]
+    fn comma_yields_last_value() {
+        let interpreter = interpret(
+            "var a = 0; var last = (a = 1, a + 1, a + 2); \
+             fun f(x, y) { return y; } var second = f(1, 2); var list = [1, 2];",
+        );
+        assert_eq!(global(&interpreter, "last"), "3");
+        assert_eq!(global(&interpreter, "a"), "1");
+        assert_eq!(global(&interpreter, "second"), "2");
+        assert_eq!(global(&interpreter, "list"), "[1, 2]");
+    }
+
+    // A writer whose contents can still be read after it is handed over.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn while_var_loops_until_nil() {
+        let interpreter = interpret(
+            "var items = [1, 2, 3]; var i = 0; \
+             fun next() { if (i < len(items)) { i = i + 1; return items[i - 1]; } return nil; } \
+             var sum = 0; while (var item = next()) { sum = sum + item; } \
+             var scoped = true; { var item = \"outer\"; while (var item = nil) {} scoped = item; }",
+        );
+        assert_eq!(global(&interpreter, "sum"), "6");
+        assert_eq!(global(&interpreter, "scoped"), "outer");
+        let item = Token {
+            token_type: TokenType::Identifier,
+            lexeme: String::from("item"),
+            literal: None,
+            line: 0,
+            offset: 0,
+            column: 0,
+            end_column: 0,
+        };
+        assert!(interpreter.globals.borrow().get(item).is_err());
+    }
+
+    #[test]
+    fn generators_yield_in_order() {
+        let interpreter = interpret(
+            "fun count(n) { for (var i = 1; i <= n; i = i + 1) { yield i * 10; } return 99; } \
+             var numbers = count(3); var seen = 0; \
+             while (var n = next(numbers)) { seen = seen * 100 + n; } \
+             var done = next(numbers); var empty = count(0); \
+             fun outer() { fun inner() { yield 1; } return inner; } var inner = outer(); \
+             var failed = true; failed = next(1);",
+        );
+        assert_eq!(global(&interpreter, "seen"), "102030");
+        assert_eq!(global(&interpreter, "done"), "nil");
+        assert_eq!(global(&interpreter, "empty"), "Generator<count>");
+        assert_eq!(global(&interpreter, "failed"), "true");
+        assert!(matches!(value(&interpreter, "inner"), Object::Function(_)));
+    }
+
+    #[test]
+    fn try_call_returns_default_on_error() {
+        // Dividing by zero gives infinity, so the failing division is by nil.
+        let interpreter = interpret(
+            "fun divide() { return 1 / nil; } fun answer() { return 42; } \
+             var x = 1; fun inner() { var y = 2; return x + y + missing; } \
+             var fallback = try_call(divide, \"default\"); var result = try_call(answer, 0); \
+             var nested = try_call(inner, -1); var after = x; \
+             var failed = true; failed = try_call(1, 0);",
+        );
+        assert_eq!(global(&interpreter, "fallback"), "default");
+        assert_eq!(global(&interpreter, "result"), "42");
+        assert_eq!(global(&interpreter, "nested"), "-1");
+        assert_eq!(global(&interpreter, "after"), "1");
+        assert_eq!(global(&interpreter, "failed"), "true");
+    }
+
+    #[test]
+    fn closures_in_methods_see_this() {
+        let interpreter = interpret(
+            "class Counter { reader() { fun read() { return this.count; } return read; } } \
+             var counter = Counter(); counter.count = 7; var read = counter.reader(); \
+             var other = Counter(); other.count = 1; other.reader(); \
+             counter.count = 8; var count = read(); \
+             class A { outer() { fun middle() { fun inner() { return this.name; } return inner; } return middle(); } } \
+             var a = A(); a.name = \"a\"; var name = a.outer()();",
+        );
+        assert_eq!(global(&interpreter, "count"), "8");
+        assert_eq!(global(&interpreter, "name"), "a");
+    }
+
+    #[test]
+    fn block_expression_gives_its_final_value() {
+        let interpreter = interpret(
+            "var a = 1; var x = { var a = 2; a = a * 3; a + 1 }; \
+             var empty = {}; var map = {\"k\": 1}; var none = { print \"side\"; }; \
+             fun f(n) { return { var doubled = n * 2; doubled }; } var called = f(4);",
+        );
+        assert_eq!(global(&interpreter, "x"), "7");
+        assert_eq!(global(&interpreter, "a"), "1");
+        assert_eq!(global(&interpreter, "empty"), "{}");
+        assert_eq!(global(&interpreter, "map"), "{\"k\": 1}");
+        assert_eq!(global(&interpreter, "none"), "nil");
+        assert_eq!(global(&interpreter, "called"), "8");
+    }
+
+    #[test]
+    fn nested_braces_are_maps_or_blocks() {
+        let interpreter = interpret(
+            "var nested = {{1}}; var inner_map = { {\"a\": 1} }; \
+             var k = \"key\"; var by_name = {k: 2}; var block_key = { {1}: 3 }; \
+             var after_block = { { var b = 1; } 5 }; \
+             var looped = { outer: while (true) { break outer; } 6 };",
+        );
+        assert_eq!(global(&interpreter, "nested"), "1");
+        assert_eq!(global(&interpreter, "inner_map"), "{\"a\": 1}");
+        assert_eq!(global(&interpreter, "by_name"), "{\"key\": 2}");
+        assert_eq!(global(&interpreter, "block_key"), "{1: 3}");
+        assert_eq!(global(&interpreter, "after_block"), "5");
+        assert_eq!(global(&interpreter, "looped"), "6");
+    }
+
+    // Resolving and running are as deep as parsing, so whatever parses must
+    // also run on a default-size thread.
+    #[test]
+    fn nesting_up_to_the_limit_runs_on_a_default_thread() {
+        let depth = crate::parser::MAX_DEPTH - 1;
+        let values = std::thread::spawn(move || {
+            [("(", ")"), ("{", "}"), ("f(", ")")].map(|(open, close)| {
+                let source = format!(
+                    "fun f(a) {{ return a; }} var x = {}1{};",
+                    open.repeat(depth),
+                    close.repeat(depth)
+                );
+                global(&interpret(&source), "x")
+            })
+        })
+        .join()
+        .unwrap();
+        assert_eq!(values, ["1", "1", "1"]);
+    }
+
+    #[test]
+    fn fields_start_with_their_defaults() {
+        let interpreter = interpret(
+            "var start = 10; \
+             class Point { x = start; y = \"hi\"; show() { return this.y; } } \
+             class Point3 < Point { y = \"three\"; z = [x_of(Point())]; } \
+             fun x_of(p) { return p.x; } \
+             var a = Point(); var b = Point(); a.x = 1; \
+             var ax = a.x; var bx = b.x; var shown = b.show(); \
+             var c = Point3(); var cy = c.y; var cz = c.z[0]; \
+             var d = Point3(); c.z[0] = 0; var dz = d.z[0];",
+        );
+        assert_eq!(global(&interpreter, "ax"), "1");
+        assert_eq!(global(&interpreter, "bx"), "10");
+        assert_eq!(global(&interpreter, "shown"), "hi");
+        assert_eq!(global(&interpreter, "cy"), "three");
+        assert_eq!(global(&interpreter, "cz"), "10");
+        assert_eq!(global(&interpreter, "dz"), "10");
+    }
+
+    #[test]
+    fn calls_results_of_method_chains() {
+        let interpreter = interpret(
+            "class Greeter { \
+               getFn() { fun greet(greeting) { return greeting + \" \" + this.name; } return greet; } \
+               self() { return this; } } \
+             var greeter = Greeter(); greeter.name = \"lox\"; \
+             var greeting = greeter.getFn()(\"hi\"); \
+             var chained = greeter.self().self().getFn()(\"hey\"); \
+             fun adder(a) { fun add(b) { return a + b; } return add; } \
+             var sum = adder(1)(2); \
+             class Holder {} var holder = Holder(); \
+             fun swap() { holder.f = adder; return \"swapped\"; } \
+             holder.f = swap; var swapped = holder.f(); var after = holder.f(3)(4); \
+             var fns = [swap]; fun replace() { fns[0] = adder; return 0; } fns[0] = replace; \
+             var replaced = fns[0](); var indexed = fns[0](5)(6);",
+        );
+        assert_eq!(global(&interpreter, "greeting"), "hi lox");
+        assert_eq!(global(&interpreter, "chained"), "hey lox");
+        assert_eq!(global(&interpreter, "sum"), "3");
+        assert_eq!(global(&interpreter, "swapped"), "swapped");
+        assert_eq!(global(&interpreter, "after"), "7");
+        assert_eq!(global(&interpreter, "replaced"), "0");
+        assert_eq!(global(&interpreter, "indexed"), "11");
+    }
+
+    #[test]
+    fn inherited_and_super_methods() {
+        let interpreter = interpret(
+            "class A { greet() { return \"A\"; } name() { return \"a\"; } } \
+             class B < A { greet() { return super.greet() + \"B\"; } } \
+             var b = B(); var greeting = b.greet(); var inherited = b.name();",
+        );
+        assert_eq!(global(&interpreter, "greeting"), "AB");
+        assert_eq!(global(&interpreter, "inherited"), "a");
+    }
+
+    #[test]
+    fn closures_are_released_on_drop() {
+        let interpreter = interpret("fun f() {} fun outer() { fun inner() {} } outer();");
+        let globals = Rc::downgrade(&interpreter.globals);
+        drop(interpreter);
+        assert!(globals.upgrade().is_none());
+    }
+
+    #[test]
+    fn functions_as_map_keys() {
+        let interpreter = interpret(
+            "fun make() { fun f() {} return f; } \
+             var a = make(); var b = make(); var m = {a: \"first\"}; m[b] = \"second\"; \
+             var first = m[a]; var second = m[b]; var alias = a; var again = m[alias];",
+        );
+        assert_eq!(global(&interpreter, "first"), "first");
+        assert_eq!(global(&interpreter, "second"), "second");
+        assert_eq!(global(&interpreter, "again"), "first");
+    }
+
+    #[test]
+    fn instances_as_map_keys() {
+        let interpreter = interpret(
+            "class A {} var a = A(); var b = A(); var m = {}; m[a] = 1; m[b] = 2; \
+             var first = m[a]; var second = m[b]; var missing = m[A()];",
+        );
+        assert_eq!(global(&interpreter, "first"), "1");
+        assert_eq!(global(&interpreter, "second"), "2");
+        assert_eq!(global(&interpreter, "missing"), "nil");
+    }
+
+    #[test]
+    fn chained_assignment_assigns_every_target() {
+        let interpreter = interpret(
+            "var a; var b; var c; var result = a = b = c = 0; \
+             class Box {} var box = Box(); var y; box.x = y = 5; var x = box.x; \
+             var log = \"\"; fun target(name) { log = log + name; return box; } \
+             target(\"first\").x = target(\"second\").z = 7; var z = box.z;",
+        );
+        assert_eq!(global(&interpreter, "a"), "0");
+        assert_eq!(global(&interpreter, "b"), "0");
+        assert_eq!(global(&interpreter, "c"), "0");
+        assert_eq!(global(&interpreter, "result"), "0");
+        assert_eq!(global(&interpreter, "x"), "5");
+        assert_eq!(global(&interpreter, "y"), "5");
+        // targets are evaluated left to right, then assigned right to left
+        assert_eq!(global(&interpreter, "log"), "firstsecond");
+        assert_eq!(global(&interpreter, "z"), "7");
+    }
+
+    #[test]
+    fn enum_variants_are_distinct_singletons() {
+        let interpreter = interpret(
+            "enum Color { Red, Green, Blue, } var red = Color.Red; \
+             var same = Color.Red == red; var different = Color.Red == Color.Green; \
+             var sky = {Color.Blue: \"sky\"}; var keyed = sky[Color.Blue]; \
+             var failed = true; failed = Color.Purple;",
+        );
+        assert_eq!(global(&interpreter, "same"), "true");
+        assert_eq!(global(&interpreter, "different"), "false");
+        assert_eq!(global(&interpreter, "keyed"), "sky");
+        assert_eq!(global(&interpreter, "failed"), "true");
+    }
+
+    #[test]
+    fn enum_variants_print_with_their_enum() {
+        let source = "enum Color { Red, Green } print Color.Red; print Color;";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let out = SharedBuffer::default();
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(SharedBuffer::default()));
+        interpreter.interpret_all(&stmts);
+        assert_eq!(out.contents(), "Color.Red\n<enum Color>\n");
+    }
+
+    #[test]
+    fn deferred_expressions_run_last_first_after_the_body() {
+        let source = "fun say(s) { print s; } \
+                      fun f() { defer say(\"first\"); defer say(\"second\"); print \"body\"; } \
+                      f();";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let out = SharedBuffer::default();
+        let interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(SharedBuffer::default()));
+        let mut resolver = Resolver::new(interpreter);
+        resolver.resolve_stmts(&stmts).expect("resolve error");
+        resolver.interpreter.interpret_all(&stmts);
+        assert_eq!(out.contents(), "body\nsecond\nfirst\n");
+    }
+
+    #[test]
+    fn deferred_expressions_run_on_return_and_error() {
+        let interpreter = interpret(
+            "var log = \"\"; fun note(s) { log = log + s; } \
+             fun returns() { var x = \"a\"; defer note(x); x = \"b\"; return x; } \
+             var returned = returns(); \
+             fun fails() { defer note(\"c\"); return nil + 1; } \
+             var failed = true; failed = fails();",
+        );
+        assert_eq!(global(&interpreter, "returned"), "b");
+        assert_eq!(global(&interpreter, "log"), "bc");
+        assert_eq!(global(&interpreter, "failed"), "true");
+    }
+
+    #[test]
+    fn evaluates_expression_against_current_state() {
+        let mut interpreter = interpret("var x = 41;");
+        let mut scanner = Scanner::new(String::from("x + 1"));
+        let tokens = scanner.scan_tokens();
+        let expr = Parser::new(tokens).parse_expression().expect("parse error");
+        let value = interpreter.evaluate_expr(&expr).expect("runtime error");
+        assert_eq!(value.borrow().to_string(), "42");
+    }
+
+    #[test]
+    fn restore_rolls_back_to_checkpoint() {
+        fn run(interpreter: Interpreter, source: &str) -> Interpreter {
+            let mut scanner = Scanner::new(String::from(source));
+            let tokens = scanner.scan_tokens();
+            let stmts = Parser::new(tokens).parse().expect("parse error");
+            let mut resolver = Resolver::new(interpreter);
+            resolver.resolve_stmts(&stmts).expect("resolve error");
+            let mut interpreter = resolver.interpreter;
+            interpreter.interpret_all(&stmts);
+            interpreter
+        }
+
+        let interpreter = interpret(
+            "var count = 1; var xs = [1, 2]; var alias = xs; \
+             class Point {} var p = Point(); p.x = 1;",
+        );
+        let checkpoint = interpreter.checkpoint();
+        let mut interpreter = run(
+            interpreter,
+            "count = 2; xs[0] = 9; p.x = 5; var added = true; var shared = false;",
+        );
+        interpreter.restore(&checkpoint);
+        assert_eq!(global(&interpreter, "count"), "1");
+        assert_eq!(global(&interpreter, "xs"), "[1, 2]");
+        assert_eq!(global(&interpreter, "p"), "Point instance");
+        assert!(interpreter.globals.borrow().lookup("added").is_none());
+
+        let mut interpreter = run(interpreter, "alias[1] = 7; var x = p.x; count = 3;");
+        assert_eq!(global(&interpreter, "xs"), "[1, 7]");
+        assert_eq!(global(&interpreter, "x"), "1");
+        interpreter.restore(&checkpoint);
+        assert_eq!(global(&interpreter, "count"), "1");
+        assert_eq!(global(&interpreter, "alias"), "[1, 2]");
+    }
+
+    #[test]
+    fn prints_escaped_tab() {
+        let mut scanner = Scanner::new(String::from(r#"print "a\tb";"#));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let out = SharedBuffer::default();
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(SharedBuffer::default()));
+        interpreter.interpret_all(&stmts);
+        assert_eq!(out.contents(), "a\tb\n");
+    }
+
+    #[test]
+    fn prints_hex_and_binary_literals() {
+        let mut scanner = Scanner::new(String::from("print 0xff; print 0b101;"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let out = SharedBuffer::default();
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(SharedBuffer::default()));
+        interpreter.interpret_all(&stmts);
+        assert_eq!(out.contents(), "255\n5\n");
+    }
+
+    #[test]
+    fn prints_exponent_literal() {
+        let mut scanner = Scanner::new(String::from("print 1.5e2;"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let out = SharedBuffer::default();
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(SharedBuffer::default()));
+        interpreter.interpret_all(&stmts);
+        assert_eq!(out.contents(), "150\n");
+    }
+
+    #[test]
+    fn verbose_print_shows_methods_and_parameters() {
+        let source = "class Animal { speak() {} move(distance) {} } class Empty {} \
+                      fun greet(greeting, name) {} \
+                      print Animal; print Empty; print greet; print clock;";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let out = SharedBuffer::default();
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(SharedBuffer::default()));
+        let (definitions, prints) = (stmts[..3].to_vec(), stmts[3..].to_vec());
+        interpreter.interpret_all(&definitions);
+        interpreter.interpret_all(&prints);
+        interpreter.set_verbose(true);
+        interpreter.interpret_all(&prints);
+        assert_eq!(
+            out.contents(),
+            "Animal\nEmpty\nFunction<greet>\nNative Function\n\
+             <class Animal: move, speak>\n<class Empty>\n<fn greet(greeting, name)>\nNative Function\n"
+        );
+    }
+
+    #[test]
+    fn deep_equals_compares_structure() {
+        let interpreter = interpret(
+            "var same = deep_equals([1, [2, \"a\"]], [1, [2, \"a\"]]); \
+             var different = deep_equals([1, [2, 3]], [1, [2, 4]]); \
+             var maps = deep_equals({\"k\": [1]}, {\"k\": [1]}); \
+             class P {} var a = P(); a.x = [1]; var b = P(); b.x = [1]; \
+             var instances = deep_equals(a, b); var identical = a == b; \
+             a.self = a; b.self = b; var cycles = deep_equals(a, b); \
+             b.x = [2]; var fields = deep_equals(a, b); \
+             var primitives = deep_equals(1, \"1\");",
+        );
+        assert_eq!(global(&interpreter, "same"), "true");
+        assert_eq!(global(&interpreter, "different"), "false");
+        assert_eq!(global(&interpreter, "maps"), "true");
+        assert_eq!(global(&interpreter, "instances"), "true");
+        assert_eq!(global(&interpreter, "identical"), "false");
+        assert_eq!(global(&interpreter, "cycles"), "true");
+        assert_eq!(global(&interpreter, "fields"), "false");
+        assert_eq!(global(&interpreter, "primitives"), "false");
+    }
+
+    #[test]
+    fn debug_quotes_strings() {
+        let source = "print \"a\"; debug(\"a\"); debug(5); debug([\"x\ny\", 1]);";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let out = SharedBuffer::default();
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(SharedBuffer::default()));
+        interpreter.interpret_all(&stmts);
+        assert_eq!(out.contents(), "a\n\"a\"\n5\n[\"x\\ny\", 1]\n");
+    }
+
+    #[test]
+    fn native_output_is_ordered_with_print() {
+        let source = "print \"before\"; clock(); debug(\"middle\"); print \"after\";";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let out = SharedBuffer::default();
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(SharedBuffer::default()));
+        interpreter.interpret_all(&stmts);
+        let contents = out.contents();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "before");
+        assert!(lines[1].parse::<u128>().is_ok());
+        assert_eq!(lines[2..], ["\"middle\"", "after"]);
+    }
+
+    #[test]
+    fn instances_concatenate_with_to_string() {
+        let interpreter = interpret(
+            "class User { toString() { return \"<\" + this.name + \">\"; } } \
+             class Point {} \
+             var user = User(); user.name = \"ada\"; \
+             var before = \"user: \" + user; var after = user + \"!\"; \
+             var failed = true; failed = \"point: \" + Point();",
+        );
+        assert_eq!(global(&interpreter, "before"), "user: <ada>");
+        assert_eq!(global(&interpreter, "after"), "<ada>!");
+        assert_eq!(global(&interpreter, "failed"), "true");
+    }
+
+    #[test]
+    fn reads_globals_after_running() {
+        let interpreter = interpret("var result = 42;");
+        let result = interpreter.get_global("result").expect("undefined");
+        assert_eq!(*result.borrow(), Object::Number(42.0));
+        assert!(interpreter.get_global("missing").is_none());
+    }
+
+    #[test]
+    fn property_of_nil_is_named_in_error() {
+        let source = "var a; print a.x; print 1.y;";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+
+        let mut interpreter = Interpreter::new();
+        interpreter.visit_stmt(&stmts[0]).expect("runtime error");
+        let error = interpreter.visit_stmt(&stmts[1]).unwrap_err();
+        assert_eq!(error.message, "Cannot read property 'x' of nil.");
+        assert_eq!(error.token.lexeme, "x");
+        let error = interpreter.visit_stmt(&stmts[2]).unwrap_err();
+        assert_eq!(
+            error.message,
+            "Only instances have properties, so 'y' can't be read."
+        );
+    }
+
+    #[test]
+    fn top_level_return_is_an_error() {
+        // The resolver would reject this, so the statements skip it.
+        let source = "print 1; return 2; print 3;";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+
+        let error = Interpreter::new().interpret(&stmts).unwrap_err();
+        assert_eq!(error.message, "Cannot return from top-level code.");
+        assert_eq!(error.token.lexeme, "return");
+
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(err.clone()));
+        interpreter.interpret_all(&stmts);
+        assert_eq!(out.contents(), "1\n3\n");
+        assert!(err
+            .contents()
+            .contains("Cannot return from top-level code."));
+    }
+
+    #[test]
+    fn large_integer_literals_are_exact() {
+        let source = "print 16777217; print 16777217 + 2;";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let out = SharedBuffer::default();
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(SharedBuffer::default()));
+        interpreter.interpret_all(&stmts);
+        assert_eq!(out.contents(), "16777217\n16777219\n");
+    }
+
+    #[test]
+    fn output_and_errors_use_separate_writers() {
+        let source = "print \"hello\";\nprint missing;";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(err.clone()));
+        interpreter.interpret_all(&stmts);
+        assert_eq!(out.contents(), "hello\n");
+        assert_eq!(
+            err.contents(),
+            "[line 2] Error missing: Get: Undefined variable 'missing'.\n"
+        );
+    }
+
+    #[test]
+    fn functions_equal_by_identity() {
+        let interpreter = interpret(
+            "fun f() {} fun g() {} var h = f; \
+             var same = f == f; var alias = f == h; var different = f != g; \
+             fun make() { fun inner() {} return inner; } \
+             var closures = make() != make();",
+        );
+        assert_eq!(global(&interpreter, "same"), "true");
+        assert_eq!(global(&interpreter, "alias"), "true");
+        assert_eq!(global(&interpreter, "different"), "true");
+        assert_eq!(global(&interpreter, "closures"), "true");
+    }
+
+    #[test]
+    fn eval_native() {
+        let interpreter = interpret(
+            "var x = 40; var sum = eval(\"1 + 2\"); var read = eval(\"x + 2\"); \
+             fun f() { var local = 5; return eval(\"local * 2\"); } var scoped = f(); \
+             eval(\"var y = 7;\"); var failed = \"unset\"; failed = eval(\"1 +\");",
+        );
+        assert_eq!(global(&interpreter, "sum"), "3");
+        assert_eq!(global(&interpreter, "read"), "42");
+        assert_eq!(global(&interpreter, "scoped"), "10");
+        assert_eq!(global(&interpreter, "y"), "7");
+        assert_eq!(global(&interpreter, "failed"), "unset");
+    }
+
+    #[test]
+    fn cyclic_instances_display_and_clone() {
+        let interpreter = interpret(
+            "class Node {} var a = Node(); var b = Node(); \
+             a.name = \"a\"; b.name = \"b\"; a.next = b; b.next = a;",
+        );
+        let token = Token {
+            token_type: TokenType::Identifier,
+            lexeme: String::from("a"),
+            literal: None,
+            line: 0,
+            offset: 0,
+            column: 0,
+            end_column: 0,
+        };
+        let a = interpreter.globals.borrow().get(token).unwrap();
+        assert_eq!(
+            a.borrow().verbose(),
+            "Node { name: \"a\", next: Node { name: \"b\", next: Node {...} } }"
+        );
+
+        let copy = deep_clone(&a);
+        assert_eq!(copy.borrow().verbose(), a.borrow().verbose());
+        assert_ne!(*copy.borrow(), *a.borrow());
+        let copy = match &*copy.borrow() {
+            Object::Instance(i) => i.clone(),
+            _ => panic!("expected an instance"),
+        };
+        let next = Rc::clone(&copy.fields()[1].1);
+        let back = match &*next.borrow() {
+            Object::Instance(b) => Rc::clone(&b.fields()[1].1),
+            _ => panic!("expected an instance"),
+        };
+        assert_eq!(*back.borrow(), Object::Instance(copy));
+    }
+
+    #[test]
+    fn interpret_returns_last_value() {
+        let mut scanner = Scanner::new(String::from("1; 2; 3;"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.interpret(&stmts).unwrap(), Object::Number(3.0));
+
+        let mut scanner = Scanner::new(String::from("1; missing; 3;"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let error = interpreter.interpret(&stmts).unwrap_err();
+        assert_eq!(error.token.lexeme, "missing");
+    }
+
+    #[test]
+    fn labeled_break_and_continue() {
+        let interpreter = interpret(
+            "var pairs = 0; var skipped = 0; \
+             outer: for (var i = 0; i < 3; i = i + 1) { \
+                 var j = 0; \
+                 inner: while (j < 3) { \
+                     j = j + 1; \
+                     if (j == 2) continue inner; \
+                     if (i == 1) { skipped = skipped + 1; continue outer; } \
+                     if (i == 2) break outer; \
+                     pairs = pairs + 1; \
+                 } \
+             }",
+        );
+        assert_eq!(global(&interpreter, "pairs"), "2");
+        assert_eq!(global(&interpreter, "skipped"), "1");
+    }
+
+    #[test]
+    fn failed_assert_reports_line() {
+        let mut scanner = Scanner::new(String::from("assert(true);\nvar x = 1;\nassert(x == 2);"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let error = Interpreter::new().interpret(&stmts).unwrap_err();
+        assert_eq!(error.message, "Assertion failed at line 3.");
+    }
+
+    #[test]
+    fn nil_coalescing() {
+        let interpreter = interpret(
+            "var a = nil ?? 5; var b = 3 ?? 5; var c = false ?? 5; \
+             var calls = 0; fun f() { calls = calls + 1; return 1; } var d = 2 ?? f();",
+        );
+        assert_eq!(global(&interpreter, "a"), "5");
+        assert_eq!(global(&interpreter, "b"), "3");
+        assert_eq!(global(&interpreter, "c"), "false");
+        assert_eq!(global(&interpreter, "calls"), "0");
+    }
+
+    #[test]
+    fn script_arguments() {
+        let mut scanner = Scanner::new(String::from("var first = args[0]; var second = args[1];"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut interpreter = Interpreter::new();
+        interpreter.define_args(&[String::from("one"), String::from("two")]);
+        let mut resolver = Resolver::new(interpreter);
+        resolver.resolve_stmts(&stmts).expect("resolve error");
+        let mut interpreter = resolver.interpreter;
+        interpreter.interpret_all(&stmts);
+        assert_eq!(global(&interpreter, "first"), "one");
+        assert_eq!(global(&interpreter, "second"), "two");
+    }
+
+    #[test]
+    fn safe_navigation() {
+        let interpreter = interpret(
+            "class Node {} var a = Node(); a.b = Node(); a.b.c = 3; var present = a?.b?.c; \
+             var missing = nil; var absent = missing?.b?.c; a.b = nil; var cut = a?.b?.c;",
+        );
+        assert_eq!(global(&interpreter, "present"), "3");
+        assert_eq!(global(&interpreter, "absent"), "nil");
+        assert_eq!(global(&interpreter, "cut"), "nil");
     }
 }
@@ -0,0 +1,124 @@
+#![allow(
+    clippy::result_large_err,
+    clippy::mutable_key_type,
+    clippy::large_enum_variant
+)]
+
+pub mod ast;
+pub mod class;
+pub mod cycle;
+pub mod enumeration;
+pub mod environment;
+pub mod error;
+pub mod function;
+pub mod generator;
+pub mod instance;
+pub mod interpreter;
+pub mod parser;
+pub mod resolver;
+pub mod scanner;
+pub mod stmt;
+pub mod token;
+
+use crate::error::{LoxError, RuntimeError};
+use crate::interpreter::Interpreter;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
+use crate::token::{Token, TokenType};
+
+pub fn report(line: usize, at: &str, message: &str) {
+    println!("{}", report_message(line, at, message));
+}
+
+pub fn report_message(line: usize, at: &str, message: &str) -> String {
+    format!("[line {line}] Error {at}: {message}")
+}
+
+pub fn lox_error(token: &Token, message: &str) {
+    if token.token_type == TokenType::Eof {
+        report(token.line, " at end", message);
+    } else {
+        let at = " at ".to_owned() + &token.lexeme + "'";
+        report(token.line, &at, message);
+    }
+}
+
+pub fn lox_runtime_error(_error: RuntimeError, had_runtime_error: &mut bool) {
+    *had_runtime_error = true;
+}
+
+/// Returns the source line containing `token`, followed by a line with a caret
+/// under the token's first character.
+pub fn snippet(source: &str, token: &Token) -> String {
+    // the token's offset counts characters, but slicing needs bytes
+    let offset = source
+        .char_indices()
+        .nth(token.offset)
+        .map_or(source.len(), |(i, _)| i);
+    let start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let end = source[offset..]
+        .find('\n')
+        .map_or(source.len(), |i| offset + i);
+    let column = source[start..offset].chars().count();
+    format!("{}\n{}^", &source[start..end], " ".repeat(column))
+}
+
+/// Scans, parses and resolves `source` without running it, returning what
+/// went wrong. Every scanning error is returned, and parsing is skipped if
+/// there are any. Resolving stops at the first error, and is skipped if the
+/// program doesn't parse.
+pub fn check(source: &str) -> Vec<LoxError> {
+    let mut scanner = Scanner::new(String::from(source));
+    scanner.scan_tokens();
+    if !scanner.errors.is_empty() {
+        return scanner.errors.into_iter().map(LoxError::Syntax).collect();
+    }
+    let tokens = &scanner.tokens;
+    let stmts = match Parser::new(tokens).parse() {
+        Ok(stmts) => stmts,
+        Err(e) => return vec![LoxError::Syntax(e)],
+    };
+    let mut resolver = Resolver::new(Interpreter::new());
+    match resolver.resolve_stmts(&stmts) {
+        Ok(()) => Vec::new(),
+        Err(e) => vec![LoxError::Resolve(e)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snippet_handles_non_ascii_source() {
+        fn snippet_of_x(source: &str) -> String {
+            let mut scanner = Scanner::new(String::from(source));
+            let tokens = scanner.scan_tokens();
+            let x = tokens.iter().find(|token| token.lexeme == "x").unwrap();
+            snippet(source, x)
+        }
+
+        assert_eq!(
+            snippet_of_x("var s=\"ééé\";x;"),
+            "var s=\"ééé\";x;\n            ^"
+        );
+        assert_eq!(snippet_of_x("var s=\"ééééé\"; print 1;\nx;"), "x;\n^");
+    }
+
+    #[test]
+    fn check_reports_without_running() {
+        let errors = check("print nil + 1;\n{ var a = a; }");
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], LoxError::Resolve(_)));
+        assert_eq!(
+            errors[0].report(),
+            "[line 2] Error a: Can't read local variable in its own initializer."
+        );
+
+        let errors = check("print (1;");
+        assert!(matches!(&errors[..], [LoxError::Syntax(_)]));
+        assert!(check("fun f() { return 1; } print f();").is_empty());
+        assert_eq!(check("print @ + #;").len(), 2);
+    }
+}
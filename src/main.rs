@@ -1,48 +1,68 @@
 use std::env;
 use std::fs;
-use std::io;
+use std::io::{self, BufRead};
+use std::mem;
 use std::process;
-use std::usize;
-
-mod ast;
-mod class;
-mod environment;
-mod error;
-mod function;
-mod instance;
-mod interpreter;
-mod parser;
-mod resolver;
-mod scanner;
-mod stmt;
-mod token;
-use token::Token;
-use token::TokenType;
-
-use crate::ast::AstPrinter;
-use crate::error::RuntimeError;
-use crate::interpreter::Interpreter;
-use crate::parser::Parser;
-use crate::resolver::Resolver;
-use crate::scanner::Scanner;
+
+use rlox::ast::AstPrinter;
+use rlox::error::LoxError;
+use rlox::interpreter::Interpreter;
+use rlox::parser::Parser;
+use rlox::resolver::Resolver;
+use rlox::scanner::Scanner;
+use rlox::token::write_tokens;
 
 fn main() {
     let mut had_error = false;
     let mut had_runtime_error = false;
     let args: Vec<String> = env::args().collect();
     let debug_mode = env::var("DEBUG").is_ok();
-    if args.len() > 2 {
-        println!("Usage: rlox [script]");
-    } else if args.len() == 2 {
-        run_file(&args[1], &mut had_error, &mut had_runtime_error, debug_mode);
+    if args.len() >= 2 && args[1] == "--check" {
+        match args.get(2) {
+            Some(path) => check_file(path),
+            None => {
+                eprintln!("Usage: rlox --check [script]");
+                process::exit(64);
+            }
+        }
+    } else if args.len() >= 2 {
+        run_file(
+            &args[1],
+            &args[2..],
+            &mut had_error,
+            &mut had_runtime_error,
+            debug_mode,
+        );
     } else {
-        run_prompt(&mut had_error, &mut had_runtime_error, debug_mode);
+        let mut interpreter = Interpreter::new();
+        run_prompt(
+            &mut io::stdin().lock(),
+            &mut interpreter,
+            &mut had_error,
+            &mut had_runtime_error,
+            debug_mode,
+        );
     }
 }
 
-fn run_file(path: &str, had_error: &mut bool, had_runtime_error: &mut bool, debug_mode: bool) {
+fn run_file(
+    path: &str,
+    args: &[String],
+    had_error: &mut bool,
+    had_runtime_error: &mut bool,
+    debug_mode: bool,
+) {
     let contents = fs::read_to_string(path).expect("Should have been able to read the file");
-    run(&contents, had_error, had_runtime_error, debug_mode);
+    let mut interpreter = Interpreter::new();
+    interpreter.define_args(args);
+    run(
+        &contents,
+        &mut interpreter,
+        had_error,
+        had_runtime_error,
+        debug_mode,
+        false,
+    );
     if *had_error {
         process::exit(65);
     }
@@ -51,64 +71,308 @@ fn run_file(path: &str, had_error: &mut bool, had_runtime_error: &mut bool, debu
     }
 }
 
-fn run_prompt(had_error: &mut bool, had_runtime_error: &mut bool, debug_mode: bool) {
+fn check_file(path: &str) {
+    let contents = fs::read_to_string(path).expect("Should have been able to read the file");
+    let errors = rlox::check(&contents);
+    for error in &errors {
+        eprintln!("{}", error.report());
+    }
+    if !errors.is_empty() {
+        process::exit(65);
+    }
+}
+
+// The interpreter is kept between lines, so definitions stay until `:reset`.
+fn run_prompt(
+    input: &mut dyn BufRead,
+    interpreter: &mut Interpreter,
+    had_error: &mut bool,
+    had_runtime_error: &mut bool,
+    debug_mode: bool,
+) {
     loop {
         let mut prompt = String::new();
         println!("> ");
-        io::stdin()
-            .read_line(&mut prompt)
-            .expect("failed to read line");
+        input.read_line(&mut prompt).expect("failed to read line");
         prompt = prompt.trim().to_string();
-        if prompt == "exit" || prompt == "" {
+        if prompt == "exit" || prompt.is_empty() {
             break;
         }
-        run(prompt.as_str(), had_error, had_runtime_error, debug_mode);
+        if prompt == ":reset" {
+            interpreter.reset();
+            writeln!(interpreter.out(), "Cleared all definitions.")
+                .expect("Failed to write output");
+            continue;
+        }
+        run(
+            prompt.as_str(),
+            interpreter,
+            had_error,
+            had_runtime_error,
+            debug_mode,
+            true,
+        );
         *had_error = false;
     }
 }
 
-fn run(source: &str, had_error: &mut bool, _had_runtime_error: &mut bool, debug_mode: bool) {
+fn run(
+    source: &str,
+    interpreter: &mut Interpreter,
+    had_error: &mut bool,
+    had_runtime_error: &mut bool,
+    debug_mode: bool,
+    repl: bool,
+) {
     let mut scanner = Scanner::new(String::from(source));
-    let tokens = scanner.scan_tokens();
+    scanner.scan_tokens();
+    if !scanner.errors.is_empty() {
+        for e in scanner.errors {
+            writeln!(interpreter.err(), "{}", LoxError::Syntax(e).report())
+                .expect("Failed to write error");
+        }
+        *had_error = true;
+        return;
+    }
+    let tokens = &scanner.tokens;
+    if debug_mode {
+        write_tokens(interpreter.out(), tokens).expect("Failed to write output");
+    }
     let mut parser = Parser::new(tokens);
-    let stmts = parser.parse();
+    let stmts = if repl {
+        parser.parse_repl()
+    } else {
+        parser.parse()
+    };
     match stmts {
         Ok(stmts) => {
-            let mut interpreter = Interpreter::new();
+            interpreter.set_source(source);
             if debug_mode {
                 let mut ast_printer = AstPrinter;
                 ast_printer.print(stmts.clone());
             }
-            let mut resolver = Resolver::new(interpreter);
-            if let Err(_e) = resolver.resolve_stmts(&stmts) {
+            let mut resolver = Resolver::new(mem::take(interpreter));
+            let resolved = resolver.resolve_stmts(&stmts);
+            *interpreter = resolver.interpreter;
+            if let Err(e) = resolved {
+                writeln!(interpreter.err(), "{}", LoxError::Resolve(e).report())
+                    .expect("Failed to write error");
                 *had_error = true;
+                return;
+            }
+            if !interpreter.interpret_all(&stmts) {
+                *had_runtime_error = true;
             }
-            interpreter = resolver.interpreter;
-            interpreter.interpret(&stmts);
         }
-        Err(_e) => {
+        Err(e) => {
+            writeln!(interpreter.err(), "{}", LoxError::Syntax(e).report())
+                .expect("Failed to write error");
             *had_error = true;
         }
     }
 }
 
-fn error(line: usize, message: &str) {
-    report(line, &"", message);
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rlox::token::{Token, TokenType};
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::rc::Rc;
 
-pub fn report(line: usize, at: &str, message: &str) {
-    println!("[line {line}] Error {at}: {message}");
-}
+    // A writer whose contents can still be read after it is handed over.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
 
-pub fn lox_error(token: &Token, message: &str) {
-    if token.token_type == TokenType::Eof {
-        report(token.line, " at end", message);
-    } else {
-        let at = " at ".to_owned() + &token.lexeme + "'";
-        report(token.line, &at, message);
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn is_defined(interpreter: &Interpreter, name: &str) -> bool {
+        let token = Token {
+            token_type: TokenType::Identifier,
+            lexeme: String::from(name),
+            literal: None,
+            line: 0,
+            offset: 0,
+            column: 0,
+            end_column: 0,
+        };
+        let defined = interpreter.globals.borrow().get(token).is_ok();
+        defined
+    }
+
+    #[test]
+    fn reset_clears_definitions() {
+        let mut interpreter = Interpreter::with_writers(Box::new(io::sink()), Box::new(io::sink()));
+        let (mut had_error, mut had_runtime_error) = (false, false);
+        run_prompt(
+            &mut "var x = 1;\n".as_bytes(),
+            &mut interpreter,
+            &mut had_error,
+            &mut had_runtime_error,
+            false,
+        );
+        assert!(is_defined(&interpreter, "x"));
+        run_prompt(
+            &mut ":reset\n".as_bytes(),
+            &mut interpreter,
+            &mut had_error,
+            &mut had_runtime_error,
+            false,
+        );
+        assert!(!is_defined(&interpreter, "x"));
+        assert!(is_defined(&interpreter, "clock"));
+    }
+
+    #[test]
+    fn repl_lines_resolve_separately() {
+        // `a` is at the same line and column in both functions, but one is
+        // a scope further out in `f`
+        let out = SharedBuffer::default();
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(io::sink()));
+        let (mut had_error, mut had_runtime_error) = (false, false);
+        run_prompt(
+            &mut "fun f(a){ {return a;} }\nfun g(a){ return  a;   }\nprint f(1);\nprint g(2);\n"
+                .as_bytes(),
+            &mut interpreter,
+            &mut had_error,
+            &mut had_runtime_error,
+            false,
+        );
+        let output = String::from_utf8(out.0.borrow().clone()).unwrap();
+        assert_eq!(output, "1\n2\n");
     }
-}
 
-pub fn lox_runtime_error(_error: RuntimeError, had_runtime_error: &mut bool) {
-    *had_runtime_error = true;
+    #[test]
+    fn runtime_error_leaves_repl_usable() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(err.clone()));
+        let (mut had_error, mut had_runtime_error) = (false, false);
+        run_prompt(
+            &mut "x + 1;\nprint 2;\n".as_bytes(),
+            &mut interpreter,
+            &mut had_error,
+            &mut had_runtime_error,
+            false,
+        );
+        let errors = String::from_utf8(err.0.borrow().clone()).unwrap();
+        assert!(errors.contains("Undefined variable 'x'."));
+        let output = String::from_utf8(out.0.borrow().clone()).unwrap();
+        assert_eq!(output, "2\n");
+        assert!(is_defined(&interpreter, "clock"));
+    }
+
+    #[test]
+    fn resolve_errors_stop_the_run() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(err.clone()));
+        let (mut had_error, mut had_runtime_error) = (false, false);
+        run(
+            "break; print 1;",
+            &mut interpreter,
+            &mut had_error,
+            &mut had_runtime_error,
+            false,
+            false,
+        );
+        assert!(had_error);
+        assert!(out.0.borrow().is_empty());
+        // no runtime error follows the resolver's
+        assert_eq!(
+            String::from_utf8_lossy(&err.0.borrow()),
+            "[line 1] Error break: Can't use 'break' outside of a loop.\n"
+        );
+        assert!(is_defined(&interpreter, "clock"));
+    }
+
+    #[test]
+    fn parse_errors_go_to_the_error_writer() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(err.clone()));
+        let (mut had_error, mut had_runtime_error) = (false, false);
+        run(
+            "print 1;\nprint (1;",
+            &mut interpreter,
+            &mut had_error,
+            &mut had_runtime_error,
+            false,
+            false,
+        );
+        assert!(had_error);
+        assert!(out.0.borrow().is_empty());
+        assert_eq!(
+            String::from_utf8_lossy(&err.0.borrow()),
+            "[line 2] Error ;: Expect ')' after expression.\n"
+        );
+    }
+
+    #[test]
+    fn scanning_errors_set_the_error_flag() {
+        let (out, err) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(err.clone()));
+        let (mut had_error, mut had_runtime_error) = (false, false);
+        run(
+            "print 1; @#$",
+            &mut interpreter,
+            &mut had_error,
+            &mut had_runtime_error,
+            false,
+            false,
+        );
+        assert!(had_error);
+        // nothing runs once scanning has failed
+        assert!(out.0.borrow().is_empty());
+        let errors = String::from_utf8(err.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = errors.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "[line 1] Error @: Unexpected character.",
+                "[line 1] Error #: Unexpected character.",
+                "[line 1] Error $: Unexpected character."
+            ]
+        );
+    }
+
+    #[test]
+    fn debug_mode_dumps_tokens() {
+        let out = SharedBuffer::default();
+        let mut interpreter =
+            Interpreter::with_writers(Box::new(out.clone()), Box::new(io::sink()));
+        let (mut had_error, mut had_runtime_error) = (false, false);
+        run(
+            "var x = 1;\nprint x;",
+            &mut interpreter,
+            &mut had_error,
+            &mut had_runtime_error,
+            true,
+            false,
+        );
+        let contents = String::from_utf8(out.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(
+            lines[..4],
+            [
+                "Var 'var' 1:0",
+                "Identifier 'x' 1:4",
+                "Equal '=' 1:6",
+                "Number '1' 1:8"
+            ]
+        );
+        assert!(lines.contains(&"Print 'print' 2:0"));
+        assert!(lines.contains(&"Eof '' 2:8"));
+        assert_eq!(lines.last(), Some(&"1"));
+    }
 }
@@ -1,106 +1,268 @@
-use crate::ast::Expr;
+use crate::ast::{Expr, ExprId, Span};
 use crate::error::SyntaxError;
 use crate::interpreter::Object;
-use crate::lox_error;
 use crate::stmt::Stmt;
 use crate::token::{Token, TokenType};
+
+/// Default limit on the number of parameters and call arguments.
+pub const MAX_PARAMS: usize = 255;
+
+/// Default limit on how deeply expressions may nest.
+pub const MAX_DEPTH: usize = 48;
+
+/// The methods, getters and setters of a class body.
+type ClassBody = (Vec<(Token, Expr)>, Vec<Stmt>, Vec<Stmt>, Vec<Stmt>);
+
 pub struct Parser<'a> {
     pub tokens: &'a Vec<Token>,
     pub current: usize,
+    pub max_params: usize,
+    pub max_depth: usize,
+    depth: usize,
+    error: Option<SyntaxError>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(tokens: &'a Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            current: 0,
+            max_params: MAX_PARAMS,
+            max_depth: MAX_DEPTH,
+            depth: 0,
+            error: None,
+        }
+    }
+
+    /// Creates a parser accepting up to `max_params` parameters or arguments,
+    /// for generated code that needs more than the default.
+    pub fn with_max_params(tokens: &'a Vec<Token>, max_params: usize) -> Self {
+        Self {
+            max_params,
+            ..Self::new(tokens)
+        }
     }
 
     pub fn parse(&mut self) -> Result<Vec<Stmt>, SyntaxError> {
         let mut statements: Vec<Stmt> = Vec::new();
         while !self.is_at_end() {
             let declaration = self.declaration();
-            match declaration {
-                Some(d) => {
-                    statements.push(d);
-                }
-                None => {}
+            if let Some(d) = declaration {
+                statements.push(d);
             }
         }
-        Ok(statements)
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(statements),
+        }
+    }
+
+    /// Parses REPL input. Input that isn't terminated by `;` or `}` is taken
+    /// as a bare expression and printed, so `1 + 2` shows `3`.
+    pub fn parse_repl(&mut self) -> Result<Vec<Stmt>, SyntaxError> {
+        if self.ends_with_statement() {
+            return self.parse();
+        }
+        let expr = self.parse_expression()?;
+        let span = expr.span();
+        Ok(vec![Stmt::Print(expr, span)])
+    }
+
+    /// Returns whether the input ends like a statement, with `;` or `}`.
+    pub fn ends_with_statement(&self) -> bool {
+        self.tokens.len() < 2
+            || matches!(
+                self.tokens[self.tokens.len() - 2].token_type,
+                TokenType::Semicolon | TokenType::RightBrace
+            )
+    }
+
+    /// Parses the whole input as a single expression.
+    pub fn parse_expression(&mut self) -> Result<Expr, SyntaxError> {
+        let expr = self.expression()?;
+        if !self.is_at_end() {
+            return Err(SyntaxError::new(
+                self.peek().clone(),
+                "Expect end of input after expression.",
+            ));
+        }
+        Ok(expr)
     }
 
     fn expression(&mut self) -> Result<Expr, SyntaxError> {
-        self.assignment()
+        self.nested(Self::comma)
     }
 
-    fn assignment(&mut self) -> Result<Expr, SyntaxError> {
-        let expr = self.or()?;
-        if self.matches(&vec![TokenType::Equal]) {
-            let equals = self.previous();
-            let value = self.assignment()?;
-            let v = value.clone();
-
-            match expr {
-                Expr::Variable { name } => {
-                    return Ok(Expr::Assign {
-                        name: name.clone(),
-                        value: Box::new(v),
-                    });
-                }
-                Expr::Get { object, name } => {
-                    return Ok(Expr::Set {
-                        object,
-                        name: name.clone(),
-                        value: Box::new(v),
-                    });
-                }
-                _ => {
-                    return Err(SyntaxError::new(
-                        equals.clone(),
-                        "Invalid assignment target.",
-                    ));
-                }
+    // A single expression, for places where commas separate expressions.
+    fn element(&mut self) -> Result<Expr, SyntaxError> {
+        self.nested(Self::assignment)
+    }
+
+    // Each level from here down to `primary` is on the stack once for every
+    // level of nesting, so it keeps only its operand in its frame and leaves
+    // the rest to a helper. Debug builds give each temporary a slot of its
+    // own, and this is what lets `MAX_DEPTH` levels fit on a default-size
+    // thread.
+    fn comma(&mut self) -> Result<Expr, SyntaxError> {
+        self.assignment().and_then(|expr| {
+            if self.check(&TokenType::Comma) {
+                self.sequence(expr)
+            } else {
+                Ok(expr)
             }
+        })
+    }
+
+    fn sequence(&mut self, first: Expr) -> Result<Expr, SyntaxError> {
+        let start = first.span();
+        let mut expressions = vec![first];
+        while self.matches(&vec![TokenType::Comma]) {
+            expressions.push(self.assignment()?);
         }
-        Ok(expr)
+        let span = start.to(self.span_of_previous());
+        Ok(Expr::Comma { expressions, span })
     }
 
-    fn or(&mut self) -> Result<Expr, SyntaxError> {
-        let mut expr = self.and()?;
+    // Guards recursion so that deeply nested input is reported instead of
+    // overflowing the stack here or in the resolver and interpreter.
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Expr, SyntaxError>,
+    ) -> Result<Expr, SyntaxError> {
+        if self.depth >= self.max_depth {
+            return Err(SyntaxError::new(
+                self.peek().clone(),
+                "Expression too deeply nested.",
+            ));
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
 
-        while self.matches(&vec![TokenType::Or]) {
-            let operator = self.previous();
-            let right = self.and()?;
-            expr = Expr::Logical {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
+    fn assignment(&mut self) -> Result<Expr, SyntaxError> {
+        self.coalesce().and_then(|expr| {
+            if self.matches(&vec![TokenType::Equal]) {
+                self.assign_to(expr)
+            } else {
+                Ok(expr)
+            }
+        })
+    }
+
+    fn assign_to(&mut self, target: Expr) -> Result<Expr, SyntaxError> {
+        let equals = self.previous();
+        let value = self.nested(Self::assignment)?;
+        let span = target.span().to(value.span());
+        let value = Box::new(value);
+        match target {
+            Expr::Variable { name, .. } => Ok(Expr::Assign {
+                id: ExprId::fresh(),
+                name,
+                value,
+                span,
+            }),
+            Expr::Get { object, name, .. } => Ok(Expr::Set {
+                object,
+                name,
+                value,
+                span,
+            }),
+            Expr::Index {
+                object,
+                bracket,
+                index,
+                ..
+            } => Ok(Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+                span,
+            }),
+            Expr::This { keyword, .. } => {
+                Err(SyntaxError::new(keyword, "Cannot assign to 'this'."))
+            }
+            _ => Err(SyntaxError::new(equals, "Invalid assignment target.")),
         }
-        Ok(expr)
+    }
+
+    fn coalesce(&mut self) -> Result<Expr, SyntaxError> {
+        self.left_assoc(&[TokenType::QuestionQuestion], Self::or, Self::logical_expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, SyntaxError> {
+        self.left_assoc(&[TokenType::Or], Self::and, Self::logical_expr)
     }
 
     fn and(&mut self) -> Result<Expr, SyntaxError> {
-        let mut expr = self.equality()?;
+        self.left_assoc(&[TokenType::And], Self::equality, Self::logical_expr)
+    }
 
-        while self.matches(&vec![TokenType::And]) {
-            let operator = self.previous();
-            let right = self.equality()?;
-            expr = Expr::Logical {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
+    // `operand (operator operand)*`, grouped to the left, for the binary and
+    // logical levels.
+    fn left_assoc(
+        &mut self,
+        operators: &[TokenType],
+        operand: fn(&mut Self) -> Result<Expr, SyntaxError>,
+        node: fn(Expr, Token, Expr) -> Expr,
+    ) -> Result<Expr, SyntaxError> {
+        operand(self).and_then(|expr| self.operators(expr, operators, operand, node))
+    }
+
+    fn operators(
+        &mut self,
+        mut expr: Expr,
+        operators: &[TokenType],
+        operand: fn(&mut Self) -> Result<Expr, SyntaxError>,
+        node: fn(Expr, Token, Expr) -> Expr,
+    ) -> Result<Expr, SyntaxError> {
+        while operators.iter().any(|operator| self.check(operator)) {
+            let operator = self.advance();
+            let right = operand(self)?;
+            expr = node(expr, operator, right);
         }
         Ok(expr)
     }
 
     fn declaration(&mut self) -> Option<Stmt> {
+        if self.matches(&vec![TokenType::Private]) {
+            let declared_private = self.private_declaration();
+            match declared_private {
+                Ok(s) => return Some(s),
+                Err(e) => {
+                    self.recover(e);
+                    return None;
+                }
+            }
+        }
+        if self.matches(&vec![TokenType::Import]) {
+            let declared_import = self.import_declaration();
+            match declared_import {
+                Ok(s) => return Some(s),
+                Err(e) => {
+                    self.recover(e);
+                    return None;
+                }
+            }
+        }
+        if self.matches(&vec![TokenType::Enum]) {
+            let declared_enum = self.enum_declaration();
+            match declared_enum {
+                Ok(s) => return Some(s),
+                Err(e) => {
+                    self.recover(e);
+                    return None;
+                }
+            }
+        }
         if self.matches(&vec![TokenType::Class]) {
             let declared_class = self.class_declaration();
             match declared_class {
                 Ok(s) => return Some(s),
-                Err(_) => {
-                    self.synchronize();
+                Err(e) => {
+                    self.recover(e);
                     return None;
                 }
             }
@@ -109,8 +271,8 @@ impl<'a> Parser<'a> {
             let declared_fun = self.function("function");
             match declared_fun {
                 Ok(s) => return Some(s),
-                Err(_) => {
-                    self.synchronize();
+                Err(e) => {
+                    self.recover(e);
                     return None;
                 }
             }
@@ -119,8 +281,8 @@ impl<'a> Parser<'a> {
             let declared_var = self.var_declaration();
             match declared_var {
                 Ok(s) => return Some(s),
-                Err(_) => {
-                    self.synchronize();
+                Err(e) => {
+                    self.recover(e);
                     return None;
                 }
             }
@@ -128,31 +290,174 @@ impl<'a> Parser<'a> {
         let stmt = self.statement();
         match stmt {
             Ok(s) => Some(s),
-            Err(_) => {
-                self.synchronize();
+            Err(e) => {
+                self.recover(e);
                 None
             }
         }
     }
 
+    fn private_declaration(&mut self) -> Result<Stmt, SyntaxError> {
+        let keyword = self.previous();
+        let declaration = if self.matches(&vec![TokenType::Class]) {
+            self.class_declaration()?
+        } else if self.matches(&vec![TokenType::Enum]) {
+            self.enum_declaration()?
+        } else if self.matches(&vec![TokenType::Fun]) {
+            self.function("function")?
+        } else if self.matches(&vec![TokenType::Var]) {
+            self.var_declaration()?
+        } else {
+            return Err(SyntaxError::new(
+                self.peek().clone(),
+                "Expect declaration after 'private'.",
+            ));
+        };
+        Ok(Stmt::Private {
+            span: Span::of(&keyword).to(declaration.span()),
+            keyword,
+            declaration: Box::new(declaration),
+        })
+    }
+
+    fn import_declaration(&mut self) -> Result<Stmt, SyntaxError> {
+        let keyword = self.previous();
+        let path = self.consume(&TokenType::String, "Expect module path after 'import'.")?;
+        if !(self.check(&TokenType::Identifier) && self.peek().lexeme == "as") {
+            return Err(SyntaxError::new(
+                self.peek().clone(),
+                "Expect 'as' after module path.",
+            ));
+        }
+        self.advance();
+        let name = self.consume(&TokenType::Identifier, "Expect module name after 'as'.")?;
+        self.consume(&TokenType::Semicolon, "Expect ';' after import.")?;
+        Ok(Stmt::Import {
+            span: Span::of(&keyword).to(self.span_of_previous()),
+            keyword,
+            path,
+            name,
+        })
+    }
+
     fn class_declaration(&mut self) -> Result<Stmt, SyntaxError> {
+        let keyword = self.previous();
         let name = self.consume(&TokenType::Identifier, "Expect class name")?;
+        let mut superclass = None;
+        if self.matches(&vec![TokenType::Less]) {
+            let name = self.consume(&TokenType::Identifier, "Expect superclass name.")?;
+            superclass = Some(Expr::Variable {
+                id: ExprId::fresh(),
+                span: Span::of(&name),
+                name,
+            });
+        }
+        let (fields, methods, getters, setters) = self.class_body()?;
+        Ok(Stmt::Class {
+            name,
+            superclass,
+            fields,
+            methods,
+            getters,
+            setters,
+            span: Span::of(&keyword).to(self.span_of_previous()),
+        })
+    }
+
+    fn enum_declaration(&mut self) -> Result<Stmt, SyntaxError> {
+        let keyword = self.previous();
+        let name = self.consume(&TokenType::Identifier, "Expect enum name.")?;
+        self.consume(&TokenType::LeftBrace, "Expect '{' before enum variants.")?;
+        let mut variants = vec![];
+        if !self.check(&TokenType::RightBrace) {
+            loop {
+                let variant = self.consume(&TokenType::Identifier, "Expect variant name.")?;
+                if variants.iter().any(|v: &Token| v.lexeme == variant.lexeme) {
+                    return Err(SyntaxError::new(variant, "Duplicate enum variant."));
+                }
+                variants.push(variant);
+                // a trailing comma is allowed
+                if !self.matches(&vec![TokenType::Comma]) || self.check(&TokenType::RightBrace) {
+                    break;
+                }
+            }
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after enum variants.")?;
+        Ok(Stmt::Enum {
+            name,
+            variants,
+            span: Span::of(&keyword).to(self.span_of_previous()),
+        })
+    }
+
+    fn class_body(&mut self) -> Result<ClassBody, SyntaxError> {
         self.consume(&TokenType::LeftBrace, "Expect '{' before class body.")?;
 
+        let mut fields = Vec::<(Token, Expr)>::new();
         let mut methods = Vec::<Stmt>::new();
+        let mut getters = Vec::<Stmt>::new();
+        let mut setters = Vec::<Stmt>::new();
         while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
-            methods.push(self.function("method")?);
+            // `get` and `set` only act as modifiers when a method name follows
+            if self.check_field() {
+                let name = self.advance();
+                self.advance();
+                let value = self.expression()?;
+                self.consume(&TokenType::Semicolon, "Expect ';' after field value.")?;
+                fields.push((name, value));
+            } else if self.check_accessor("get") {
+                self.advance();
+                let getter = self.function("getter")?;
+                self.check_arity(&getter, 0, "A getter can't take parameters.")?;
+                getters.push(getter);
+            } else if self.check_accessor("set") {
+                self.advance();
+                let setter = self.function("setter")?;
+                self.check_arity(&setter, 1, "A setter must take exactly one parameter.")?;
+                setters.push(setter);
+            } else {
+                methods.push(self.function("method")?);
+            }
         }
         self.consume(&TokenType::RightBrace, "Expect '}' after class body.")?;
-        Ok(Stmt::Class { name, methods })
+        Ok((fields, methods, getters, setters))
+    }
+
+    // A field is a name followed by `=` and its default value.
+    fn check_field(&self) -> bool {
+        self.check(&TokenType::Identifier)
+            && self.tokens[self.current + 1].token_type == TokenType::Equal
+    }
+
+    fn check_accessor(&self, modifier: &str) -> bool {
+        self.check(&TokenType::Identifier)
+            && self.peek().lexeme == modifier
+            && self.tokens[self.current + 1].token_type == TokenType::Identifier
+    }
+
+    fn check_arity(&self, function: &Stmt, arity: usize, message: &str) -> Result<(), SyntaxError> {
+        if let Stmt::Function { name, params, .. } = function {
+            if params.len() != arity {
+                return Err(SyntaxError::new(name.clone(), message));
+            }
+        }
+        Ok(())
     }
 
     fn statement(&mut self) -> Result<Stmt, SyntaxError> {
+        if self.matches(&vec![TokenType::Break, TokenType::Continue]) {
+            return self.jump_statement();
+        }
         if self.matches(&vec![TokenType::If]) {
             return self.if_statement();
         }
+        if self.check(&TokenType::Identifier)
+            && self.tokens[self.current + 1].token_type == TokenType::Colon
+        {
+            return self.labeled_statement();
+        }
         if self.matches(&vec![TokenType::For]) {
-            return self.for_statement();
+            return self.for_statement(None);
         }
         if self.matches(&vec![TokenType::Print]) {
             return self.print_statement();
@@ -161,17 +466,41 @@ impl<'a> Parser<'a> {
             return self.return_statement();
         }
         if self.matches(&vec![TokenType::While]) {
-            return self.while_statement();
+            return self.while_statement(None);
+        }
+        if self.matches(&vec![TokenType::Yield]) {
+            return self.yield_statement();
+        }
+        if self.matches(&vec![TokenType::Defer]) {
+            return self.defer_statement();
         }
         if self.matches(&vec![TokenType::LeftBrace]) {
+            let brace = self.previous();
             return Ok(Stmt::Block {
                 statements: self.block()?,
+                span: Span::of(&brace).to(self.span_of_previous()),
             });
         }
         self.expression_statement()
     }
 
-    fn for_statement(&mut self) -> Result<Stmt, SyntaxError> {
+    fn labeled_statement(&mut self) -> Result<Stmt, SyntaxError> {
+        let label = self.advance();
+        self.advance();
+        if self.matches(&vec![TokenType::For]) {
+            return self.for_statement(Some(label));
+        }
+        if self.matches(&vec![TokenType::While]) {
+            return self.while_statement(Some(label));
+        }
+        Err(SyntaxError::new(
+            self.peek().clone(),
+            "Expect loop after label.",
+        ))
+    }
+
+    fn for_statement(&mut self, label: Option<Token>) -> Result<Stmt, SyntaxError> {
+        let start = Span::of(label.as_ref().unwrap_or(&self.previous()));
         self.consume(&TokenType::LeftParen, "Expect '(' after 'for'.")?;
 
         let initializer;
@@ -195,39 +524,47 @@ impl<'a> Parser<'a> {
         }
         self.consume(&TokenType::RightParen, "Expect ')' after for clauses.")?;
 
-        let mut body = self.statement()?;
-
-        match increment {
-            Some(i) => {
-                body = Stmt::Block {
-                    statements: vec![body, Stmt::Expr(i)],
-                }
-            }
-            None => {}
-        }
-
-        if condition.is_none() {
-            condition = Some(Expr::Literal {
-                value: Object::Bool(true),
-            });
-        }
+        let body = self.statement()?;
 
-        body = Stmt::While {
-            condition: condition.unwrap(),
+        Ok(Stmt::For {
+            label,
+            initializer: initializer.map(Box::new),
+            condition,
+            increment,
+            span: start.to(body.span()),
             body: Box::new(body),
-        };
+        })
+    }
 
-        if let Some(i) = initializer {
-            body = Stmt::Block {
-                statements: vec![i, body],
-            }
+    fn jump_statement(&mut self) -> Result<Stmt, SyntaxError> {
+        let keyword = self.previous();
+        let label = if self.check(&TokenType::Identifier) {
+            Some(self.advance())
+        } else {
+            None
+        };
+        self.consume(
+            &TokenType::Semicolon,
+            &format!("Expect ';' after '{}'.", keyword.lexeme),
+        )?;
+        let span = Span::of(&keyword).to(self.span_of_previous());
+        if keyword.token_type == TokenType::Break {
+            Ok(Stmt::Break {
+                keyword,
+                label,
+                span,
+            })
+        } else {
+            Ok(Stmt::Continue {
+                keyword,
+                label,
+                span,
+            })
         }
-        println!("[PARSER] for body {:?}", body);
-
-        Ok(body)
     }
 
     fn if_statement(&mut self) -> Result<Stmt, SyntaxError> {
+        let keyword = self.previous();
         self.consume(&TokenType::LeftParen, "Expect '(' after 'if'.")?;
         let condition = self.expression()?;
         self.consume(&TokenType::RightParen, "Expect ')' after if condition.")?;
@@ -238,30 +575,70 @@ impl<'a> Parser<'a> {
         if self.matches(&vec![TokenType::Else]) {
             else_branch = Some(Box::new(self.statement()?));
         }
+        let end = else_branch.as_ref().unwrap_or(&then_branch).span();
         Ok(Stmt::If {
             condition,
             then_branch,
             else_branch,
+            span: Span::of(&keyword).to(end),
         })
     }
 
     fn print_statement(&mut self) -> Result<Stmt, SyntaxError> {
+        let keyword = self.previous();
         let value = self.expression()?;
         self.consume(&TokenType::Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::Print(value))
+        Ok(Stmt::Print(
+            value,
+            Span::of(&keyword).to(self.span_of_previous()),
+        ))
     }
 
     fn return_statement(&mut self) -> Result<Stmt, SyntaxError> {
         let keyword = self.previous();
-        let mut value = Expr::Literal { value: Object::Nil };
+        let mut value = Expr::Literal {
+            value: Object::Nil,
+            lexeme: None,
+            span: Span::of(&keyword),
+        };
         if !self.check(&TokenType::Semicolon) {
             value = self.expression()?;
         }
         self.consume(&TokenType::Semicolon, "Expect ';' after return value.")?;
-        Ok(Stmt::Return { keyword, value })
+        Ok(Stmt::Return {
+            span: Span::of(&keyword).to(self.span_of_previous()),
+            keyword,
+            value,
+        })
+    }
+
+    fn defer_statement(&mut self) -> Result<Stmt, SyntaxError> {
+        let keyword = self.previous();
+        let value = self.expression()?;
+        self.consume(
+            &TokenType::Semicolon,
+            "Expect ';' after deferred expression.",
+        )?;
+        Ok(Stmt::Defer {
+            span: Span::of(&keyword).to(self.span_of_previous()),
+            keyword,
+            value,
+        })
+    }
+
+    fn yield_statement(&mut self) -> Result<Stmt, SyntaxError> {
+        let keyword = self.previous();
+        let value = self.expression()?;
+        self.consume(&TokenType::Semicolon, "Expect ';' after yield value.")?;
+        Ok(Stmt::Yield {
+            span: Span::of(&keyword).to(self.span_of_previous()),
+            keyword,
+            value,
+        })
     }
 
     fn var_declaration(&mut self) -> Result<Stmt, SyntaxError> {
+        let keyword = self.previous();
         let name = self.consume(&TokenType::Identifier, "Expect variable name.")?;
         let mut initializer = None;
         if self.matches(&vec![TokenType::Equal]) {
@@ -271,16 +648,59 @@ impl<'a> Parser<'a> {
             &TokenType::Semicolon,
             "Expect ';' after variable declaration",
         )?;
-        Ok(Stmt::Var { name, initializer })
+        Ok(Stmt::Var {
+            name,
+            initializer,
+            span: Span::of(&keyword).to(self.span_of_previous()),
+        })
     }
 
-    fn while_statement(&mut self) -> Result<Stmt, SyntaxError> {
+    fn while_statement(&mut self, label: Option<Token>) -> Result<Stmt, SyntaxError> {
+        let start = Span::of(label.as_ref().unwrap_or(&self.previous()));
         self.consume(&TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        if self.matches(&vec![TokenType::Var]) {
+            return self.while_var(label, start);
+        }
         let condition = self.expression()?;
         self.consume(&TokenType::RightParen, "Expect ')' after condition.")?;
         let body = self.statement()?;
         Ok(Stmt::While {
+            label,
             condition,
+            span: start.to(body.span()),
+            body: Box::new(body),
+        })
+    }
+
+    // `while (var x = next()) body` runs as `for (var x; x = next();) body`, so
+    // `x` is scoped to the loop and assigned before each test.
+    fn while_var(&mut self, label: Option<Token>, start: Span) -> Result<Stmt, SyntaxError> {
+        let keyword = self.previous();
+        let name = self.consume(&TokenType::Identifier, "Expect variable name.")?;
+        self.consume(
+            &TokenType::Equal,
+            "Expect '=' after variable name in loop condition.",
+        )?;
+        let value = self.expression()?;
+        self.consume(&TokenType::RightParen, "Expect ')' after condition.")?;
+        let initializer = Stmt::Var {
+            name: name.clone(),
+            initializer: None,
+            span: Span::of(&keyword).to(Span::of(&name)),
+        };
+        let condition = Expr::Assign {
+            id: ExprId::fresh(),
+            name,
+            span: initializer.span().to(value.span()),
+            value: Box::new(value),
+        };
+        let body = self.statement()?;
+        Ok(Stmt::For {
+            label,
+            initializer: Some(Box::new(initializer)),
+            condition: Some(condition),
+            increment: None,
+            span: start.to(body.span()),
             body: Box::new(body),
         })
     }
@@ -288,10 +708,13 @@ impl<'a> Parser<'a> {
     fn expression_statement(&mut self) -> Result<Stmt, SyntaxError> {
         let expr = self.expression()?;
         self.consume(&TokenType::Semicolon, "Expect ';' after expression.")?;
-        Ok(Stmt::Expr(expr))
+        let span = expr.span().to(self.span_of_previous());
+        Ok(Stmt::Expr(expr, span))
     }
 
     fn function(&mut self, kind: &str) -> Result<Stmt, SyntaxError> {
+        // functions start at `fun`, getters and setters at `get` or `set`
+        let keyword = self.previous();
         let name = self.consume(
             &TokenType::Identifier,
             &("Expect ".to_owned() + kind + " name."),
@@ -305,8 +728,11 @@ impl<'a> Parser<'a> {
         if !self.check(&TokenType::RightParen) {
             params.push(self.consume(&TokenType::Identifier, "Expect parameter name.")?);
             while self.matches(&vec![TokenType::Comma]) {
-                if params.len() >= 255 {
-                    lox_error(self.peek(), "Can't have more than 255 parameters.");
+                if params.len() >= self.max_params {
+                    return Err(SyntaxError::new(
+                        self.peek().clone(),
+                        &format!("Can't have more than {} parameters.", self.max_params),
+                    ));
                 }
                 params.push(self.consume(&TokenType::Identifier, "Expect parameter name.")?)
             }
@@ -321,15 +747,20 @@ impl<'a> Parser<'a> {
 
         let body = self.block()?;
 
-        Ok(Stmt::Function { name, params, body })
+        let start = if kind == "method" { &name } else { &keyword };
+        Ok(Stmt::Function {
+            span: Span::of(start).to(self.span_of_previous()),
+            name,
+            params,
+            body,
+        })
     }
 
     fn block(&mut self) -> Result<Vec<Stmt>, SyntaxError> {
         let mut statements = Vec::new();
         while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
-            match self.declaration() {
-                Some(d) => statements.push(d),
-                None => {}
+            if let Some(d) = self.declaration() {
+                statements.push(d)
             }
         }
         self.consume(&TokenType::RightBrace, "Expect '}' after block.")?;
@@ -337,18 +768,11 @@ impl<'a> Parser<'a> {
     }
 
     fn equality(&mut self) -> Result<Expr, SyntaxError> {
-        let mut expr = self.comparison()?;
-
-        while self.matches(&vec![TokenType::BangEqual, TokenType::EqualEqual]) {
-            let operator = self.previous();
-            let right = self.comparison()?;
-            expr = Expr::Binary {
-                left: Box::new(expr),
-                operator: operator.clone(),
-                right: Box::new(right),
-            };
-        }
-        Ok(expr)
+        self.left_assoc(
+            &[TokenType::BangEqual, TokenType::EqualEqual],
+            Self::comparison,
+            Self::binary_expr,
+        )
     }
 
     fn matches(&mut self, token_types: &Vec<TokenType>) -> bool {
@@ -370,7 +794,7 @@ impl<'a> Parser<'a> {
 
     fn advance(&mut self) -> Token {
         if !self.is_at_end() {
-            self.current = self.current + 1;
+            self.current += 1;
         }
         self.previous()
     }
@@ -387,6 +811,17 @@ impl<'a> Parser<'a> {
         self.tokens[self.current - 1].clone()
     }
 
+    fn span_of_previous(&self) -> Span {
+        Span::of(&self.tokens[self.current - 1])
+    }
+
+    fn recover(&mut self, error: SyntaxError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+        self.synchronize();
+    }
+
     fn synchronize(&mut self) {
         self.advance();
 
@@ -396,6 +831,7 @@ impl<'a> Parser<'a> {
             }
             match self.peek().token_type {
                 TokenType::Class
+                | TokenType::Enum
                 | TokenType::Fun
                 | TokenType::Var
                 | TokenType::For
@@ -410,98 +846,130 @@ impl<'a> Parser<'a> {
     }
 
     fn comparison(&mut self) -> Result<Expr, SyntaxError> {
-        let mut expr = self.term()?;
-        while self.matches(&vec![
-            TokenType::Greater,
-            TokenType::GreaterEqual,
-            TokenType::Less,
-            TokenType::LessEqual,
-        ]) {
-            let operator = self.previous();
-            let right = self.term()?;
-            expr = Expr::Binary {
-                left: Box::new(expr),
-                operator: operator.clone(),
-                right: Box::new(right),
-            };
-        }
-        Ok(expr)
+        self.left_assoc(
+            &[
+                TokenType::Greater,
+                TokenType::GreaterEqual,
+                TokenType::Less,
+                TokenType::LessEqual,
+            ],
+            Self::term,
+            Self::binary_expr,
+        )
     }
 
     fn term(&mut self) -> Result<Expr, SyntaxError> {
-        let mut expr = self.factor()?;
-        while self.matches(&vec![TokenType::Minus, TokenType::Plus]) {
-            let operator = self.previous();
-            let right = self.factor()?;
-            expr = Expr::Binary {
-                left: Box::new(expr),
-                operator: operator.clone(),
-                right: Box::new(right),
-            };
-        }
-        Ok(expr)
+        self.left_assoc(
+            &[TokenType::Minus, TokenType::Plus],
+            Self::factor,
+            Self::binary_expr,
+        )
     }
 
     fn factor(&mut self) -> Result<Expr, SyntaxError> {
-        let mut expr = self.unary()?;
-        while self.matches(&vec![TokenType::Slash, TokenType::Star]) {
-            let operator = self.previous();
-            let right = self.unary()?;
-            expr = Expr::Binary {
-                left: Box::new(expr),
-                operator: operator.clone(),
-                right: Box::new(right),
-            };
-        }
-        Ok(expr)
+        self.left_assoc(
+            &[TokenType::Slash, TokenType::Star],
+            Self::unary,
+            Self::binary_expr,
+        )
     }
 
     fn unary(&mut self) -> Result<Expr, SyntaxError> {
         if self.matches(&vec![TokenType::Bang, TokenType::Minus]) {
-            let operator = self.previous();
-            let right = self.unary()?;
-            return Ok(Expr::Unary {
-                operator: operator.clone(),
-                right: Box::new(right),
-            });
+            return self.prefix();
         }
         self.call()
     }
 
-    fn finish_call(&mut self, callee: &Expr) -> Result<Expr, SyntaxError> {
+    fn prefix(&mut self) -> Result<Expr, SyntaxError> {
+        let operator = self.previous();
+        let right = self.nested(Self::unary)?;
+        Ok(Self::unary_expr(operator, right))
+    }
+
+    // Building the node here rather than in the operator loops keeps their
+    // stack frames small, which matters for deeply nested expressions.
+    fn binary_expr(left: Expr, operator: Token, right: Expr) -> Expr {
+        Expr::Binary {
+            span: left.span().to(right.span()),
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        }
+    }
+
+    fn logical_expr(left: Expr, operator: Token, right: Expr) -> Expr {
+        Expr::Logical {
+            span: left.span().to(right.span()),
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        }
+    }
+
+    // Negating a number literal gives a negative literal, written as in the
+    // source. A literal that is already negative is left under the `-`, so
+    // `- -5` stays a negation of `-5`.
+    fn unary_expr(operator: Token, right: Expr) -> Expr {
+        match right {
+            Expr::Literal {
+                value: Object::Number(n),
+                lexeme: Some(lexeme),
+                span,
+            } if operator.token_type == TokenType::Minus && !lexeme.starts_with('-') => {
+                Expr::Literal {
+                    value: Object::Number(-n),
+                    lexeme: Some(format!("-{lexeme}")),
+                    span: Span::of(&operator).to(span),
+                }
+            }
+            right => Expr::Unary {
+                span: Span::of(&operator).to(right.span()),
+                operator,
+                right: Box::new(right),
+            },
+        }
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, SyntaxError> {
         let mut arguments = vec![];
         if !self.check(&TokenType::RightParen) {
-            let expression = Box::new(self.expression()?);
-            arguments.push(expression);
-            while self.matches(&vec![TokenType::Comma]) {
-                if arguments.len() >= 255 {
-                    lox_error(self.peek(), "Can't have more than 255 arguments.");
+            loop {
+                arguments.push(self.element()?);
+                if !self.matches(&vec![TokenType::Comma]) {
+                    break;
+                }
+                if arguments.len() >= self.max_params {
+                    return Err(SyntaxError::new(
+                        self.peek().clone(),
+                        &format!("Can't have more than {} arguments.", self.max_params),
+                    ));
                 }
-                let expression = Box::new(self.expression()?);
-                arguments.push(expression);
             }
         }
         let paren = self.consume(&TokenType::RightParen, "Expect ')' after arguments.")?;
-
         Ok(Expr::Call {
-            callee: Box::new(callee.clone()),
+            span: callee.span().to(Span::of(&paren)),
+            callee: Box::new(callee),
             paren,
             arguments,
         })
     }
 
     fn call(&mut self) -> Result<Expr, SyntaxError> {
-        let mut expr = self.primary()?;
+        self.primary().and_then(|expr| self.postfix(expr))
+    }
+
+    fn postfix(&mut self, mut expr: Expr) -> Result<Expr, SyntaxError> {
         loop {
             if self.matches(&vec![TokenType::LeftParen]) {
-                expr = self.finish_call(&expr)?;
-            } else if self.matches(&vec![TokenType::Dot]) {
-                let name =
-                    self.consume(&TokenType::Identifier, "Expect propery name after '.'.")?;
-                expr = Expr::Get {
-                    object: Box::new(expr),
-                    name,
-                }
+                expr = self.finish_call(expr)?;
+            } else if self.matches(&vec![
+                TokenType::Dot,
+                TokenType::QuestionDot,
+                TokenType::LeftBracket,
+            ]) {
+                expr = self.access(expr)?;
             } else {
                 break;
             }
@@ -509,54 +977,566 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
+    // The `.name`, `?.name` or `[index]` after `object`. Like `binary_expr`,
+    // this is kept out of `postfix` for the sake of its stack frame.
+    fn access(&mut self, object: Expr) -> Result<Expr, SyntaxError> {
+        let operator = self.previous();
+        match operator.token_type {
+            TokenType::Dot => {
+                let name =
+                    self.consume(&TokenType::Identifier, "Expect propery name after '.'.")?;
+                Ok(Expr::Get {
+                    span: object.span().to(Span::of(&name)),
+                    object: Box::new(object),
+                    name,
+                })
+            }
+            TokenType::QuestionDot => {
+                let name =
+                    self.consume(&TokenType::Identifier, "Expect property name after '?.'.")?;
+                Ok(Expr::OptionalGet {
+                    span: object.span().to(Span::of(&name)),
+                    object: Box::new(object),
+                    name,
+                })
+            }
+            _ => {
+                let index = self.expression()?;
+                self.consume(&TokenType::RightBracket, "Expect ']' after index.")?;
+                Ok(Expr::Index {
+                    span: object.span().to(self.span_of_previous()),
+                    object: Box::new(object),
+                    bracket: operator,
+                    index: Box::new(index),
+                })
+            }
+        }
+    }
+
+    // Numbers keep their lexeme, so printing shows them as written.
+    fn literal(&mut self) -> Expr {
+        let token = self.previous();
+        let lexeme = match token.token_type {
+            TokenType::Number => Some(token.lexeme),
+            _ => None,
+        };
+        Expr::Literal {
+            value: token.literal.expect("No literal found in token"),
+            lexeme,
+            span: self.span_of_previous(),
+        }
+    }
+
     fn primary(&mut self) -> Result<Expr, SyntaxError> {
+        if self.matches(&vec![TokenType::LeftParen]) {
+            return self.grouping();
+        }
+        if self.matches(&vec![TokenType::LeftBracket]) {
+            return self.array();
+        }
+        if self.matches(&vec![TokenType::LeftBrace]) {
+            return self.brace();
+        }
+        self.atom()
+    }
+
+    fn grouping(&mut self) -> Result<Expr, SyntaxError> {
+        let paren = self.previous();
+        self.expression().and_then(|expr| {
+            self.consume(&TokenType::RightParen, "Expect ')' after expression.")?;
+            Ok(Expr::Grouping {
+                expression: Box::new(expr),
+                span: Span::of(&paren).to(self.span_of_previous()),
+            })
+        })
+    }
+
+    // The primaries that don't start with a bracket.
+    fn atom(&mut self) -> Result<Expr, SyntaxError> {
         if self.matches(&vec![TokenType::False]) {
             return Ok(Expr::Literal {
                 value: Object::Bool(false),
+                lexeme: None,
+                span: self.span_of_previous(),
             });
         }
         if self.matches(&vec![TokenType::True]) {
             return Ok(Expr::Literal {
                 value: Object::Bool(true),
+                lexeme: None,
+                span: self.span_of_previous(),
             });
         }
         if self.matches(&vec![TokenType::Nil]) {
-            return Ok(Expr::Literal { value: Object::Nil });
+            return Ok(Expr::Literal {
+                value: Object::Nil,
+                lexeme: None,
+                span: self.span_of_previous(),
+            });
         }
         if self.matches(&vec![TokenType::Number, TokenType::String]) {
-            return Ok(Expr::Literal {
-                value: self.previous().literal.expect("No literal found in token"),
+            return Ok(self.literal());
+        }
+        if self.matches(&vec![TokenType::Class]) {
+            let keyword = self.previous();
+            let (fields, methods, getters, setters) = self.class_body()?;
+            return Ok(Expr::Class {
+                span: Span::of(&keyword).to(self.span_of_previous()),
+                keyword,
+                fields,
+                methods,
+                getters,
+                setters,
+            });
+        }
+        if self.matches(&vec![TokenType::Super]) {
+            let keyword = self.previous();
+            self.consume(&TokenType::Dot, "Expect '.' after 'super'.")?;
+            let method = self.consume(&TokenType::Identifier, "Expect superclass method name.")?;
+            return Ok(Expr::Super {
+                id: ExprId::fresh(),
+                span: Span::of(&keyword).to(Span::of(&method)),
+                keyword,
+                method,
             });
         }
         if self.matches(&vec![TokenType::This]) {
             return Ok(Expr::This {
+                id: ExprId::fresh(),
                 keyword: self.previous(),
+                span: self.span_of_previous(),
             });
         }
         if self.matches(&vec![TokenType::Identifier]) {
             return Ok(Expr::Variable {
+                id: ExprId::fresh(),
                 name: self.previous(),
+                span: self.span_of_previous(),
             });
         }
 
-        if self.matches(&vec![TokenType::LeftParen]) {
-            let expr = self.expression()?;
-            self.consume(&TokenType::RightParen, &"Expect ')' after expression.")?;
-            return Ok(Expr::Grouping {
-                expression: Box::new(expr),
-            });
+        Err(self.error_at_current("Expected expression."))
+    }
+
+    fn array(&mut self) -> Result<Expr, SyntaxError> {
+        let bracket = self.previous();
+        let mut elements = vec![];
+        if !self.check(&TokenType::RightBracket) {
+            loop {
+                elements.push(self.element()?);
+                if !self.matches(&vec![TokenType::Comma]) {
+                    break;
+                }
+            }
         }
-        Err(SyntaxError::new(
-            self.tokens[self.current].clone(),
-            &"Expected expression.",
-        ))
+        self.consume(&TokenType::RightBracket, "Expect ']' after elements.")?;
+        Ok(Expr::Array {
+            span: Span::of(&bracket).to(self.span_of_previous()),
+            bracket,
+            elements,
+        })
+    }
+
+    // `{` starts a map if it is empty or its first element is followed by
+    // `:`, and otherwise a block whose final expression gives its value.
+    // This is decided by looking ahead rather than by trying to parse, since
+    // a failed parse would already have reported its error.
+    fn brace(&mut self) -> Result<Expr, SyntaxError> {
+        if self.starts_map(self.current) {
+            self.map()
+        } else {
+            self.block_expression()
+        }
+    }
+
+    // Whether the tokens from `start`, just after a `{`, are a map's entries.
+    fn starts_map(&self, start: usize) -> bool {
+        match self.tokens[start].token_type {
+            TokenType::RightBrace => return true,
+            // `name:` labels a loop, and is otherwise a key
+            TokenType::Identifier if self.tokens[start + 1].token_type == TokenType::Colon => {
+                return !matches!(
+                    self.tokens[start + 2].token_type,
+                    TokenType::While | TokenType::For
+                );
+            }
+            // a nested `{` is a key or a block statement, found by the scan
+            TokenType::LeftBrace => {}
+            _ if self.statement_at(start) => return false,
+            _ => {}
+        }
+        // a `:` in the first element, outside any brackets, makes it a key
+        let mut depth = 0;
+        for token in &self.tokens[start..] {
+            match token.token_type {
+                TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => depth += 1,
+                TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace
+                    if depth > 0 =>
+                {
+                    depth -= 1
+                }
+                TokenType::Colon if depth == 0 => return true,
+                TokenType::RightParen
+                | TokenType::RightBracket
+                | TokenType::RightBrace
+                | TokenType::Semicolon
+                | TokenType::Comma
+                | TokenType::Eof => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    // Whether the `{` at `open` is a value inside a block expression rather
+    // than a nested block statement: a map, or the block's last item.
+    fn brace_is_value(&self, open: usize) -> bool {
+        if self.starts_map(open + 1) {
+            return true;
+        }
+        let mut depth = 0;
+        for (i, token) in self.tokens.iter().enumerate().skip(open) {
+            match token.token_type {
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return self.tokens[i + 1].token_type == TokenType::RightBrace;
+                    }
+                }
+                TokenType::Eof => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    fn block_expression(&mut self) -> Result<Expr, SyntaxError> {
+        let brace = self.previous();
+        let mut statements = Vec::new();
+        let mut value = None;
+        while value.is_none() && !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            value = self.block_item(&mut statements)?;
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after block.")?;
+        let span = Span::of(&brace).to(self.span_of_previous());
+        // a block without a final expression is nil
+        let value = value.unwrap_or(Expr::Literal {
+            value: Object::Nil,
+            lexeme: None,
+            span,
+        });
+        Ok(Expr::Block {
+            brace,
+            statements,
+            value: Box::new(value),
+            span,
+        })
+    }
+
+    // A statement of a block expression, added to `statements`, or the
+    // expression that ends it, which is returned.
+    fn block_item(&mut self, statements: &mut Vec<Stmt>) -> Result<Option<Expr>, SyntaxError> {
+        let value_brace = self.check(&TokenType::LeftBrace) && self.brace_is_value(self.current);
+        if self.starts_statement() && !value_brace {
+            self.block_declaration(statements);
+            return Ok(None);
+        }
+        self.expression().and_then(|expr| {
+            if self.check(&TokenType::RightBrace) {
+                return Ok(Some(expr));
+            }
+            self.consume(&TokenType::Semicolon, "Expect ';' after expression.")?;
+            let span = expr.span().to(self.span_of_previous());
+            statements.push(Stmt::Expr(expr, span));
+            Ok(None)
+        })
+    }
+
+    fn block_declaration(&mut self, statements: &mut Vec<Stmt>) {
+        if let Some(d) = self.declaration() {
+            statements.push(d);
+        }
+    }
+
+    // Whether the next token begins something other than an expression
+    // statement, such as a declaration or a nested block.
+    fn starts_statement(&self) -> bool {
+        self.statement_at(self.current)
+    }
+
+    fn statement_at(&self, index: usize) -> bool {
+        match self.tokens[index].token_type {
+            TokenType::Var
+            | TokenType::Fun
+            | TokenType::Print
+            | TokenType::If
+            | TokenType::While
+            | TokenType::For
+            | TokenType::Return
+            | TokenType::Break
+            | TokenType::Continue
+            | TokenType::Yield
+            | TokenType::Defer
+            | TokenType::Import
+            | TokenType::Private
+            | TokenType::Enum
+            | TokenType::LeftBrace => true,
+            // `class {` is a class expression
+            TokenType::Class => self.tokens[index + 1].token_type == TokenType::Identifier,
+            TokenType::Identifier => self.tokens[index + 1].token_type == TokenType::Colon,
+            _ => false,
+        }
+    }
+
+    fn map(&mut self) -> Result<Expr, SyntaxError> {
+        let brace = self.previous();
+        let mut entries = vec![];
+        if !self.check(&TokenType::RightBrace) {
+            loop {
+                let key = self.element()?;
+                self.consume(&TokenType::Colon, "Expect ':' after map key.")?;
+                let value = self.element()?;
+                entries.push((key, value));
+                if !self.matches(&vec![TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after map entries.")?;
+        Ok(Expr::Map {
+            span: Span::of(&brace).to(self.span_of_previous()),
+            brace,
+            entries,
+        })
     }
 
     fn consume(&mut self, token_type: &TokenType, message: &str) -> Result<Token, SyntaxError> {
-        if self.check(token_type) == true {
+        if self.check(token_type) {
             return Ok(self.advance());
         }
 
-        Err(SyntaxError::new(self.tokens[self.current].clone(), message))
+        Err(self.error_at_current(message))
+    }
+
+    // An error token from the scanner is reported with its own message, in
+    // place of whatever was expected there.
+    fn error_at_current(&self, message: &str) -> SyntaxError {
+        let token = self.peek().clone();
+        match (&token.token_type, &token.literal) {
+            (TokenType::Error, Some(Object::String(scanned))) => {
+                let scanned = scanned.to_string();
+                SyntaxError::new(token, &scanned)
+            }
+            _ => SyntaxError::new(token, message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    fn call_with_arguments(count: usize) -> String {
+        let arguments = vec!["0"; count].join(", ");
+        format!("f({arguments});")
+    }
+
+    #[test]
+    fn too_many_arguments_is_a_syntax_error() {
+        let mut scanner = Scanner::new(call_with_arguments(256));
+        let tokens = scanner.scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let error = parser.parse().expect_err("expected a syntax error");
+        assert_eq!(error.message, "Can't have more than 255 arguments.");
+    }
+
+    #[test]
+    fn max_arguments_parses() {
+        let mut scanner = Scanner::new(call_with_arguments(255));
+        let tokens = scanner.scan_tokens();
+        let mut parser = Parser::new(tokens);
+        assert!(parser.parse().is_ok());
+    }
+
+    #[test]
+    fn too_many_parameters_is_a_syntax_error() {
+        let params = (0..256)
+            .map(|i| format!("p{i}"))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let mut scanner = Scanner::new(format!("fun f({params}) {{}}"));
+        let tokens = scanner.scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let error = parser.parse().expect_err("expected a syntax error");
+        assert_eq!(error.message, "Can't have more than 255 parameters.");
+    }
+
+    #[test]
+    fn raised_limit_allows_more_arguments() {
+        let mut scanner = Scanner::new(call_with_arguments(300));
+        let tokens = scanner.scan_tokens();
+        let mut parser = Parser::with_max_params(tokens, 300);
+        let stmts = parser.parse().expect("expected the call to parse");
+        match &stmts[0] {
+            Stmt::Expr(Expr::Call { arguments, .. }, _) => assert_eq!(arguments.len(), 300),
+            _ => panic!("expected a call statement"),
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_a_syntax_error() {
+        let source = format!("print {}1{};", "(".repeat(1_000), ")".repeat(1_000));
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let error = parser.parse().expect_err("expected a syntax error");
+        assert_eq!(error.message, "Expression too deeply nested.");
+    }
+
+    #[test]
+    fn deep_unary_nesting_is_a_syntax_error() {
+        let source = format!("print {}1;", "-".repeat(1_000));
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let error = parser.parse().expect_err("expected a syntax error");
+        assert_eq!(error.message, "Expression too deeply nested.");
+    }
+
+    #[test]
+    fn long_assignment_chain_is_a_syntax_error() {
+        let source = format!("{}1;", "a = ".repeat(1_000));
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let error = parser.parse().expect_err("expected a syntax error");
+        assert_eq!(error.message, "Expression too deeply nested.");
+    }
+
+    #[test]
+    fn nesting_up_to_the_limit_parses() {
+        let depth = MAX_DEPTH - 1;
+        let source = format!("print {}1{};", "(".repeat(depth), ")".repeat(depth));
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens();
+        let mut parser = Parser::new(tokens);
+        assert!(parser.parse().is_ok());
+    }
+
+    // A spawned thread has the default stack size, whatever the test
+    // harness was given.
+    #[test]
+    fn nesting_up_to_the_limit_parses_on_a_default_thread() {
+        let depth = MAX_DEPTH - 1;
+        std::thread::spawn(move || {
+            let nestings = [
+                ("(", ")"),
+                ("[", "]"),
+                ("{", "}"),
+                ("{1: ", "}"),
+                ("f(", ")"),
+                ("-", ""),
+                ("a = ", ""),
+            ];
+            for (open, close) in nestings {
+                let source = format!("print {}1{};", open.repeat(depth), close.repeat(depth));
+                let mut scanner = Scanner::new(source);
+                let tokens = scanner.scan_tokens();
+                let mut parser = Parser::new(tokens);
+                assert!(parser.parse().is_ok(), "nesting {open:?} failed to parse");
+            }
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn setter_needs_one_parameter() {
+        let mut scanner = Scanner::new(String::from("class A { set x() {} }"));
+        let tokens = scanner.scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let error = parser.parse().expect_err("expected a syntax error");
+        assert_eq!(error.message, "A setter must take exactly one parameter.");
+    }
+
+    #[test]
+    fn parse_repl_accepts_bare_expression() {
+        let mut scanner = Scanner::new(String::from("1+2"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse_repl().expect("parse error");
+        assert!(matches!(
+            stmts.as_slice(),
+            [Stmt::Print(Expr::Binary { .. }, _)]
+        ));
+    }
+
+    #[test]
+    fn binary_span_covers_both_operands() {
+        let mut scanner = Scanner::new(String::from("var x = 1 +\n  foo * 2;"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        match &stmts[0] {
+            Stmt::Var {
+                initializer: Some(binary @ Expr::Binary { right, .. }),
+                span,
+                ..
+            } => {
+                assert_eq!(
+                    binary.span(),
+                    Span {
+                        start: (1, 8),
+                        end: (2, 9)
+                    }
+                );
+                assert_eq!(right.span().start, (2, 2));
+                assert_eq!(span.end, (2, 10));
+            }
+            _ => panic!("expected a variable with a binary initializer"),
+        }
+    }
+
+    #[test]
+    fn parse_repl_accepts_statement() {
+        let mut scanner = Scanner::new(String::from("var x = 1;"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse_repl().expect("parse error");
+        assert!(matches!(stmts.as_slice(), [Stmt::Var { .. }]));
+    }
+
+    #[test]
+    fn assigning_to_this_is_a_syntax_error() {
+        let mut scanner = Scanner::new(String::from("class C { m() { this = 1; } }"));
+        let tokens = scanner.scan_tokens();
+        let error = Parser::new(tokens)
+            .parse()
+            .expect_err("expected a syntax error");
+        assert_eq!(error.message, "Cannot assign to 'this'.");
+    }
+
+    #[test]
+    fn negative_number_literals_are_folded() {
+        let mut scanner = Scanner::new(String::from("print -5; print - -5; print -(5); print -x;"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let exprs: Vec<&Expr> = stmts
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::Print(expr, _) => expr,
+                _ => panic!("expected a print statement"),
+            })
+            .collect();
+        assert!(matches!(
+            exprs[0],
+            Expr::Literal { value: Object::Number(n), lexeme: Some(lexeme), span }
+                if *n == -5.0 && lexeme == "-5" && span.start == (1, 6)
+        ));
+        assert!(matches!(
+            exprs[1],
+            Expr::Unary { right, .. } if matches!(**right, Expr::Literal { .. })
+        ));
+        assert!(matches!(exprs[2], Expr::Unary { .. }));
+        assert!(matches!(exprs[3], Expr::Unary { .. }));
     }
 }
@@ -1,7 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::iter;
 
-use crate::ast::Expr;
 use crate::ast::Visitor;
+use crate::ast::{Expr, ExprId};
 use crate::error::RuntimeError;
 use crate::interpreter::Interpreter;
 use crate::stmt::Stmt;
@@ -9,8 +10,26 @@ use crate::token::Token;
 
 pub struct Resolver {
     pub interpreter: Interpreter,
-    scopes: Vec<HashMap<String, bool>>,
+    // each name maps to its declaring token and whether it has been defined yet
+    scopes: Vec<HashMap<String, (Token, bool)>>,
+    // names each scope's block declares further down, so earlier uses can be
+    // reported instead of resolving to an enclosing variable
+    upcoming: Vec<HashSet<String>>,
+    globals: HashMap<String, Token>,
+    definitions: Vec<(Token, Token)>,
     current_function: FunctionType,
+    current_class: ClassType,
+    // labels of the loops enclosing the current statement, innermost last
+    loops: Vec<Option<String>>,
+    warn_shadowing: bool,
+    // whether declaring a global twice is an error
+    strict_globals: bool,
+    warnings: Vec<(Token, String)>,
+    // the most locals each function has live at once, keyed by its name
+    slots: HashMap<Token, usize>,
+    // where the current function's scopes start, and its most live locals so far
+    slot_base: usize,
+    max_live: usize,
 }
 
 impl Resolver {
@@ -20,202 +39,487 @@ impl Resolver {
             // this only tracks local block scopes, variables declared at the top level in the global scope
             // are NOT tracked
             scopes: Vec::new(),
+            upcoming: Vec::new(),
+            globals: HashMap::new(),
+            definitions: Vec::new(),
             current_function: FunctionType::None,
+            current_class: ClassType::None,
+            loops: Vec::new(),
+            warn_shadowing: false,
+            strict_globals: false,
+            warnings: Vec::new(),
+            slots: HashMap::new(),
+            slot_base: 0,
+            max_live: 0,
         }
     }
 
+    /// Creates a resolver that warns when a local shadows an outer variable.
+    pub fn with_shadow_warnings(interpreter: Interpreter) -> Resolver {
+        Resolver {
+            warn_shadowing: true,
+            ..Resolver::new(interpreter)
+        }
+    }
+
+    /// Creates a resolver that reports declaring a global that is already
+    /// declared, which is otherwise allowed so the REPL can redefine names.
+    pub fn strict(interpreter: Interpreter) -> Resolver {
+        Resolver {
+            strict_globals: true,
+            ..Resolver::new(interpreter)
+        }
+    }
+
+    /// Returns the warnings reported so far, with the token each refers to.
+    pub fn warnings(&self) -> &[(Token, String)] {
+        &self.warnings
+    }
+
+    /// Pairs each resolved variable use with the token that declared it.
+    pub fn definitions(&self) -> &[(Token, Token)] {
+        &self.definitions
+    }
+
+    /// Returns the most local slots each function resolved so far needs at
+    /// once, counting its parameters, keyed by the token naming it. Like the
+    /// interpreter's locals, this is what a stack allocator would need.
+    pub fn max_slots(&self) -> &HashMap<Token, usize> {
+        &self.slots
+    }
+
     pub fn resolve_stmts(&mut self, statements: &Vec<Stmt>) -> Result<(), RuntimeError> {
-        println!("[RESOLVER] resolve_stmts");
         for statement in statements {
-            println!("[RESOLVER] statement {:?}", statement);
             self.visit_stmt(statement)?;
         }
         Ok(())
     }
 
     fn begin_scope(&mut self) {
-        println!("[RESOLVER] begin_scope");
         self.scopes.push(HashMap::new());
+        self.upcoming.push(HashSet::new());
     }
 
     fn end_scope(&mut self) {
         self.scopes.pop();
+        self.upcoming.pop();
+    }
+
+    /// Resolves the statements of a block in the current scope, after noting
+    /// the names it declares.
+    fn resolve_block(&mut self, statements: &Vec<Stmt>) -> Result<(), RuntimeError> {
+        let upcoming = self.upcoming.last_mut().unwrap();
+        for statement in statements {
+            match statement {
+                Stmt::Class { name, .. }
+                | Stmt::Enum { name, .. }
+                | Stmt::Function { name, .. }
+                | Stmt::Import { name, .. }
+                | Stmt::Var { name, .. } => {
+                    upcoming.insert(name.lexeme.clone());
+                }
+                _ => {}
+            }
+        }
+        self.resolve_stmts(statements)
     }
 
     fn declare(&mut self, name: &Token) -> Result<(), RuntimeError> {
         if self.scopes.is_empty() {
+            let previous = self.globals.insert(name.lexeme.clone(), name.clone());
+            if self.strict_globals && previous.is_some() {
+                return Err(RuntimeError::new(
+                    name.clone(),
+                    &format!("Variable '{}' already declared.", name.lexeme),
+                    None,
+                ));
+            }
             return Ok(());
         }
         let scope = self.scopes.last_mut().unwrap();
         if scope.contains_key(&name.lexeme) {
             return Err(RuntimeError::new(
                 name.clone(),
-                &"Already a variable with this name in this scope.",
+                "Already a variable with this name in this scope.",
                 None,
             ));
         }
-        scope.insert(name.lexeme.clone(), false);
+        scope.insert(name.lexeme.clone(), (name.clone(), false));
+        self.upcoming.last_mut().unwrap().remove(&name.lexeme);
+        if self.current_function != FunctionType::None {
+            let live = self.scopes[self.slot_base..].iter().map(HashMap::len).sum();
+            self.max_live = self.max_live.max(live);
+        }
+        if self.warn_shadowing && self.shadows(name) {
+            self.warn(
+                name,
+                &format!("Variable '{}' shadows an outer binding.", name.lexeme),
+            );
+        }
         Ok(())
     }
 
+    fn shadows(&self, name: &Token) -> bool {
+        let enclosing = &self.scopes[..self.scopes.len() - 1];
+        enclosing
+            .iter()
+            .any(|scope| scope.contains_key(&name.lexeme))
+            || self.globals.contains_key(&name.lexeme)
+    }
+
+    // Only collected, so the caller decides where warnings go.
+    fn warn(&mut self, token: &Token, message: &str) {
+        self.warnings.push((token.clone(), message.to_string()));
+    }
+
     fn define(&mut self, name: &Token) {
         if self.scopes.is_empty() {
             return;
         }
         let scope = self.scopes.last_mut().unwrap();
-        scope.insert(name.lexeme.clone(), true);
+        if let Some((_, defined)) = scope.get_mut(&name.lexeme) {
+            *defined = true;
+        }
     }
 
-    fn resolve_local(&mut self, expr: &Expr, name: &Token) {
-        println!("[RESOLVER] resolve_local, self.scopes: {:?}", self.scopes);
+    fn resolve_local(&mut self, id: ExprId, name: &Token) {
         for i in (0..self.scopes.len()).rev() {
-            if self.scopes[i].contains_key(&name.lexeme) {
-                println!(
-                    "[RESOLVER] resolving expr {:?} depth: {}",
-                    expr,
-                    self.scopes.len() - 1 - i
-                );
-                self.interpreter.resolve(expr, self.scopes.len() - 1 - i);
+            if let Some((declaration, _)) = self.scopes[i].get(&name.lexeme) {
+                self.definitions.push((name.clone(), declaration.clone()));
+                self.interpreter.resolve(id, self.scopes.len() - 1 - i);
                 return;
             }
         }
+        if let Some(declaration) = self.globals.get(&name.lexeme) {
+            self.definitions.push((name.clone(), declaration.clone()));
+        }
+    }
+    fn resolve_class(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        fields: &Vec<(Token, Expr)>,
+        members: [&Vec<Stmt>; 3],
+    ) -> Result<(), RuntimeError> {
+        // Field defaults are evaluated in the scope the class is declared in.
+        for (_, value) in fields {
+            self.visit_expr(value)?;
+        }
+        let enclosing_class = self.current_class.clone();
+        self.current_class = ClassType::Class;
+        let resolved = self.resolve_superclass_and_members(name, superclass, members);
+        self.current_class = enclosing_class;
+        resolved
+    }
+
+    // A superclass gets a scope of its own holding `super`, enclosing the one
+    // holding `this`.
+    fn resolve_superclass_and_members(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        members: [&Vec<Stmt>; 3],
+    ) -> Result<(), RuntimeError> {
+        if let Some(superclass) = superclass {
+            if let Expr::Variable { name: s, .. } = superclass {
+                if s.lexeme == name.lexeme {
+                    return Err(RuntimeError::new(
+                        s.clone(),
+                        "A class can't inherit from itself.",
+                        None,
+                    ));
+                }
+            }
+            self.current_class = ClassType::Subclass;
+            self.visit_expr(superclass)?;
+            self.begin_scope();
+            let scope = self.scopes.last_mut().unwrap();
+            scope.insert("super".into(), (name.clone(), true));
+        }
+
+        self.begin_scope();
+        let scope = self.scopes.last_mut().unwrap();
+        scope.insert("this".into(), (name.clone(), true));
+
+        let mut resolved = Ok(());
+        for method in members.into_iter().flatten() {
+            resolved = self.resolve_function(method, FunctionType::Method);
+            if resolved.is_err() {
+                break;
+            }
+        }
+
+        self.end_scope();
+        if superclass.is_some() {
+            self.end_scope();
+        }
+        resolved
     }
+
+    fn resolve_loop_body(
+        &mut self,
+        label: &Option<Token>,
+        body: &Stmt,
+    ) -> Result<(), RuntimeError> {
+        self.loops.push(label.as_ref().map(|l| l.lexeme.clone()));
+        let resolved = self.visit_stmt(body);
+        self.loops.pop();
+        resolved
+    }
+
     fn resolve_function(
         &mut self,
         stmt: &Stmt,
         function_type: FunctionType,
     ) -> Result<(), RuntimeError> {
         if let Stmt::Function {
-            name: _,
-            params,
-            body,
+            name, params, body, ..
         } = stmt
         {
             let enclosing_function = self.current_function.clone();
             self.current_function = function_type;
+            let enclosing_loops = std::mem::take(&mut self.loops);
+            let enclosing_slots = (self.slot_base, std::mem::take(&mut self.max_live));
+            self.slot_base = self.scopes.len();
             self.begin_scope();
-            for param in params {
-                self.declare(param)?;
-                self.define(param);
-            }
-            self.resolve_stmts(body)?;
+            let resolved = self.resolve_params_and_body(params, body);
             self.end_scope();
+            self.slots.insert(name.clone(), self.max_live);
+            (self.slot_base, self.max_live) = enclosing_slots;
             self.current_function = enclosing_function;
+            self.loops = enclosing_loops;
+            resolved?;
         }
         Ok(())
     }
-}
 
-impl<'a> Visitor<(), ()> for Resolver {
-    fn visit_expr(&mut self, e: &Expr) -> Result<(), RuntimeError> {
-        match e {
-            Expr::Assign { name, value } => {
-                self.visit_expr(value)?;
-                self.resolve_local(e, name);
-                Ok(())
+    fn resolve_params_and_body(
+        &mut self,
+        params: &Vec<Token>,
+        body: &Vec<Stmt>,
+    ) -> Result<(), RuntimeError> {
+        for param in params {
+            self.declare(param)?;
+            self.define(param);
+        }
+        self.resolve_block(body)
+    }
+
+    // Like `visit_expr` for each of `exprs` in turn, stopping at the first
+    // error. Sharing this keeps `visit_expr`'s frame small, as it is on the
+    // stack once for every level of nesting.
+    fn visit_exprs<'a>(
+        &mut self,
+        exprs: impl IntoIterator<Item = &'a Expr>,
+    ) -> Result<(), RuntimeError> {
+        exprs.into_iter().try_for_each(|expr| self.visit_expr(expr))
+    }
+
+    fn resolve_super(&mut self, id: ExprId, keyword: &Token) -> Result<(), RuntimeError> {
+        match self.current_class {
+            ClassType::None => {
+                return Err(RuntimeError::new(
+                    keyword.clone(),
+                    "Can't use 'super' outside of a class.",
+                    None,
+                ))
             }
-            Expr::Binary {
-                left,
-                operator: _,
-                right,
-            } => {
-                self.visit_expr(left)?;
-                self.visit_expr(right)?;
-                Ok(())
+            ClassType::Class => {
+                return Err(RuntimeError::new(
+                    keyword.clone(),
+                    "Can't use 'super' in a class with no superclass.",
+                    None,
+                ))
             }
-            Expr::Call {
-                callee,
-                paren: _,
-                arguments,
-            } => {
-                self.visit_expr(callee)?;
+            ClassType::Subclass => {}
+        }
+        self.resolve_local(id, keyword);
+        Ok(())
+    }
 
-                for argument in arguments {
-                    self.visit_expr(argument)?;
-                }
-                Ok(())
+    // Field defaults are resolved outside the class they belong to, so a
+    // `this` there is only bound if an enclosing class binds it.
+    fn resolve_this(&mut self, id: ExprId, keyword: &Token) -> Result<(), RuntimeError> {
+        if let ClassType::None = self.current_class {
+            return Err(RuntimeError::new(
+                keyword.clone(),
+                "Can't use 'this' outside of a class.",
+                None,
+            ));
+        }
+        self.resolve_local(id, keyword);
+        Ok(())
+    }
+
+    fn resolve_variable(&mut self, id: ExprId, name: &Token) -> Result<(), RuntimeError> {
+        if !self.scopes.is_empty()
+            && self
+                .scopes
+                .last()
+                .unwrap()
+                .get(&name.lexeme)
+                .is_some_and(|(_, defined)| !defined)
+        {
+            return Err(RuntimeError::new(
+                name.clone(),
+                "Can't read local variable in its own initializer.",
+                None,
+            ));
+        }
+        self.resolve_assigned(id, name)
+    }
+
+    fn resolve_assigned(&mut self, id: ExprId, name: &Token) -> Result<(), RuntimeError> {
+        self.check_declared(name)?;
+        self.resolve_local(id, name);
+        Ok(())
+    }
+
+    // A name that a block of the current function declares further down
+    // can't be used before that, even from a block nested in it, or it would
+    // resolve to an enclosing variable instead. The search stops at the
+    // innermost scope that already declares the name.
+    fn check_declared(&self, name: &Token) -> Result<(), RuntimeError> {
+        for i in (self.slot_base..self.scopes.len()).rev() {
+            if self.scopes[i].contains_key(&name.lexeme) {
+                break;
             }
-            Expr::Get { object, name: _ } => {
-                self.visit_expr(&object)?;
-                Ok(())
+            if self.upcoming[i].contains(&name.lexeme) {
+                return Err(RuntimeError::new(
+                    name.clone(),
+                    &format!("Cannot use '{}' before its declaration.", name.lexeme),
+                    None,
+                ));
             }
-            Expr::Grouping { expression } => self.visit_expr(expression),
-            Expr::Literal { value: _ } => Ok(()),
-            Expr::Logical {
-                left,
-                operator: _,
-                right,
+        }
+        Ok(())
+    }
+}
+
+impl Visitor<(), ()> for Resolver {
+    fn visit_expr(&mut self, e: &Expr) -> Result<(), RuntimeError> {
+        match e {
+            Expr::Array { elements, .. } => self.visit_exprs(elements),
+            Expr::Assign {
+                id, name, value, ..
             } => {
-                self.visit_expr(left)?;
-                self.visit_expr(right)?;
-                Ok(())
+                self.visit_expr(value)?;
+                self.resolve_assigned(*id, name)
             }
-            Expr::Set {
-                object,
-                name: _,
-                value,
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.visit_exprs([&**left, &**right])
+            }
+            Expr::Block {
+                statements, value, ..
             } => {
+                self.begin_scope();
+                self.resolve_block(statements)?;
                 self.visit_expr(value)?;
-                self.visit_expr(object)?;
-                Ok(())
-            }
-            Expr::This { keyword } => {
-                self.resolve_local(e, keyword);
+                self.end_scope();
                 Ok(())
             }
-            Expr::Unary { operator: _, right } => self.visit_expr(right),
-            Expr::Variable { name } => {
-                if !self.scopes.is_empty()
-                    && self
-                        .scopes
-                        .last()
-                        .unwrap()
-                        .get(&name.lexeme)
-                        .is_some_and(|b| *b == false)
-                {
-                    return Err(RuntimeError::new(
-                        name.clone(),
-                        "Can't read local variable in its own initializer.",
-                        None,
-                    ));
-                }
-                self.resolve_local(e, name);
-                Ok(())
+            Expr::Call {
+                callee, arguments, ..
+            } => self.visit_exprs(iter::once(&**callee).chain(arguments)),
+            Expr::Class {
+                keyword,
+                fields,
+                methods,
+                getters,
+                setters,
+                ..
+            } => self.resolve_class(keyword, None, fields, [methods, getters, setters]),
+            Expr::Comma { expressions, .. } => self.visit_exprs(expressions),
+            Expr::Get { object, .. } | Expr::OptionalGet { object, .. } => self.visit_expr(object),
+            Expr::Grouping { expression, .. } => self.visit_expr(expression),
+            Expr::Index { object, index, .. } => self.visit_exprs([&**object, &**index]),
+            Expr::Literal { .. } => Ok(()),
+            Expr::Map { entries, .. } => {
+                self.visit_exprs(entries.iter().flat_map(|(key, value)| [key, value]))
             }
+            Expr::Set { object, value, .. } => self.visit_exprs([&**value, &**object]),
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => self.visit_exprs([&**value, &**object, &**index]),
+            Expr::Super { id, keyword, .. } => self.resolve_super(*id, keyword),
+            Expr::This { id, keyword, .. } => self.resolve_this(*id, keyword),
+            Expr::Unary { right, .. } => self.visit_expr(right),
+            Expr::Variable { id, name, .. } => self.resolve_variable(*id, name),
         }
     }
 
     fn visit_stmt(&mut self, s: &crate::stmt::Stmt) -> Result<(), RuntimeError> {
         match s {
-            Stmt::Block { statements } => {
-                println!("[RESOLVER] Stmt::Block");
-                println!("[RESOLVER] {:?}", statements);
+            Stmt::Block { statements, .. } => {
                 self.begin_scope();
-                self.resolve_stmts(statements)?;
+                self.resolve_block(statements)?;
                 self.end_scope();
                 Ok(())
             }
-            Stmt::Class { name, methods } => {
+            Stmt::Break { keyword, label, .. } | Stmt::Continue { keyword, label, .. } => {
+                if self.loops.is_empty() {
+                    return Err(RuntimeError::new(
+                        keyword.clone(),
+                        &format!("Can't use '{}' outside of a loop.", keyword.lexeme),
+                        None,
+                    ));
+                }
+                if let Some(l) = label {
+                    if !self.loops.contains(&Some(l.lexeme.clone())) {
+                        return Err(RuntimeError::new(
+                            l.clone(),
+                            &format!("No enclosing loop labeled '{}'.", l.lexeme),
+                            None,
+                        ));
+                    }
+                }
+                Ok(())
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                fields,
+                methods,
+                getters,
+                setters,
+                ..
+            } => {
                 self.declare(name)?;
                 self.define(name);
-
+                self.resolve_class(
+                    name,
+                    superclass.as_ref(),
+                    fields,
+                    [methods, getters, setters],
+                )
+            }
+            Stmt::Expr(e, _) => self.visit_expr(e),
+            Stmt::For {
+                label,
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
                 self.begin_scope();
-                let scope = self.scopes.last_mut().unwrap();
-                scope.insert("this".into(), true);
-
-                for method in methods {
-                    self.resolve_function(method, FunctionType::Method)?;
+                if let Some(i) = initializer {
+                    self.visit_stmt(i)?;
                 }
-
+                if let Some(c) = condition {
+                    self.visit_expr(c)?;
+                }
+                if let Some(i) = increment {
+                    self.visit_expr(i)?;
+                }
+                self.resolve_loop_body(label, body)?;
                 self.end_scope();
                 Ok(())
             }
-            Stmt::Expr(e) => self.visit_expr(e),
-            Stmt::Function {
-                name,
-                params: _,
-                body: _,
-            } => {
+            Stmt::Function { name, .. } => {
                 self.declare(name)?;
                 self.define(name);
                 self.resolve_function(s, FunctionType::Function)?;
@@ -225,16 +529,36 @@ impl<'a> Visitor<(), ()> for Resolver {
                 condition,
                 then_branch,
                 else_branch,
+                ..
             } => {
                 self.visit_expr(condition)?;
-                self.visit_stmt(&then_branch)?;
+                self.visit_stmt(then_branch)?;
                 if let Some(s) = else_branch {
                     self.visit_stmt(s)?;
                 }
                 Ok(())
             }
-            Stmt::Print(e) => self.visit_expr(e),
-            Stmt::Return { keyword, value } => {
+            Stmt::Enum { name, .. } | Stmt::Import { name, .. } => {
+                self.declare(name)?;
+                self.define(name);
+                Ok(())
+            }
+            Stmt::Print(e, _) => self.visit_expr(e),
+            Stmt::Private {
+                keyword,
+                declaration,
+                ..
+            } => {
+                if !self.scopes.is_empty() {
+                    return Err(RuntimeError::new(
+                        keyword.clone(),
+                        "Can only use 'private' at the top level.",
+                        None,
+                    ));
+                }
+                self.visit_stmt(declaration)
+            }
+            Stmt::Return { keyword, value, .. } => {
                 if self.current_function == FunctionType::None {
                     return Err(RuntimeError::new(
                         keyword.clone(),
@@ -245,21 +569,46 @@ impl<'a> Visitor<(), ()> for Resolver {
                 self.visit_expr(value)?;
                 Ok(())
             }
-            Stmt::Var { name, initializer } => {
+            Stmt::Var {
+                name, initializer, ..
+            } => {
                 self.declare(name)?;
                 if let Some(i) = initializer {
-                    self.visit_expr(&i)?;
+                    self.visit_expr(i)?;
                 }
                 self.define(name);
                 Ok(())
             }
-            Stmt::While { condition, body } => {
-                println!("[RESOLVER] Stmt::While condition: {:?}", condition);
+            Stmt::While {
+                label,
+                condition,
+                body,
+                ..
+            } => {
                 self.visit_expr(condition)?;
-                println!("[RESOLVER] Stmt::While body: {:?}", body);
-                self.visit_stmt(body)?;
+                self.resolve_loop_body(label, body)?;
                 Ok(())
             }
+            Stmt::Defer { keyword, value, .. } => {
+                if self.current_function == FunctionType::None {
+                    return Err(RuntimeError::new(
+                        keyword.clone(),
+                        "Can't use 'defer' outside of a function.",
+                        None,
+                    ));
+                }
+                self.visit_expr(value)
+            }
+            Stmt::Yield { keyword, value, .. } => {
+                if self.current_function == FunctionType::None {
+                    return Err(RuntimeError::new(
+                        keyword.clone(),
+                        "Can't yield outside of a function.",
+                        None,
+                    ));
+                }
+                self.visit_expr(value)
+            }
         }
     }
 }
@@ -271,7 +620,264 @@ enum FunctionType {
     Method,
 }
 
+#[derive(Clone, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::interpreter::Object;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::token::TokenType;
+
+    fn interpret(source: &str) -> Interpreter {
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut resolver = Resolver::new(Interpreter::new());
+        resolver.resolve_stmts(&stmts).expect("resolve error");
+        let mut interpreter = resolver.interpreter;
+        interpreter.interpret_all(&stmts);
+        interpreter
+    }
+
+    fn global(interpreter: &Interpreter, name: &str) -> Object {
+        let token = Token {
+            token_type: TokenType::Identifier,
+            lexeme: String::from(name),
+            literal: None,
+            line: 0,
+            offset: 0,
+            column: 0,
+            end_column: 0,
+        };
+        let value = interpreter.globals.borrow().get(token).expect("undefined");
+        let value = value.borrow().clone();
+        value
+    }
+
+    #[test]
+    fn closure_sees_variable_assigned_in_loop() {
+        let interpreter = interpret(
+            "var result; { var x = 0; fun get() { return x; } \
+             for (var i = 0; i < 3; i = i + 1) { x = i; } result = get(); }",
+        );
+        assert_eq!(global(&interpreter, "result"), Object::Number(2.0));
+    }
+
+    #[test]
+    fn closure_called_each_iteration() {
+        let interpreter = interpret(
+            "var sum = 0; { var x = 0; fun add() { sum = sum + x; } \
+             for (var i = 0; i < 4; i = i + 1) { x = i; add(); } }",
+        );
+        assert_eq!(global(&interpreter, "sum"), Object::Number(6.0));
+    }
+
+    #[test]
+    fn definitions_follow_shadowing() {
+        let source = "var a = 1; { var a = 2; print a; } print a;";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut resolver = Resolver::new(Interpreter::new());
+        resolver.resolve_stmts(&stmts).expect("resolve error");
+
+        let offsets: Vec<(usize, usize)> = resolver
+            .definitions()
+            .iter()
+            .map(|(usage, declaration)| (usage.offset, declaration.offset))
+            .collect();
+        let inner_use = source.find("print a").unwrap() + 6;
+        let outer_use = source.rfind("print a").unwrap() + 6;
+        let inner_declaration = source.rfind("var a").unwrap() + 4;
+        let outer_declaration = source.find("var a").unwrap() + 4;
+        assert_eq!(
+            offsets,
+            vec![
+                (inner_use, inner_declaration),
+                (outer_use, outer_declaration)
+            ]
+        );
+    }
+
+    #[test]
+    fn break_outside_loop_is_an_error() {
+        for source in ["break;", "while (true) { fun f() { continue; } }"] {
+            let mut scanner = Scanner::new(String::from(source));
+            let tokens = scanner.scan_tokens();
+            let stmts = Parser::new(tokens).parse().expect("parse error");
+            let mut resolver = Resolver::new(Interpreter::new());
+            assert!(resolver.resolve_stmts(&stmts).is_err());
+        }
+    }
+
+    #[test]
+    fn resolves_super_in_subclass_method() {
+        let source = "class A { greet() { return \"A\"; } } \
+                      class B < A { greet() { return super.greet() + \"B\"; } }";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut resolver = Resolver::new(Interpreter::new());
+        assert!(resolver.resolve_stmts(&stmts).is_ok());
+    }
+
+    #[test]
+    fn misplaced_super_is_an_error() {
+        for source in [
+            "print super.x;",
+            "class A { f() { return super.f(); } }",
+            "class A < A {}",
+        ] {
+            let mut scanner = Scanner::new(String::from(source));
+            let tokens = scanner.scan_tokens();
+            let stmts = Parser::new(tokens).parse().expect("parse error");
+            let mut resolver = Resolver::new(Interpreter::new());
+            assert!(resolver.resolve_stmts(&stmts).is_err());
+        }
+    }
+
+    #[test]
+    fn warns_when_shadowing_outer_variable() {
+        let source = "{ var x = 1; { var x = 2; var y = 3; } }";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut resolver = Resolver::with_shadow_warnings(Interpreter::new());
+        resolver.resolve_stmts(&stmts).expect("resolve error");
+
+        let warnings = resolver.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].0.offset, source.rfind("x").unwrap());
+        assert_eq!(warnings[0].1, "Variable 'x' shadows an outer binding.");
+
+        let mut resolver = Resolver::new(Interpreter::new());
+        resolver.resolve_stmts(&stmts).expect("resolve error");
+        assert!(resolver.warnings().is_empty());
+    }
+
+    #[test]
+    fn strict_mode_rejects_duplicate_globals() {
+        let source = "var x = 1;\nvar x = 2;";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut resolver = Resolver::strict(Interpreter::new());
+        let error = resolver.resolve_stmts(&stmts).unwrap_err();
+        assert_eq!(error.message, "Variable 'x' already declared.");
+        assert_eq!(error.token.line, 2);
+
+        let mut resolver = Resolver::new(Interpreter::new());
+        assert!(resolver.resolve_stmts(&stmts).is_ok());
+    }
+
+    #[test]
+    fn break_to_unknown_label_is_an_error() {
+        let source = "outer: while (true) { while (true) { break inner; } }";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut resolver = Resolver::new(Interpreter::new());
+        let error = resolver.resolve_stmts(&stmts).unwrap_err();
+        assert_eq!(error.message, "No enclosing loop labeled 'inner'.");
+    }
+
+    #[test]
+    fn yield_outside_function_is_an_error() {
+        let mut scanner = Scanner::new(String::from("yield 1;"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut resolver = Resolver::new(Interpreter::new());
+        let error = resolver.resolve_stmts(&stmts).unwrap_err();
+        assert_eq!(error.message, "Can't yield outside of a function.");
+    }
+
+    #[test]
+    fn this_in_a_field_default_is_an_error() {
+        let mut scanner = Scanner::new(String::from("class A { y = this; } A();"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut resolver = Resolver::new(Interpreter::new());
+        let error = resolver.resolve_stmts(&stmts).unwrap_err();
+        assert_eq!(error.message, "Can't use 'this' outside of a class.");
+    }
+
+    #[test]
+    fn field_default_sees_an_enclosing_this() {
+        let interpreter = interpret(
+            "class Outer { make() { class Inner { owner = this; } return Inner(); } } \
+             var outer = Outer(); var same = outer.make().owner == outer;",
+        );
+        assert_eq!(global(&interpreter, "same"), Object::Bool(true));
+    }
+
+    #[test]
+    fn counts_most_locals_live_at_once() {
+        let source = "fun f(a) { var b; { var c; var d; } { var e; } \
+                      fun g() { var h; } }";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut resolver = Resolver::new(Interpreter::new());
+        resolver.resolve_stmts(&stmts).expect("resolve error");
+
+        let slots: HashMap<&str, usize> = resolver
+            .max_slots()
+            .iter()
+            .map(|(name, slots)| (name.lexeme.as_str(), *slots))
+            .collect();
+        // a, b, c and d; g is declared after the first block ends
+        assert_eq!(slots["f"], 4);
+        assert_eq!(slots["g"], 1);
+    }
+
+    #[test]
+    fn use_before_declaration_is_an_error() {
+        let source = "var x = 1;\n{\n  print x;\n  var x = 2;\n}";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut resolver = Resolver::new(Interpreter::new());
+        let error = resolver.resolve_stmts(&stmts).unwrap_err();
+        assert_eq!(error.message, "Cannot use 'x' before its declaration.");
+        assert_eq!(error.token.line, 3);
+    }
+
+    #[test]
+    fn use_before_declaration_in_an_enclosing_block_is_an_error() {
+        let source = "var x = 1;\n{\n  {\n    print x;\n  }\n  var x = 2;\n}";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut resolver = Resolver::new(Interpreter::new());
+        let error = resolver.resolve_stmts(&stmts).unwrap_err();
+        assert_eq!(error.message, "Cannot use 'x' before its declaration.");
+        assert_eq!(error.token.line, 4);
+    }
+
+    #[test]
+    fn assignment_before_declaration_is_an_error() {
+        let mut scanner = Scanner::new(String::from("var x; { x = 1; var x; }"));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut resolver = Resolver::new(Interpreter::new());
+        let error = resolver.resolve_stmts(&stmts).unwrap_err();
+        assert_eq!(error.message, "Cannot use 'x' before its declaration.");
+    }
+
+    #[test]
+    fn check_stops_at_the_function_boundary() {
+        let source = "{ fun f() { return g(); } fun g() { return 1; } }";
+        let mut scanner = Scanner::new(String::from(source));
+        let tokens = scanner.scan_tokens();
+        let stmts = Parser::new(tokens).parse().expect("parse error");
+        let mut resolver = Resolver::new(Interpreter::new());
+        assert!(resolver.resolve_stmts(&stmts).is_ok());
+    }
 }
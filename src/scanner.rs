@@ -1,28 +1,62 @@
-use crate::error;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::error::SyntaxError;
 use crate::interpreter::Object;
 use crate::token::{Token, TokenType};
 
 pub struct Scanner {
     pub source: String,
+    // the source split into characters once, since `chars().nth()` on the
+    // string would make each lookup walk it from the start
+    chars: Vec<char>,
     pub tokens: Vec<Token>,
+    // every error found, in order; each also leaves an error token behind
+    pub errors: Vec<SyntaxError>,
     pub start: usize,
     pub current: usize,
     pub line: usize,
+    // where the current line starts, and the column the current token starts at
+    line_start: usize,
+    column: usize,
+    // string literals seen so far, so identical literals share storage
+    strings: HashSet<Rc<str>>,
+    // keywords added by the embedder, checked before the built-in ones
+    keywords: HashMap<String, TokenType>,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Self {
         Self {
+            chars: source.chars().collect(),
             source,
             tokens: Vec::new(),
+            errors: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            column: 0,
+            strings: HashSet::new(),
+            keywords: HashMap::new(),
+        }
+    }
+
+    /// Creates a scanner that also recognises `keywords`, e.g. mapping `func`
+    /// to `TokenType::Fun`. These take precedence over the built-in keywords.
+    pub fn with_keywords(source: String, keywords: HashMap<&str, TokenType>) -> Self {
+        Self {
+            keywords: keywords
+                .into_iter()
+                .map(|(text, token_type)| (text.to_string(), token_type))
+                .collect(),
+            ..Self::new(source)
         }
     }
     pub fn scan_tokens(&mut self) -> &Vec<Token> {
         while !self.is_at_end() {
             self.start = self.current;
+            self.column = self.start - self.line_start;
             self.scan_single_token();
         }
         self.tokens.push(Token {
@@ -30,11 +64,14 @@ impl Scanner {
             lexeme: String::from(""),
             literal: None,
             line: self.line,
+            offset: self.current,
+            column: self.current - self.line_start,
+            end_column: self.current - self.line_start,
         });
         &self.tokens
     }
     fn is_at_end(&mut self) -> bool {
-        self.current >= self.source.len()
+        self.current >= self.chars.len()
     }
     fn scan_single_token(&mut self) {
         let c = self.advance();
@@ -43,6 +80,9 @@ impl Scanner {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
+            ':' => self.add_token(TokenType::Colon),
             ',' => self.add_token(TokenType::Comma),
             '.' => self.add_token(TokenType::Dot),
             '-' => self.add_token(TokenType::Minus),
@@ -66,6 +106,16 @@ impl Scanner {
                 }
             }
 
+            '?' => {
+                if self.matches(&'?') {
+                    self.add_token(TokenType::QuestionQuestion)
+                } else if self.matches(&'.') {
+                    self.add_token(TokenType::QuestionDot)
+                } else {
+                    self.add_error_token("Unexpected character.")
+                }
+            }
+
             '<' => {
                 if self.matches(&'=') {
                     self.add_token(TokenType::LessEqual)
@@ -87,6 +137,8 @@ impl Scanner {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                } else if self.matches(&'*') {
+                    self.block_comment();
                 } else {
                     self.add_token(TokenType::Slash)
                 }
@@ -96,6 +148,7 @@ impl Scanner {
 
             '\n' => {
                 self.line += 1;
+                self.line_start = self.current;
             }
 
             '"' => {
@@ -103,45 +156,81 @@ impl Scanner {
             }
 
             c => {
-                if c.is_digit(10) {
+                if c.is_ascii_digit() {
                     self.number();
                 } else if c.is_ascii_alphabetic() || c == '_' {
                     self.identifier();
                 } else {
-                    error(self.line, &"Unexpected character.")
+                    self.add_error_token("Unexpected character.")
+                }
+            }
+        }
+    }
+    // Block comments nest, so a comment can be wrapped around code that
+    // already contains one.
+    fn block_comment(&mut self) {
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                self.add_error_token("Unterminated block comment.");
+                return;
+            }
+            match self.advance() {
+                '/' if self.matches(&'*') => depth += 1,
+                '*' if self.matches(&'/') => depth -= 1,
+                '\n' => {
+                    self.line += 1;
+                    self.line_start = self.current;
                 }
+                _ => {}
             }
         }
     }
+
     fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current).unwrap();
-        self.current = self.current + 1;
+        let c = self.chars[self.current];
+        self.current += 1;
         c
     }
     fn add_token(&mut self, token_type: TokenType) {
-        let text = &self.source[self.start..self.current];
         self.tokens.push(Token {
             token_type,
-            lexeme: String::from(text),
+            lexeme: self.text(self.start, self.current),
             literal: None,
             line: self.line,
+            offset: self.start,
+            column: self.column,
+            end_column: self.current - self.line_start,
         });
     }
 
     fn add_token_with_literal(&mut self, token_type: TokenType, literal: Option<Object>) {
-        let text = &self.source[self.start..self.current];
         self.tokens.push(Token {
             token_type,
-            lexeme: String::from(text),
+            lexeme: self.text(self.start, self.current),
             literal,
             line: self.line,
+            offset: self.start,
+            column: self.column,
+            end_column: self.current - self.line_start,
         });
     }
+    // The error is collected rather than reported, and the parser fails when
+    // it reaches the token, so scanning carries on past it.
+    fn add_error_token(&mut self, message: &str) {
+        let literal = Object::String(Rc::from(message));
+        self.add_token_with_literal(TokenType::Error, Some(literal));
+        self.errors.push(SyntaxError {
+            token: self.tokens[self.tokens.len() - 1].clone(),
+            message: message.to_string(),
+        });
+    }
+
     fn matches(&mut self, expected: &char) -> bool {
         if self.is_at_end() {
             return false;
         }
-        if &self.source.chars().nth(self.current).unwrap() != expected {
+        if &self.chars[self.current] != expected {
             return false;
         }
         self.current += 1;
@@ -151,24 +240,34 @@ impl Scanner {
         if self.is_at_end() {
             return '\0';
         }
-        return self.source.chars().nth(self.current).unwrap();
+        self.chars[self.current]
     }
     fn peek_next(&mut self) -> char {
-        if self.current + 1 >= self.source.len() {
+        if self.current + 1 >= self.chars.len() {
             return '\0';
         }
-        return self.source.chars().nth(self.current + 1).unwrap();
+        self.chars[self.current + 1]
+    }
+
+    fn text(&self, start: usize, end: usize) -> String {
+        self.chars[start..end].iter().collect()
     }
     fn string(&mut self) {
         while self.peek() != '"' && !self.is_at_end() {
+            // the character after a backslash is part of the escape, so an
+            // escaped quote doesn't end the string
+            if self.peek() == '\\' && self.peek_next() != '\0' {
+                self.advance();
+            }
             if self.peek() == '\n' {
                 self.line += 1;
+                self.line_start = self.current + 1;
             }
             self.advance();
         }
 
         if self.is_at_end() {
-            error(self.line, &"Unterminated string.");
+            self.add_error_token("Unterminated string.");
             return;
         }
 
@@ -176,59 +275,376 @@ impl Scanner {
         self.advance();
 
         // Trim the surrounding quotes.
-        self.add_token_with_literal(
-            TokenType::String,
-            Some(Object::String(String::from(
-                &self.source[self.start + 1..self.current - 1],
-            ))),
-        )
+        let text = self.text(self.start + 1, self.current - 1);
+        let text = match unescape(&text) {
+            Ok(text) => text,
+            Err(message) => return self.add_error_token(message),
+        };
+        let value = self.intern(&text);
+        self.add_token_with_literal(TokenType::String, Some(Object::String(value)))
+    }
+
+    fn intern(&mut self, text: &str) -> Rc<str> {
+        if let Some(string) = self.strings.get(text) {
+            return Rc::clone(string);
+        }
+        let string: Rc<str> = Rc::from(text);
+        self.strings.insert(Rc::clone(&string));
+        string
     }
 
     fn number(&mut self) {
-        while self.peek().is_digit(10) {
-            self.advance();
+        if self.chars[self.start] == '0' {
+            match self.peek() {
+                'x' | 'X' => return self.radix_number(16),
+                'b' | 'B' => return self.radix_number(2),
+                _ => {}
+            }
         }
+        self.digits();
 
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             self.advance();
+            self.digits();
+        }
 
-            while self.peek().is_digit(10) {
-                self.advance();
+        // An exponent, as in `2.5e-4`. Without digits after it the `e` is
+        // left for the next token.
+        if matches!(self.peek(), 'e' | 'E') {
+            let sign = matches!(self.peek_next(), '+' | '-');
+            let first_digit = self.current + if sign { 2 } else { 1 };
+            if self
+                .chars
+                .get(first_digit)
+                .is_some_and(char::is_ascii_digit)
+            {
+                self.current = first_digit;
+                self.digits();
             }
         }
 
+        let text = self.text(self.start, self.current).replace('_', "");
         self.add_token_with_literal(
             TokenType::Number,
-            Some(Object::Number(
-                self.source[self.start..self.current].parse().unwrap(),
-            )),
+            Some(Object::Number(text.parse().unwrap())),
         )
     }
 
+    // `0xFF` or `0b1010`. Everything up to the next non-word character is
+    // taken as the literal, so `0xFG` is one bad literal rather than `0xF`
+    // followed by `G`.
+    fn radix_number(&mut self, radix: u32) {
+        self.advance();
+        while self.peek().is_ascii_alphanumeric() || self.peek() == '_' {
+            self.advance();
+        }
+        let digits = self.text(self.start + 2, self.current).replace('_', "");
+        match i64::from_str_radix(&digits, radix) {
+            Ok(n) => self.add_token_with_literal(TokenType::Number, Some(Object::Number(n as f64))),
+            Err(_) => self.add_error_token("Invalid number literal."),
+        }
+    }
+
+    // An underscore may separate digits, as in `1_000`.
+    fn digits(&mut self) {
+        while self.peek().is_ascii_digit()
+            || (self.peek() == '_' && self.peek_next().is_ascii_digit())
+        {
+            self.advance();
+        }
+    }
+
     fn identifier(&mut self) {
         while self.peek().is_ascii_alphanumeric() || self.peek() == '_' {
             self.advance();
         }
-        let text = &self.source[self.start..self.current];
-        let keyword = match text {
+        let text = self.text(self.start, self.current);
+        if let Some(token_type) = self.keywords.get(&text) {
+            let token_type = token_type.clone();
+            self.add_token(token_type);
+            return;
+        }
+        let keyword = match text.as_str() {
             "and" => TokenType::And,
+            "break" => TokenType::Break,
             "class" => TokenType::Class,
+            "continue" => TokenType::Continue,
+            "defer" => TokenType::Defer,
             "else" => TokenType::Else,
+            "enum" => TokenType::Enum,
             "false" => TokenType::False,
             "for" => TokenType::For,
             "fun" => TokenType::Fun,
             "if" => TokenType::If,
+            "import" => TokenType::Import,
             "nil" => TokenType::Nil,
             "or" => TokenType::Or,
             "print" => TokenType::Print,
+            "private" => TokenType::Private,
             "return" => TokenType::Return,
             "super" => TokenType::Super,
             "this" => TokenType::This,
             "true" => TokenType::True,
             "var" => TokenType::Var,
             "while" => TokenType::While,
+            "yield" => TokenType::Yield,
             _ => TokenType::Identifier,
         };
         self.add_token(keyword);
     }
 }
+
+/// Decodes the escapes in a string literal: `\n`, `\t`, `\r`, `\\` and `\"`,
+/// along with `\x41` and `\u{1F600}`. Any other escape is an error.
+fn unescape(text: &str) -> Result<String, &'static str> {
+    let mut value = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        let (digits, message) = match chars.peek() {
+            Some('x') => {
+                chars.next();
+                let digits: String = chars.by_ref().take(2).collect();
+                (Some(digits).filter(|d| d.len() == 2), "Invalid hex escape.")
+            }
+            Some('u') => {
+                chars.next();
+                let mut digits = None;
+                if chars.next_if_eq(&'{').is_some() {
+                    let mut inside = String::new();
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            digits = Some(inside).filter(|d| (1..=6).contains(&d.len()));
+                            break;
+                        }
+                        inside.push(c);
+                    }
+                }
+                (digits, "Invalid unicode escape.")
+            }
+            Some(&c) => {
+                let escaped = match c {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    '\\' | '"' => c,
+                    _ => return Err("Invalid escape sequence."),
+                };
+                chars.next();
+                value.push(escaped);
+                continue;
+            }
+            None => return Err("Invalid escape sequence."),
+        };
+        let code = digits
+            .filter(|d| d.chars().all(|c| c.is_ascii_hexdigit()))
+            .and_then(|d| u32::from_str_radix(&d, 16).ok())
+            .and_then(char::from_u32)
+            .ok_or(message)?;
+        value.push(code);
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_string_literals_share_storage() {
+        let mut scanner = Scanner::new(String::from("\"lox\" == \"lox\""));
+        let tokens = scanner.scan_tokens();
+        match (&tokens[0].literal, &tokens[2].literal) {
+            (Some(Object::String(l)), Some(Object::String(r))) => {
+                assert_eq!(l, r);
+                assert!(Rc::ptr_eq(l, r));
+            }
+            _ => panic!("expected string literals"),
+        }
+    }
+
+    #[test]
+    fn extra_keywords_alias_built_in_ones() {
+        let keywords = HashMap::from([("func", TokenType::Fun)]);
+        let mut scanner =
+            Scanner::with_keywords(String::from("func add(a, b) { return a + b; }"), keywords);
+        let tokens = scanner.scan_tokens();
+        assert_eq!(tokens[0].token_type, TokenType::Fun);
+        assert_eq!(tokens[0].lexeme, "func");
+        let stmts = crate::parser::Parser::new(tokens)
+            .parse()
+            .expect("parse error");
+        assert!(
+            matches!(&stmts[0], crate::stmt::Stmt::Function { name, .. } if name.lexeme == "add")
+        );
+    }
+
+    #[test]
+    fn hex_and_unicode_escapes() {
+        let mut scanner = Scanner::new(String::from(r#""\x41\x62c" "\u{1F600}!" "a\nb""#));
+        let tokens = scanner.scan_tokens();
+        let literals: Vec<String> = tokens[..3]
+            .iter()
+            .map(|token| match &token.literal {
+                Some(Object::String(s)) => s.to_string(),
+                _ => panic!("expected a string literal"),
+            })
+            .collect();
+        assert_eq!(literals, ["Abc", "\u{1F600}!", "a\nb"]);
+        assert_eq!(tokens[1].lexeme, r#""\u{1F600}!""#);
+    }
+
+    #[test]
+    fn malformed_escapes_are_errors() {
+        for source in [
+            r#""\x4""#,
+            r#""\xZZ""#,
+            r#""\u{}""#,
+            r#""\u{1234567}""#,
+            r#""\u{D800}""#,
+            r#""\u{110000}""#,
+            r#""\u41""#,
+            r#""\u{41""#,
+        ] {
+            let mut scanner = Scanner::new(String::from(source));
+            let tokens = scanner.scan_tokens();
+            assert_eq!(tokens[0].token_type, TokenType::Error, "{source}");
+            assert_eq!(scanner.errors.len(), 1, "{source}");
+        }
+    }
+
+    #[test]
+    fn bad_characters_become_error_tokens() {
+        let mut scanner = Scanner::new(String::from("1 @ 2"));
+        let types: Vec<TokenType> = scanner
+            .scan_tokens()
+            .iter()
+            .map(|token| token.token_type.clone())
+            .collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Number,
+                TokenType::Error,
+                TokenType::Number,
+                TokenType::Eof
+            ]
+        );
+        assert_eq!(scanner.tokens[1].lexeme, "@");
+
+        let mut scanner = Scanner::new(String::from("print 1 @ 2; print \"open"));
+        let tokens = scanner.scan_tokens();
+        assert_eq!(tokens[tokens.len() - 2].token_type, TokenType::Error);
+        let error = crate::parser::Parser::new(tokens)
+            .parse()
+            .expect_err("expected a syntax error");
+        assert_eq!(error.message, "Unexpected character.");
+        assert_eq!(error.token.lexeme, "@");
+    }
+
+    #[test]
+    fn tokens_record_start_and_end_columns() {
+        let mut scanner = Scanner::new(String::from("1 + 22\n\"a\nbc\""));
+        let columns: Vec<(&str, usize, usize, usize)> = scanner
+            .scan_tokens()
+            .iter()
+            .map(|token| {
+                let lexeme = token.lexeme.as_str();
+                (lexeme, token.line, token.column, token.end_column)
+            })
+            .collect();
+        assert_eq!(columns[1], ("+", 1, 2, 3));
+        assert_eq!(columns[2], ("22", 1, 4, 6));
+        // a string spanning lines ends on its last line
+        assert_eq!(columns[3], ("\"a\nbc\"", 3, 0, 3));
+    }
+
+    #[test]
+    fn collects_every_error() {
+        let mut scanner = Scanner::new(String::from("@#$"));
+        scanner.scan_tokens();
+        let lexemes: Vec<&str> = scanner
+            .errors
+            .iter()
+            .map(|error| error.token.lexeme.as_str())
+            .collect();
+        assert_eq!(lexemes, ["@", "#", "$"]);
+
+        let mut scanner = Scanner::new(String::from("print 1 @ 2;\nprint \"open"));
+        scanner.scan_tokens();
+        let errors: Vec<(usize, &str)> = scanner
+            .errors
+            .iter()
+            .map(|error| (error.token.line, error.message.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            [(1, "Unexpected character."), (2, "Unterminated string.")]
+        );
+    }
+
+    #[test]
+    fn block_comments_nest() {
+        let mut scanner = Scanner::new(String::from(
+            "/* outer /* inner /* deepest */ */ still\n commented */ print 2;",
+        ));
+        let tokens = scanner.scan_tokens();
+        let lexemes: Vec<&str> = tokens.iter().map(|token| token.lexeme.as_str()).collect();
+        assert_eq!(lexemes, ["print", "2", ";", ""]);
+        assert_eq!(tokens[0].line, 2);
+    }
+
+    #[test]
+    fn exponent_literals() {
+        let mut scanner = Scanner::new(String::from("1e3 2.5e-4 1E+2 2e"));
+        let tokens = scanner.scan_tokens();
+        let numbers: Vec<f64> = tokens[..4]
+            .iter()
+            .map(|token| match &token.literal {
+                Some(Object::Number(n)) => *n,
+                _ => panic!("expected a number literal"),
+            })
+            .collect();
+        assert_eq!(numbers, [1000.0, 0.00025, 100.0, 2.0]);
+        // `2e` is a number followed by an identifier
+        assert_eq!(tokens[4].token_type, TokenType::Identifier);
+    }
+
+    #[test]
+    fn hex_and_binary_literals() {
+        let mut scanner = Scanner::new(String::from("0xFF 0b1010 0x1_00 0x 0b12 0xFG"));
+        let tokens = scanner.scan_tokens();
+        let literals: Vec<Option<f64>> = tokens[..6]
+            .iter()
+            .map(|token| match (&token.token_type, &token.literal) {
+                (TokenType::Number, Some(Object::Number(n))) => Some(*n),
+                (TokenType::Error, _) => None,
+                _ => panic!("expected a number or an error"),
+            })
+            .collect();
+        assert_eq!(
+            literals,
+            [Some(255.0), Some(10.0), Some(256.0), None, None, None]
+        );
+        assert_eq!(tokens.len(), 7);
+    }
+
+    #[test]
+    fn simple_escapes() {
+        let mut scanner = Scanner::new(String::from(r#""say \"hi\"\r\n" "back\\" "tab\t" "\q""#));
+        let tokens = scanner.scan_tokens();
+        let literals: Vec<String> = tokens[..3]
+            .iter()
+            .map(|token| match &token.literal {
+                Some(Object::String(s)) => s.to_string(),
+                _ => panic!("expected a string literal"),
+            })
+            .collect();
+        assert_eq!(literals, ["say \"hi\"\r\n", "back\\", "tab\t"]);
+        assert_eq!(tokens[3].token_type, TokenType::Error);
+        assert_eq!(scanner.errors[0].message, "Invalid escape sequence.");
+    }
+}
@@ -1,36 +1,119 @@
-use crate::{ast::Expr, token::Token};
+use crate::{
+    ast::{Expr, Span},
+    token::Token,
+};
 
-#[derive(Clone, Debug, Hash)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub enum Stmt {
     Block {
         statements: Vec<Stmt>,
+        span: Span,
+    },
+    Break {
+        keyword: Token,
+        label: Option<Token>,
+        span: Span,
     },
     Class {
         name: Token,
+        superclass: Option<Expr>,
+        fields: Vec<(Token, Expr)>,
         methods: Vec<Stmt>,
+        getters: Vec<Stmt>,
+        setters: Vec<Stmt>,
+        span: Span,
+    },
+    Continue {
+        keyword: Token,
+        label: Option<Token>,
+        span: Span,
+    },
+    Defer {
+        keyword: Token,
+        value: Expr,
+        span: Span,
+    },
+    Enum {
+        name: Token,
+        variants: Vec<Token>,
+        span: Span,
+    },
+    Expr(Expr, Span),
+    // Kept separate from `While` so that `continue` still runs the increment.
+    For {
+        label: Option<Token>,
+        initializer: Option<Box<Stmt>>,
+        condition: Option<Expr>,
+        increment: Option<Expr>,
+        body: Box<Stmt>,
+        span: Span,
     },
-    Expr(Expr),
     Function {
         name: Token,
         params: Vec<Token>,
         body: Vec<Stmt>,
+        span: Span,
     },
     If {
         condition: Expr,
         then_branch: Box<Stmt>,
         else_branch: Option<Box<Stmt>>,
+        span: Span,
+    },
+    Import {
+        keyword: Token,
+        path: Token,
+        name: Token,
+        span: Span,
+    },
+    Print(Expr, Span),
+    Private {
+        keyword: Token,
+        declaration: Box<Stmt>,
+        span: Span,
     },
-    Print(Expr),
     Return {
         keyword: Token,
         value: Expr,
+        span: Span,
     },
     Var {
         name: Token,
         initializer: Option<Expr>,
+        span: Span,
     },
     While {
+        label: Option<Token>,
         condition: Expr,
         body: Box<Stmt>,
+        span: Span,
     },
+    Yield {
+        keyword: Token,
+        value: Expr,
+        span: Span,
+    },
+}
+
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Expr(_, span) | Stmt::Print(_, span) => *span,
+            Stmt::Block { span, .. }
+            | Stmt::Break { span, .. }
+            | Stmt::Class { span, .. }
+            | Stmt::Enum { span, .. }
+            | Stmt::Continue { span, .. }
+            | Stmt::Defer { span, .. }
+            | Stmt::For { span, .. }
+            | Stmt::Function { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::Import { span, .. }
+            | Stmt::Private { span, .. }
+            | Stmt::Return { span, .. }
+            | Stmt::Var { span, .. }
+            | Stmt::While { span, .. }
+            | Stmt::Yield { span, .. } => *span,
+        }
+    }
 }
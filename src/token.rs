@@ -1,4 +1,5 @@
 use std::fmt;
+use std::io::{self, Write};
 
 use crate::interpreter::Object;
 
@@ -9,6 +10,9 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -26,6 +30,8 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    QuestionQuestion,
+    QuestionDot,
 
     // Literals
     Identifier,
@@ -33,28 +39,93 @@ pub enum TokenType {
     Number,
 
     And,
+    Break,
     Class,
+    Continue,
+    Defer,
     Else,
+    Enum,
     False,
     Fun,
     For,
     If,
+    Import,
     Nil,
     Or,
     Print,
+    Private,
     Return,
     Super,
     This,
     True,
     Var,
     While,
+    Yield,
+
+    // a character or string the scanner couldn't make sense of, with the
+    // message describing why as its literal
+    Error,
 
     Eof,
 }
 
 impl fmt::Display for TokenType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self)
+        let text = match self {
+            TokenType::LeftParen => "(",
+            TokenType::RightParen => ")",
+            TokenType::LeftBrace => "{",
+            TokenType::RightBrace => "}",
+            TokenType::LeftBracket => "[",
+            TokenType::RightBracket => "]",
+            TokenType::Colon => ":",
+            TokenType::Comma => ",",
+            TokenType::Dot => ".",
+            TokenType::Minus => "-",
+            TokenType::Plus => "+",
+            TokenType::Semicolon => ";",
+            TokenType::Slash => "/",
+            TokenType::Star => "*",
+            TokenType::Bang => "!",
+            TokenType::BangEqual => "!=",
+            TokenType::Equal => "=",
+            TokenType::EqualEqual => "==",
+            TokenType::Greater => ">",
+            TokenType::GreaterEqual => ">=",
+            TokenType::Less => "<",
+            TokenType::LessEqual => "<=",
+            TokenType::QuestionQuestion => "??",
+            TokenType::QuestionDot => "?.",
+            TokenType::Identifier => "identifier",
+            TokenType::String => "string",
+            TokenType::Number => "number",
+            TokenType::And => "and",
+            TokenType::Break => "break",
+            TokenType::Class => "class",
+            TokenType::Continue => "continue",
+            TokenType::Defer => "defer",
+            TokenType::Else => "else",
+            TokenType::Enum => "enum",
+            TokenType::False => "false",
+            TokenType::Fun => "fun",
+            TokenType::For => "for",
+            TokenType::If => "if",
+            TokenType::Import => "import",
+            TokenType::Nil => "nil",
+            TokenType::Or => "or",
+            TokenType::Print => "print",
+            TokenType::Private => "private",
+            TokenType::Return => "return",
+            TokenType::Super => "super",
+            TokenType::This => "this",
+            TokenType::True => "true",
+            TokenType::Var => "var",
+            TokenType::While => "while",
+            TokenType::Yield => "yield",
+            TokenType::Error => "error",
+            TokenType::Eof => "end of file",
+        };
+        write!(f, "{}", text)
     }
 }
 
@@ -64,4 +135,39 @@ pub struct Token {
     pub lexeme: String,
     pub literal: Option<Object>,
     pub line: usize,
+    /// Position of the lexeme in the source, counted in characters, which
+    /// keeps otherwise identical tokens on the same line distinct.
+    pub offset: usize,
+    /// Column where the lexeme starts, counting from 0.
+    pub column: usize,
+    /// Column just past the lexeme's last character, so the lexeme covers
+    /// `column..end_column`. For a string spanning lines, this is on the
+    /// line it ends on.
+    pub end_column: usize,
+}
+
+/// Writes one line per token, giving its type, lexeme, line and column.
+pub fn write_tokens(out: &mut dyn Write, tokens: &[Token]) -> io::Result<()> {
+    for token in tokens {
+        writeln!(
+            out,
+            "{:?} '{}' {}:{}",
+            token.token_type, token.lexeme, token.line, token.column
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_token_types() {
+        assert_eq!(TokenType::Plus.to_string(), "+");
+        assert_eq!(TokenType::LessEqual.to_string(), "<=");
+        assert_eq!(TokenType::Identifier.to_string(), "identifier");
+        assert_eq!(TokenType::While.to_string(), "while");
+        assert_eq!(TokenType::Eof.to_string(), "end of file");
+    }
 }
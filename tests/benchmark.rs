@@ -0,0 +1,82 @@
+//! Runs fixed workloads and fails if one takes far longer than it should, to
+//! catch performance regressions such as quadratic scanning or costly hashing
+//! of `Expr` keys. Elapsed times are printed with `--nocapture`.
+
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+use rlox::interpreter::{Interpreter, Object};
+use rlox::parser::Parser;
+use rlox::resolver::Resolver;
+use rlox::scanner::Scanner;
+
+// Generous enough for an unoptimized build on a slow machine.
+const THRESHOLD: Duration = Duration::from_secs(30);
+
+fn run(name: &str, source: &str) -> Object {
+    let start = Instant::now();
+    let mut scanner = Scanner::new(String::from(source));
+    let tokens = scanner.scan_tokens();
+    let stmts = Parser::new(tokens).parse().expect("parse error");
+    let interpreter = Interpreter::with_writers(
+        Box::new(Cursor::new(Vec::new())),
+        Box::new(Cursor::new(Vec::new())),
+    );
+    let mut resolver = Resolver::new(interpreter);
+    resolver.resolve_stmts(&stmts).expect("resolve error");
+    let value = resolver
+        .interpreter
+        .interpret(&stmts)
+        .expect("runtime error");
+    let elapsed = start.elapsed();
+    println!("{}: {:?}", name, elapsed);
+    assert!(
+        elapsed < THRESHOLD,
+        "{} took {:?}, over the {:?} threshold",
+        name,
+        elapsed,
+        THRESHOLD
+    );
+    value
+}
+
+#[test]
+fn recursive_fibonacci() {
+    let value = run("fibonacci", include_str!("../fibonacci_bench.txt"));
+    assert_eq!(value.to_string(), "2584");
+}
+
+#[test]
+fn tight_loop() {
+    let value = run("loop", include_str!("../loop_bench.txt"));
+    assert_eq!(value.to_string(), "20000");
+}
+
+// Looks up a global function on every iteration. Finding globals by name
+// rather than through a cloned `Token` took this from about 3.7s to 3.3s.
+#[test]
+fn global_calls() {
+    let value = run("global calls", include_str!("../global_call_bench.txt"));
+    assert_eq!(value.to_string(), "20000");
+}
+
+// Scans about 1MB of source. Indexing a `Vec<char>` rather than calling
+// `chars().nth()` for every character took this from minutes to about
+// 130ms unoptimized.
+#[test]
+fn scan_large_file() {
+    let line = "var total = total + 12.5; // add \"x\"\n";
+    let source = line.repeat(1_000_000 / line.len());
+    let start = Instant::now();
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan_tokens().len();
+    let elapsed = start.elapsed();
+    println!("scan: {:?}", elapsed);
+    assert_eq!(tokens, 189_190);
+    assert!(
+        elapsed < THRESHOLD,
+        "scanning took {:?}, over the {:?} threshold",
+        elapsed,
+        THRESHOLD
+    );
+}
@@ -0,0 +1,72 @@
+//! Runs the `rlox` binary on a script, to check what reaches the real
+//! standard output rather than an injected writer.
+
+use std::env;
+use std::fs;
+use std::process::{Command, Output};
+
+// Writes `source` to a script named after `name` and runs rlox with `args`
+// followed by the script's path.
+fn run(name: &str, args: &[&str], source: &str) -> Output {
+    let path = env::temp_dir().join(format!("rlox-{}-{}.lox", name, std::process::id()));
+    fs::write(&path, source).expect("failed to write script");
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .arg(&path)
+        .output()
+        .expect("failed to run rlox");
+    fs::remove_file(&path).expect("failed to remove script");
+    output
+}
+
+#[test]
+fn stdout_holds_only_program_output() {
+    let source = "fun add(a, b) { var sum = a; sum = sum + b; return sum; }\n\
+                  var i = 0; while (i < 2) { i = i + 1; }\n\
+                  { var x = add(i, 1); print x; }\n";
+    let output = run("output", &[], source);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+}
+
+#[test]
+fn check_reports_scanning_errors() {
+    let output = run("check", &["--check"], "print @;\nprint #;\n");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "[line 1] Error @: Unexpected character.\n[line 2] Error #: Unexpected character.\n"
+    );
+}
+
+#[test]
+fn check_without_a_path_prints_usage() {
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .arg("--check")
+        .output()
+        .expect("failed to run rlox");
+    assert_eq!(output.status.code(), Some(64));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Usage: rlox --check [script]\n"
+    );
+}
+
+#[test]
+fn block_expression_reports_no_error() {
+    let output = run(
+        "block",
+        &[],
+        "var x = { var a = 2; a + 1 }; print x;\nprint {{1}};\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n1\n");
+}
+
+#[test]
+fn runtime_error_exits_with_70() {
+    let output = run("runtime", &[], "print 1;\nprint nil + 1;\nprint 2;\n");
+    assert_eq!(output.status.code(), Some(70));
+    // the statements after the error still run
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "1\n2\n");
+}